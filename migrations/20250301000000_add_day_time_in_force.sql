@@ -0,0 +1,4 @@
+-- Day orders are cancelled at session close
+ALTER TABLE orders DROP CONSTRAINT orders_time_in_force_check;
+ALTER TABLE orders ADD CONSTRAINT orders_time_in_force_check
+    CHECK (time_in_force IN ('GTC', 'DAY', 'IOC', 'FOK'));
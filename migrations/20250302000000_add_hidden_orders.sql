@@ -0,0 +1,2 @@
+-- Hidden orders rest without appearing in market data
+ALTER TABLE orders ADD COLUMN hidden BOOLEAN NOT NULL DEFAULT FALSE;
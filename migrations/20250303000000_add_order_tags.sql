@@ -0,0 +1,2 @@
+-- Free-form labels strategies use to group their orders
+ALTER TABLE orders ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
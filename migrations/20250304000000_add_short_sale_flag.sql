@@ -0,0 +1,2 @@
+-- Short sales are subject to the uptick rule while restricted
+ALTER TABLE orders ADD COLUMN is_short BOOLEAN NOT NULL DEFAULT FALSE;
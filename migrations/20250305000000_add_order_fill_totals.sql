@@ -0,0 +1,3 @@
+-- Running fill totals so clients need not recompute them from trades
+ALTER TABLE orders ADD COLUMN filled_quantity DECIMAL(20,4) NOT NULL DEFAULT 0;
+ALTER TABLE orders ADD COLUMN avg_fill_price DECIMAL(20,4);
@@ -0,0 +1,2 @@
+-- Currency the instrument trades and settles in, matching cash_positions.currency
+ALTER TABLE instruments ADD COLUMN currency VARCHAR(3) NOT NULL DEFAULT 'RMD';
@@ -0,0 +1,2 @@
+-- Spread the aggressor faced, for execution-quality analysis
+ALTER TABLE trades ADD COLUMN spread_at_execution DECIMAL(20,4);
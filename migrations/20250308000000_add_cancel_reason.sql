@@ -0,0 +1,4 @@
+-- Why an order was cancelled, for audit and client reporting. This replaces
+-- the free-text reason column from the initial schema, which nothing wrote to.
+ALTER TABLE orders DROP COLUMN reason;
+ALTER TABLE orders ADD COLUMN cancel_reason VARCHAR(32);
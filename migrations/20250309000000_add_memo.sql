@@ -0,0 +1,3 @@
+-- Free-text reference set on an order and carried onto the trades it aggresses
+ALTER TABLE orders ADD COLUMN memo TEXT;
+ALTER TABLE trades ADD COLUMN memo TEXT;
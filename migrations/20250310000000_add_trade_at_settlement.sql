@@ -0,0 +1,10 @@
+-- Trade-at-settlement orders execute at the settlement price; any price they carry is informational
+ALTER TABLE orders DROP CONSTRAINT orders_order_type_check;
+ALTER TABLE orders ADD CONSTRAINT orders_order_type_check
+    CHECK (order_type IN ('LIMIT', 'MARKET', 'TAS'));
+ALTER TABLE orders DROP CONSTRAINT market_order_no_price;
+ALTER TABLE orders ADD CONSTRAINT market_order_no_price CHECK (
+    (order_type = 'MARKET' AND price IS NULL) OR
+    (order_type = 'LIMIT' AND price IS NOT NULL AND price > 0) OR
+    (order_type = 'TAS' AND (price IS NULL OR price > 0))
+    );
@@ -0,0 +1,3 @@
+-- A zero tick or lot size makes every order on the instrument invalid
+ALTER TABLE instruments ADD CONSTRAINT instruments_tick_size_positive CHECK (tick_size > 0);
+ALTER TABLE instruments ADD CONSTRAINT instruments_lot_size_positive CHECK (lot_size > 0);
@@ -0,0 +1,2 @@
+-- Orders may opt out of trading against hidden resting orders
+ALTER TABLE orders ADD COLUMN interact_with_hidden BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,199 @@
+use super::models::*;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+/// Execution cost of `order` against the price when the decision to trade was
+/// made: the sum over its fills of `(fill price - decision price) * quantity`,
+/// negated for sells. Positive values are a cost, negative values an
+/// improvement. Trades the order did not take part in are ignored.
+pub fn implementation_shortfall(order: &Order, trades: &[Trade], decision_price: Decimal) -> Decimal {
+    let slippage: Decimal = trades
+        .iter()
+        .filter(|trade| trade.buyer_order_id == order.id || trade.seller_order_id == order.id)
+        .map(|trade| (trade.price - decision_price) * trade.quantity)
+        .sum();
+
+    match order.side {
+        OrderSide::BUY => slippage,
+        OrderSide::SELL => -slippage,
+    }
+}
+
+/// Total `price * quantity` of the trades `broker_id` took part in, as buyer
+/// or seller. A trade the broker crossed with itself is counted once.
+pub fn broker_traded_notional(broker_id: Uuid, trades: &[Trade]) -> Decimal {
+    trades
+        .iter()
+        .filter(|trade| trade.buyer_broker_id == broker_id || trade.seller_broker_id == broker_id)
+        .map(|trade| trade.price * trade.quantity)
+        .sum()
+}
+
+/// Number of trades between brokers `a` and `b`, whichever of them bought,
+/// and the quantity they traded with each other
+pub fn pair_volume(trades: &[Trade], a: Uuid, b: Uuid) -> (u64, Decimal) {
+    trades
+        .iter()
+        .filter(|trade| {
+            (trade.buyer_broker_id == a && trade.seller_broker_id == b)
+                || (trade.buyer_broker_id == b && trade.seller_broker_id == a)
+        })
+        .fold((0, Decimal::ZERO), |(count, volume), trade| (count + 1, volume + trade.quantity))
+}
+
+/// Several fills reported as one execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsolidatedFill {
+    pub total_quantity: Decimal,
+    pub vwap: Decimal,
+    pub first_time: DateTime<Utc>,
+    pub last_time: DateTime<Utc>,
+}
+
+/// Rolls the trades from one incoming order's sweep into a single fill.
+/// `None` if there are no trades or they carry no quantity.
+pub fn consolidate_execution(trades: &[Trade]) -> Option<ConsolidatedFill> {
+    let first_time = trades.iter().map(|trade| trade.execution_time).min()?;
+    let last_time = trades.iter().map(|trade| trade.execution_time).max()?;
+    let total_quantity: Decimal = trades.iter().map(|trade| trade.quantity).sum();
+    if total_quantity.is_zero() {
+        return None;
+    }
+
+    let notional: Decimal = trades.iter().map(|trade| trade.price * trade.quantity).sum();
+
+    Some(ConsolidatedFill {
+        total_quantity,
+        vwap: notional / total_quantity,
+        first_time,
+        last_time,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use rust_decimal_macros::dec;
+
+    fn create_test_order(side: OrderSide, quantity: Decimal) -> Order {
+        Order {
+            status: OrderStatus::FILLED,
+            remaining_quantity: Decimal::ZERO,
+            filled_quantity: quantity,
+            ..Order::for_test(side, None, quantity)
+        }
+    }
+
+    fn create_test_trade(order: &Order, price: Decimal, quantity: Decimal) -> Trade {
+        let (buyer_order_id, seller_order_id) = match order.side {
+            OrderSide::BUY => (order.id, Uuid::new_v4()),
+            OrderSide::SELL => (Uuid::new_v4(), order.id),
+        };
+
+        Trade {
+            buyer_order_id,
+            seller_order_id,
+            ..Trade::for_test(price, quantity)
+        }
+    }
+
+    #[test]
+    fn test_shortfall_for_buy_filled_above_decision_price() {
+        let order = create_test_order(OrderSide::BUY, dec!(10));
+        let mut trades = vec![
+            create_test_trade(&order, dec!(101), dec!(4)),
+            create_test_trade(&order, dec!(102), dec!(6)),
+        ];
+        // A trade belonging to some other order is not counted
+        trades.push(create_test_trade(&create_test_order(OrderSide::BUY, dec!(1)), dec!(150), dec!(1)));
+
+        // 4 * 1 + 6 * 2 = 16 paid over the decision price
+        assert_eq!(implementation_shortfall(&order, &trades, dec!(100)), dec!(16));
+    }
+
+    #[test]
+    fn test_shortfall_for_buy_filled_below_decision_price() {
+        let order = create_test_order(OrderSide::BUY, dec!(10));
+        let trades = vec![create_test_trade(&order, dec!(99.5), dec!(10))];
+
+        assert_eq!(implementation_shortfall(&order, &trades, dec!(100)), dec!(-5));
+    }
+
+    #[test]
+    fn test_shortfall_sign_flips_for_sells() {
+        let order = create_test_order(OrderSide::SELL, dec!(10));
+        let trades = vec![create_test_trade(&order, dec!(99), dec!(10))];
+
+        // Selling below the decision price is a cost
+        assert_eq!(implementation_shortfall(&order, &trades, dec!(100)), dec!(10));
+    }
+
+    #[test]
+    fn test_broker_traded_notional_counts_both_sides() {
+        let broker = Uuid::new_v4();
+        let order = create_test_order(OrderSide::BUY, dec!(10));
+
+        let mut bought = create_test_trade(&order, dec!(100), dec!(2));
+        bought.buyer_broker_id = broker;
+        let mut sold = create_test_trade(&order, dec!(50.5), dec!(4));
+        sold.seller_broker_id = broker;
+        let unrelated = create_test_trade(&order, dec!(1000), dec!(100));
+
+        let trades = vec![bought, unrelated, sold];
+
+        // 100 * 2 + 50.5 * 4
+        assert_eq!(broker_traded_notional(broker, &trades), dec!(402));
+        assert_eq!(broker_traded_notional(Uuid::new_v4(), &trades), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_consolidate_execution_of_a_sweep() {
+        let order = create_test_order(OrderSide::BUY, dec!(10));
+        let start = Utc::now();
+
+        let mut trades = vec![
+            create_test_trade(&order, dec!(100), dec!(2)),
+            create_test_trade(&order, dec!(101), dec!(3)),
+            create_test_trade(&order, dec!(102), dec!(5)),
+        ];
+        for (offset, trade) in trades.iter_mut().enumerate() {
+            trade.execution_time = start + Duration::microseconds(offset as i64 * 10);
+        }
+
+        let fill = consolidate_execution(&trades).unwrap();
+
+        // (200 + 303 + 510) / 10
+        assert_eq!(fill.total_quantity, dec!(10));
+        assert_eq!(fill.vwap, dec!(101.3));
+        assert_eq!(fill.first_time, start);
+        assert_eq!(fill.last_time, start + Duration::microseconds(20));
+        assert_eq!(consolidate_execution(&[]), None);
+    }
+
+    #[test]
+    fn test_pair_volume_counts_both_directions() {
+        let (alice, bob, carol) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let order = create_test_order(OrderSide::BUY, dec!(10));
+        let between = |buyer, seller, quantity| {
+            let mut trade = create_test_trade(&order, dec!(100), quantity);
+            trade.buyer_broker_id = buyer;
+            trade.seller_broker_id = seller;
+            trade
+        };
+
+        let trades = vec![
+            between(alice, bob, dec!(3)),
+            between(bob, alice, dec!(5)),
+            between(alice, carol, dec!(7)),
+            between(carol, bob, dec!(11)),
+            between(alice, bob, dec!(2)),
+        ];
+
+        assert_eq!(pair_volume(&trades, alice, bob), (3, dec!(10)));
+        assert_eq!(pair_volume(&trades, bob, alice), (3, dec!(10)));
+        assert_eq!(pair_volume(&trades, alice, carol), (1, dec!(7)));
+        assert_eq!(pair_volume(&trades, alice, Uuid::new_v4()), (0, Decimal::ZERO));
+    }
+}
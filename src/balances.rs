@@ -0,0 +1,24 @@
+use rust_decimal::Decimal;
+use std::fmt;
+use uuid::Uuid;
+
+/// Where the book hands back balances a broker locked to back a resting
+/// order. Locking for a new order happens before it reaches the book; the
+/// book only reports how much of it the order no longer needs, and asks for
+/// more when an amendment makes the order need more. Buys hold cash worth
+/// their notional, sells hold the instrument.
+pub trait BalanceProvider: fmt::Debug + Send + Sync {
+    /// Returns `amount` of `currency` to the broker's available cash
+    fn release_cash(&self, broker_id: Uuid, currency: &str, amount: Decimal);
+
+    /// Returns `quantity` of the instrument to the broker's available inventory
+    fn release_inventory(&self, broker_id: Uuid, instrument_id: Uuid, quantity: Decimal);
+
+    /// Locks a further `amount` of `currency`; `false` if the broker does not
+    /// have that much available, in which case nothing is locked
+    fn lock_cash(&self, broker_id: Uuid, currency: &str, amount: Decimal) -> bool;
+
+    /// Locks a further `quantity` of the instrument; `false` if the broker
+    /// does not have that much available, in which case nothing is locked
+    fn lock_inventory(&self, broker_id: Uuid, instrument_id: Uuid, quantity: Decimal) -> bool;
+}
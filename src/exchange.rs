@@ -0,0 +1,257 @@
+use super::models::*;
+use super::order_engine::{OrderBook, OrderError};
+use rust_decimal::Decimal;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Which venue a router prefers when several show the same best price
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RouteTieBreak {
+    /// The venue added to the exchange first
+    #[default]
+    FirstAdded,
+    /// The venue displaying the most quantity at that price
+    LargestSize,
+}
+
+/// What `Exchange::route_order` did. Fills made before a venue refused the
+/// order stand, so they are reported alongside the error.
+#[derive(Debug, Clone, Default)]
+pub struct RouteResult {
+    /// Every fill across the venues, in the order they happened
+    pub trades: Vec<Trade>,
+    /// Why routing stopped early, if a venue refused a child order
+    pub error: Option<OrderError>,
+}
+
+/// Several order books, possibly more than one per instrument (a lit book
+/// and a dark one, say), each identified by a venue id, with a router that
+/// spreads an order across the books for its instrument.
+#[derive(Debug, Default)]
+pub struct Exchange {
+    // Kept in the order added, which `RouteTieBreak::FirstAdded` relies on
+    venues: Vec<(Uuid, OrderBook)>,
+    tie_break: RouteTieBreak,
+}
+
+impl Exchange {
+    pub fn new(tie_break: RouteTieBreak) -> Self {
+        Self {
+            venues: Vec::new(),
+            tie_break,
+        }
+    }
+
+    /// Adds `book` as venue `venue_id`, replacing any book already there
+    pub fn add_venue(&mut self, venue_id: Uuid, book: OrderBook) {
+        match self.venues.iter_mut().find(|(id, _)| *id == venue_id) {
+            Some((_, existing)) => *existing = book,
+            None => self.venues.push((venue_id, book)),
+        }
+    }
+
+    pub fn venue(&self, venue_id: Uuid) -> Option<&OrderBook> {
+        self.venues.iter().find(|(id, _)| *id == venue_id).map(|(_, book)| book)
+    }
+
+    pub fn venue_mut(&mut self, venue_id: Uuid) -> Option<&mut OrderBook> {
+        self.venues.iter_mut().find(|(id, _)| *id == venue_id).map(|(_, book)| book)
+    }
+
+    /// The venue with the best displayed price for an order on `side` of
+    /// `instrument_id`, i.e. the lowest ask for a buy and the highest bid for
+    /// a sell. Venues tied on price are split by the exchange's `RouteTieBreak`.
+    pub fn best_venue(&self, instrument_id: Uuid, side: OrderSide) -> Option<Uuid> {
+        self.best_quote(instrument_id, &side, &HashSet::new()).map(|(venue_id, _)| venue_id)
+    }
+
+    // Venues in `skip` are left out, as if they had nothing to show
+    fn best_quote(&self, instrument_id: Uuid, side: &OrderSide, skip: &HashSet<Uuid>) -> Option<(Uuid, Decimal)> {
+        let mut best: Option<(Uuid, Decimal, Decimal)> = None;
+
+        let candidates = self
+            .venues
+            .iter()
+            .filter(|(venue_id, book)| book.instrument_id() == instrument_id && !skip.contains(venue_id));
+        for (venue_id, book) in candidates {
+            let (price, size) = match side {
+                OrderSide::BUY => (book.best_ask(), book.depth(1).asks.first().map(|level| level.quantity)),
+                OrderSide::SELL => (book.best_bid(), book.depth(1).bids.first().map(|level| level.quantity)),
+            };
+            let (Some(price), Some(size)) = (price, size) else {
+                continue;
+            };
+
+            let better = match best {
+                None => true,
+                Some((_, best_price, best_size)) => {
+                    let improves = match side {
+                        OrderSide::BUY => price < best_price,
+                        OrderSide::SELL => price > best_price,
+                    };
+                    improves || (price == best_price && self.tie_break == RouteTieBreak::LargestSize && size > best_size)
+                }
+            };
+            if better {
+                best = Some((*venue_id, price, size));
+            }
+        }
+
+        best.map(|(venue_id, price, _)| (venue_id, price))
+    }
+
+    /// Works `order` through the venues for its instrument, best price first:
+    /// each step sends an immediate-or-cancel child for what is left to the
+    /// current best venue at that venue's best price, never past the order's
+    /// own limit. Whatever cannot be filled that way is not rested anywhere.
+    /// A venue that fills nothing at its quoted price is passed over for the
+    /// rest of the route. An error from a venue stops the routing; fills
+    /// already made stand and come back with the error.
+    pub fn route_order(&mut self, order: Order) -> RouteResult {
+        let mut result = RouteResult::default();
+        let mut remaining = order.remaining_quantity;
+        let mut exhausted = HashSet::new();
+
+        while remaining > Decimal::ZERO {
+            let Some((venue_id, price)) = self.best_quote(order.instrument_id, &order.side, &exhausted) else {
+                break;
+            };
+            let within_limit = order.price.is_none_or(|limit| match order.side {
+                OrderSide::BUY => price <= limit,
+                OrderSide::SELL => price >= limit,
+            });
+            if !within_limit {
+                break;
+            }
+
+            let child = Order {
+                id: Uuid::new_v4(),
+                order_type: OrderType::LIMIT,
+                time_in_force: TimeInForce::IOC,
+                price: Some(price),
+                original_quantity: remaining,
+                remaining_quantity: remaining,
+                filled_quantity: Decimal::ZERO,
+                avg_fill_price: None,
+                ..order.clone()
+            };
+            let book = self.venue_mut(venue_id).expect("best_quote only returns known venues");
+            let fills = match book.add_order(child) {
+                Ok(fills) => fills,
+                Err(error) => {
+                    result.error = Some(error);
+                    break;
+                }
+            };
+
+            let filled: Decimal = fills.iter().map(|trade| trade.quantity).sum();
+            // Nothing tradable at the quoted price after all; asking again would loop
+            if filled.is_zero() {
+                exhausted.insert(venue_id);
+                continue;
+            }
+            remaining -= filled;
+            result.trades.extend(fills);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::order_engine::HaltReason;
+    use rust_decimal_macros::dec;
+
+    fn create_test_order(instrument_id: Uuid, side: OrderSide, price: Option<Decimal>, quantity: Decimal) -> Order {
+        Order {
+            instrument_id,
+            ..Order::for_test(side, price, quantity)
+        }
+    }
+
+    // A lit and a dark venue for one instrument, each with asks at the given levels
+    fn two_venue_exchange(
+        tie_break: RouteTieBreak,
+        lit_asks: &[(Decimal, Decimal)],
+        dark_asks: &[(Decimal, Decimal)],
+    ) -> (Exchange, Uuid, Uuid, Uuid) {
+        let instrument_id = Uuid::new_v4();
+        let (lit, dark) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut exchange = Exchange::new(tie_break);
+
+        for (venue_id, asks) in [(lit, lit_asks), (dark, dark_asks)] {
+            let mut book = OrderBook::new(instrument_id);
+            for &(price, quantity) in asks {
+                book.add_order(create_test_order(instrument_id, OrderSide::SELL, Some(price), quantity)).unwrap();
+            }
+            exchange.add_venue(venue_id, book);
+        }
+        (exchange, instrument_id, lit, dark)
+    }
+
+    #[test]
+    fn test_router_fills_better_priced_book_first() {
+        let lit_asks = [(dec!(101), dec!(5)), (dec!(103), dec!(5))];
+        let dark_asks = [(dec!(100), dec!(3)), (dec!(102), dec!(2))];
+        let (mut exchange, instrument_id, lit, dark) = two_venue_exchange(RouteTieBreak::FirstAdded, &lit_asks, &dark_asks);
+        assert_eq!(exchange.best_venue(instrument_id, OrderSide::BUY), Some(dark));
+        assert_eq!(exchange.best_venue(instrument_id, OrderSide::SELL), None);
+        assert_eq!(exchange.best_venue(Uuid::new_v4(), OrderSide::BUY), None);
+
+        let trades = exchange.route_order(create_test_order(instrument_id, OrderSide::BUY, Some(dec!(102)), dec!(12))).trades;
+
+        // 3 at 100 in the dark book, 5 at 101 lit, 2 at 102 dark; 103 is past the limit
+        let fills: Vec<(Decimal, Decimal)> = trades.iter().map(|t| (t.price, t.quantity)).collect();
+        assert_eq!(fills, vec![(dec!(100), dec!(3)), (dec!(101), dec!(5)), (dec!(102), dec!(2))]);
+        assert_eq!(exchange.venue(dark).unwrap().bbo(), (None, None));
+        assert_eq!(exchange.venue(lit).unwrap().bbo(), (None, Some(dec!(103))));
+        assert_eq!(exchange.best_venue(instrument_id, OrderSide::BUY), Some(lit));
+    }
+
+    #[test]
+    fn test_tie_break_between_books_at_the_same_price() {
+        let levels = |quantity| [(dec!(100), quantity)];
+
+        let (exchange, instrument_id, lit, _) = two_venue_exchange(RouteTieBreak::FirstAdded, &levels(dec!(2)), &levels(dec!(7)));
+        assert_eq!(exchange.best_venue(instrument_id, OrderSide::BUY), Some(lit));
+
+        let (mut exchange, instrument_id, lit, dark) = two_venue_exchange(RouteTieBreak::LargestSize, &levels(dec!(2)), &levels(dec!(7)));
+        assert_eq!(exchange.best_venue(instrument_id, OrderSide::BUY), Some(dark));
+
+        // The larger venue is drained first, then the router moves on
+        let trades = exchange.route_order(create_test_order(instrument_id, OrderSide::BUY, None, dec!(8))).trades;
+        let fills: Vec<Decimal> = trades.iter().map(|t| t.quantity).collect();
+        assert_eq!(fills, vec![dec!(7), dec!(1)]);
+        assert_eq!(exchange.venue(dark).unwrap().bbo(), (None, None));
+        assert_eq!(exchange.venue(lit).unwrap().depth(1).asks[0].quantity, dec!(1));
+    }
+
+    #[test]
+    fn test_router_passes_over_venue_that_cannot_fill() {
+        let (mut exchange, instrument_id, lit, dark) =
+            two_venue_exchange(RouteTieBreak::FirstAdded, &[(dec!(101), dec!(5))], &[(dec!(100), dec!(5))]);
+        // The dark book shows the better price but only trades in steps of 10
+        exchange.venue_mut(dark).unwrap().config_mut().min_fill_increment = Some(dec!(10));
+
+        let result = exchange.route_order(create_test_order(instrument_id, OrderSide::BUY, Some(dec!(101)), dec!(5)));
+        assert!(result.error.is_none());
+        let fills: Vec<(Decimal, Decimal)> = result.trades.iter().map(|t| (t.price, t.quantity)).collect();
+        assert_eq!(fills, vec![(dec!(101), dec!(5))]);
+        assert_eq!(exchange.venue(lit).unwrap().bbo(), (None, None));
+        assert_eq!(exchange.venue(dark).unwrap().bbo(), (None, Some(dec!(100))));
+    }
+
+    #[test]
+    fn test_router_reports_fills_made_before_a_venue_refuses() {
+        let (mut exchange, instrument_id, lit, _) =
+            two_venue_exchange(RouteTieBreak::FirstAdded, &[(dec!(101), dec!(5))], &[(dec!(100), dec!(3))]);
+        exchange.venue_mut(lit).unwrap().halt(HaltReason::Manual);
+
+        let result = exchange.route_order(create_test_order(instrument_id, OrderSide::BUY, Some(dec!(101)), dec!(8)));
+        let fills: Vec<(Decimal, Decimal)> = result.trades.iter().map(|t| (t.price, t.quantity)).collect();
+        assert_eq!(fills, vec![(dec!(100), dec!(3))]);
+        assert_eq!(result.error, Some(OrderError::Halted(HaltReason::Manual)));
+    }
+}
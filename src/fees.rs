@@ -0,0 +1,116 @@
+use super::models::*;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// How fee amounts are rounded to the currency's minor unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+    /// Midpoints round away from zero
+    HalfUp,
+    /// Midpoints round to the nearest even digit, so rounding carries no bias
+    #[default]
+    Bankers,
+    /// Anything past the minor unit rounds up
+    Ceiling,
+}
+
+impl RoundingMode {
+    pub fn round(&self, amount: Decimal, decimal_places: u32) -> Decimal {
+        let strategy = match self {
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::Bankers => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::Ceiling => RoundingStrategy::ToPositiveInfinity,
+        };
+        amount.round_dp_with_strategy(decimal_places, strategy)
+    }
+}
+
+/// Basis-point fees charged on traded notional
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeSchedule {
+    /// Charged to the resting side of a trade
+    pub maker_bps: Decimal,
+    /// Charged to the side that took liquidity
+    pub taker_bps: Decimal,
+    /// Decimal places of the currency's minor unit, e.g. 2 for cents
+    pub decimal_places: u32,
+    pub rounding: RoundingMode,
+    /// Currency the fees are charged in; must match the traded instrument's
+    pub currency: String,
+}
+
+impl FeeSchedule {
+    pub fn maker_fee(&self, notional: Decimal) -> Decimal {
+        self.fee(notional, self.maker_bps)
+    }
+
+    pub fn taker_fee(&self, notional: Decimal) -> Decimal {
+        self.fee(notional, self.taker_bps)
+    }
+
+    fn fee(&self, notional: Decimal, bps: Decimal) -> Decimal {
+        self.rounding.round(notional * bps / Decimal::from(10_000), self.decimal_places)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TradeFees {
+    pub maker_fee: Decimal,
+    pub taker_fee: Decimal,
+    pub currency: String,
+}
+
+/// Maker and taker fees on a trade's notional, rounded per the schedule
+pub fn compute_fees(trade: &Trade, schedule: &FeeSchedule) -> TradeFees {
+    let notional = trade.price * trade.quantity;
+
+    TradeFees {
+        maker_fee: schedule.maker_fee(notional),
+        taker_fee: schedule.taker_fee(notional),
+        currency: schedule.currency.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn schedule(rounding: RoundingMode) -> FeeSchedule {
+        FeeSchedule {
+            maker_bps: dec!(20),
+            taker_bps: dec!(100),
+            decimal_places: 2,
+            rounding,
+            currency: "RMD".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_rounding_modes_on_a_midpoint() {
+        assert_eq!(RoundingMode::HalfUp.round(dec!(0.125), 2), dec!(0.13));
+        assert_eq!(RoundingMode::Bankers.round(dec!(0.125), 2), dec!(0.12));
+        assert_eq!(RoundingMode::Ceiling.round(dec!(0.125), 2), dec!(0.13));
+
+        // Off the midpoint only ceiling rounds up
+        assert_eq!(RoundingMode::HalfUp.round(dec!(0.121), 2), dec!(0.12));
+        assert_eq!(RoundingMode::Bankers.round(dec!(0.121), 2), dec!(0.12));
+        assert_eq!(RoundingMode::Ceiling.round(dec!(0.121), 2), dec!(0.13));
+        assert_eq!(RoundingMode::default(), RoundingMode::Bankers);
+    }
+
+    #[test]
+    fn test_compute_fees_rounds_to_minor_unit() {
+        // Notional 12.5: taker 1% = 0.125, maker 0.2% = 0.025
+        let trade = Trade::for_test(dec!(12.5), dec!(1));
+
+        let fees = compute_fees(&trade, &schedule(RoundingMode::Bankers));
+        assert_eq!(fees.currency, "RMD");
+        assert_eq!((fees.maker_fee, fees.taker_fee), (dec!(0.02), dec!(0.12)));
+
+        let fees = compute_fees(&trade, &schedule(RoundingMode::HalfUp));
+        assert_eq!((fees.maker_fee, fees.taker_fee), (dec!(0.03), dec!(0.13)));
+
+        let fees = compute_fees(&trade, &schedule(RoundingMode::Ceiling));
+        assert_eq!((fees.maker_fee, fees.taker_fee), (dec!(0.03), dec!(0.13)));
+    }
+}
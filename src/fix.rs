@@ -0,0 +1,98 @@
+use super::order_engine::{DepthLevel, OrderBook};
+use rust_decimal::Decimal;
+
+/// FIX MDEntryType (tag 269), limited to the book sides
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MdEntryType {
+    Bid,
+    Offer,
+}
+
+impl MdEntryType {
+    /// The value sent on the wire
+    pub fn code(&self) -> char {
+        match self {
+            MdEntryType::Bid => '0',
+            MdEntryType::Offer => '1',
+        }
+    }
+}
+
+/// One repeating-group entry of a FIX market data snapshot (35=W)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MdEntry {
+    pub entry_type: MdEntryType,
+    /// MDEntryPx (270)
+    pub price: Decimal,
+    /// MDEntrySize (271)
+    pub size: Decimal,
+    /// MDEntryPositionNo (290): 1 for the best level on each side
+    pub position: u32,
+}
+
+/// The top `levels` displayed levels of each side as FIX-style entries,
+/// bids best first followed by offers best first
+pub fn market_data_snapshot(book: &OrderBook, levels: usize) -> Vec<MdEntry> {
+    let depth = book.depth(levels);
+
+    let entries = |entry_type: MdEntryType, side: Vec<DepthLevel>| {
+        side.into_iter().zip(1..).map(move |(level, position)| MdEntry {
+            entry_type,
+            price: level.price,
+            size: level.quantity,
+            position,
+        })
+    };
+
+    entries(MdEntryType::Bid, depth.bids)
+        .chain(entries(MdEntryType::Offer, depth.asks))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::models::*;
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    fn create_test_order(side: OrderSide, price: Decimal, quantity: Decimal) -> Order {
+        Order::for_test(side, Some(price), quantity)
+    }
+
+    #[test]
+    fn test_snapshot_of_a_two_level_book() {
+        let mut book = OrderBook::new(Uuid::nil());
+        for (side, price, quantity) in [
+            (OrderSide::BUY, dec!(99), dec!(3)),
+            (OrderSide::BUY, dec!(99), dec!(2)),
+            (OrderSide::BUY, dec!(98), dec!(4)),
+            (OrderSide::SELL, dec!(101), dec!(1)),
+            (OrderSide::SELL, dec!(102), dec!(6)),
+            (OrderSide::SELL, dec!(103), dec!(7)),
+        ] {
+            book.add_order(create_test_order(side, price, quantity)).unwrap();
+        }
+
+        let entries = market_data_snapshot(&book, 2);
+
+        let entry = |entry_type, price, size, position| MdEntry {
+            entry_type,
+            price,
+            size,
+            position,
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry(MdEntryType::Bid, dec!(99), dec!(5), 1),
+                entry(MdEntryType::Bid, dec!(98), dec!(4), 2),
+                entry(MdEntryType::Offer, dec!(101), dec!(1), 1),
+                entry(MdEntryType::Offer, dec!(102), dec!(6), 2),
+            ]
+        );
+        assert_eq!((entries[0].entry_type.code(), entries[2].entry_type.code()), ('0', '1'));
+        assert_eq!(market_data_snapshot(&book, 10).len(), 5);
+        assert!(market_data_snapshot(&OrderBook::new(Uuid::nil()), 5).is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+use super::models::*;
+use chrono::SecondsFormat;
+use serde::{Deserialize, Serialize};
+
+/// A trade as published on the public market-data feed. Nothing identifies
+/// the counterparties: broker ids, order ids and memos stay internal.
+/// Decimals are strings so consumers see exactly the scale the trade printed
+/// at, and the timestamp is RFC 3339 in UTC.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExternalTrade {
+    pub trade_id: String,
+    pub instrument_id: String,
+    pub price: String,
+    pub quantity: String,
+    pub executed_at: String,
+}
+
+pub fn trade_to_external(trade: &Trade) -> ExternalTrade {
+    ExternalTrade {
+        trade_id: trade.id.to_string(),
+        instrument_id: trade.instrument_id.to_string(),
+        price: trade.price.to_string(),
+        quantity: trade.quantity.to_string(),
+        executed_at: trade.execution_time.to_rfc3339_opts(SecondsFormat::Micros, true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_external_trade_hides_counterparties() {
+        let trade = Trade {
+            instrument_id: Uuid::new_v4(),
+            execution_time: Utc.with_ymd_and_hms(2025, 3, 10, 14, 30, 5).unwrap(),
+            spread_at_execution: Some(dec!(0.05)),
+            memo: Some("client ref 42".to_string()),
+            ..Trade::for_test(dec!(101.50), dec!(2.000))
+        };
+
+        let external = trade_to_external(&trade);
+
+        // Trailing zeros survive, so the feed shows the price as it printed
+        assert_eq!(external.price, "101.50");
+        assert_eq!(external.quantity, "2.000");
+        assert_eq!(external.executed_at, "2025-03-10T14:30:05.000000Z");
+        assert_eq!(external.trade_id, trade.id.to_string());
+        assert_eq!(external.instrument_id, trade.instrument_id.to_string());
+
+        let published = format!("{:?}", external);
+        for hidden in [trade.buyer_broker_id, trade.seller_broker_id, trade.buyer_order_id, trade.seller_order_id] {
+            assert!(!published.contains(&hidden.to_string()));
+        }
+        assert!(!published.contains("client ref"));
+    }
+}
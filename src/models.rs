@@ -18,6 +18,7 @@ pub enum InstrumentType {
 pub enum OrderType {
     LIMIT,
     MARKET,
+    TAS, // Trade at settlement: executes at the settlement price once it is printed
 }
 
 #[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -27,6 +28,25 @@ pub enum OrderSide {
     SELL,
 }
 
+impl OrderSide {
+    pub fn opposite(&self) -> OrderSide {
+        match self {
+            OrderSide::BUY => OrderSide::SELL,
+            OrderSide::SELL => OrderSide::BUY,
+        }
+    }
+}
+
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[sqlx(type_name = "varchar")]
+pub enum TimeInForce {
+    #[default]
+    GTC, // Good till cancelled
+    DAY, // Cancelled at session close
+    IOC, // Immediate or cancel
+    FOK, // Fill or kill
+}
+
 #[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[sqlx(type_name = "varchar")] // Changed from instrument_status to varchar to match SQL
 pub enum InstrumentStatus {
@@ -53,6 +73,17 @@ pub enum OrderStatus {
     REJECTED,
 }
 
+// Why a cancelled order was taken off the book
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[sqlx(type_name = "varchar")]
+pub enum CancelReason {
+    UserRequested,
+    BrokerSuspended,
+    Expiry, // DAY orders at session close
+    SelfTradePrevention,
+    ImmediateOrCancel, // IOC or FOK quantity that could not execute on arrival
+}
+
 #[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[sqlx(type_name = "varchar")] // Changed from trade_status to varchar to match SQL
 pub enum TradeStatus {
@@ -71,6 +102,7 @@ pub struct Instrument {
     pub status: InstrumentStatus,
     pub lot_size: i32,
     pub tick_size: Decimal, // Changed from f64 to Decimal to match SQL DECIMAL(10,4)
+    pub currency: String, // Currency the instrument trades and settles in
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -107,21 +139,36 @@ pub struct SecurityPosition {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
+#[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Order {
     pub id: Uuid,
     pub broker_id: Uuid,
     pub instrument_id: Uuid,
     pub order_type: OrderType,
     pub side: OrderSide,
+    pub time_in_force: TimeInForce,
     pub status: OrderStatus,
     pub price: Option<Decimal>,
     pub original_quantity: Decimal,
     pub remaining_quantity: Decimal,
+    pub filled_quantity: Decimal,
+    pub avg_fill_price: Option<Decimal>, // Running average over the order's fills
+    pub hidden: bool, // Hidden orders rest without appearing in market data
+    pub is_short: bool, // Short sales are subject to the uptick rule while restricted
+    pub tags: Vec<String>, // Free-form labels strategies use to group their orders
+    pub cancel_reason: Option<CancelReason>, // Set whenever the order is cancelled
+    pub memo: Option<String>, // Copied onto the trades this order makes as the aggressor
+    #[serde(default = "interacts_with_hidden_by_default")]
+    pub interact_with_hidden: bool, // When false the order only takes displayed liquidity
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+// Orders serialized before the flag existed took hidden liquidity, as the column default does
+fn interacts_with_hidden_by_default() -> bool {
+    true
+}
+
 #[derive(sqlx::FromRow, Serialize, Deserialize, Debug, Clone)]
 pub struct Trade {
     pub id: Uuid,
@@ -135,6 +182,68 @@ pub struct Trade {
     pub execution_time: DateTime<Utc>,
     pub status: TradeStatus,
     pub settlement_time: Option<DateTime<Utc>>,
+    pub spread_at_execution: Option<Decimal>, // Best ask minus best bid just before the incoming order started matching
+    pub memo: Option<String>, // Reference carried over from the aggressing order's memo
+}
+
+// Fixtures for tests across the crate. Set anything else with struct update syntax.
+#[cfg(test)]
+impl Order {
+    // A pending, unfilled GTC order for the nil instrument: a limit at
+    // `price` when one is given, a market order otherwise
+    pub(crate) fn for_test(side: OrderSide, price: Option<Decimal>, quantity: Decimal) -> Order {
+        Order {
+            id: Uuid::new_v4(),
+            broker_id: Uuid::new_v4(),
+            instrument_id: Uuid::nil(),
+            order_type: if price.is_some() { OrderType::LIMIT } else { OrderType::MARKET },
+            side,
+            time_in_force: TimeInForce::GTC,
+            status: OrderStatus::PENDING,
+            price,
+            original_quantity: quantity,
+            remaining_quantity: quantity,
+            filled_quantity: Decimal::ZERO,
+            avg_fill_price: None,
+            hidden: false,
+            is_short: false,
+            tags: Vec::new(),
+            cancel_reason: None,
+            memo: None,
+            interact_with_hidden: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Trade {
+    // A trade awaiting settlement between fresh orders and brokers on the nil instrument
+    pub(crate) fn for_test(price: Decimal, quantity: Decimal) -> Trade {
+        Trade {
+            id: Uuid::new_v4(),
+            instrument_id: Uuid::nil(),
+            buyer_order_id: Uuid::new_v4(),
+            seller_order_id: Uuid::new_v4(),
+            buyer_broker_id: Uuid::new_v4(),
+            seller_broker_id: Uuid::new_v4(),
+            price,
+            quantity,
+            execution_time: Utc::now(),
+            status: TradeStatus::PENDING_SETTLEMENT,
+            settlement_time: None,
+            spread_at_execution: None,
+            memo: None,
+        }
+    }
+}
+
+/// An amount tagged with its currency so figures from different markets are not mixed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Money {
+    pub amount: Decimal,
+    pub currency: String,
 }
 
 // These index structs appear to be helpers for database queries
@@ -1,427 +1,8839 @@
+use super::balances::BalanceProvider;
+use super::clock::{Clock, SystemClock};
+use super::fees::{FeeSchedule, RoundingMode};
 use super::models::*;
 use rust_decimal::Decimal;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
 use uuid::Uuid;
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
+
+/// Currency assumed for books not built from an `Instrument`
+pub const DEFAULT_CURRENCY: &str = "RMD";
+
+/// Tag marking an order as a market maker's quote; `quote` sets it on both legs
+pub const MARKET_MAKER_TAG: &str = "market-maker";
+
+// Spread history kept for `time_weighted_avg_spread` unless configured
+const DEFAULT_SPREAD_HISTORY_HOURS: i64 = 24;
+
+/// How orders smaller than the instrument's lot size are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OddLotPolicy {
+    /// Odd lots trade in the main book alongside round lots
+    #[default]
+    Allow,
+    /// Odd lots are refused at submission
+    Reject,
+    /// Odd lots rest in a dedicated book and only match other odd lots
+    SeparateBook,
+}
+
+/// Reference price a stop order is compared against when `check_stops` runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StopTrigger {
+    /// The price of the most recent trade
+    #[default]
+    LastTrade,
+    /// The midpoint of the best bid and best ask
+    Mid,
+    /// The touch on the side the stop would trade against: best ask for buys, best bid for sells
+    BidAsk,
+}
+
+/// Segregated flow for brokers that internalize: their orders match their own
+/// resting orders first, fall through to the public book, and rest internally
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum InternalizationPolicy {
+    #[default]
+    Disabled,
+    Brokers(HashSet<Uuid>),
+}
+
+/// How an incoming order's quantity is shared among resting orders at a price
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchingAlgo {
+    /// Price-time priority: earliest resting order fills first
+    #[default]
+    Fifo,
+    /// Each resting order at the level receives a share proportional to its size
+    ProRata,
+}
+
+impl MatchingAlgo {
+    fn strategy(self) -> Arc<dyn MatchingStrategy> {
+        match self {
+            MatchingAlgo::Fifo => Arc::new(FifoStrategy),
+            MatchingAlgo::ProRata => Arc::new(ProRataStrategy),
+        }
+    }
+}
+
+/// Whether incoming orders match on arrival or are collected for an auction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TradingPhase {
+    /// Limit orders rest without matching until `run_opening_auction` uncrosses the book
+    PreOpen,
+    #[default]
+    Continuous,
+    /// Frequent batch auctions: orders rest as in pre-open and each
+    /// `run_batch` uncrosses them at one price, staying in this phase
+    Batch,
+}
+
+/// What happens to the part of a market order the book could not fill
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MarketRemainderPolicy {
+    /// The unfilled quantity is rejected
+    #[default]
+    Reject,
+    /// The unfilled quantity rests as a limit order at the order's last fill price,
+    /// subject to the same level cap and price collar as any limit order. An
+    /// order that did not fill at all, or whose remainder those refuse, is
+    /// still rejected. Only GTC and DAY orders rest; an IOC or FOK remainder
+    /// is cancelled under either policy.
+    RestAtLastTrade,
+}
+
+/// What happens when an incoming order meets a resting order from the same broker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelfTradePolicy {
+    /// The two orders trade with each other like any other pair
+    #[default]
+    Allow,
+    /// The resting order is passed over and left in the book; matching
+    /// carries on with the next counterparty, at a worse price if the limit allows.
+    /// Orders routed to the broker's internal venue still cross as intended.
+    Skip,
+}
+
+/// How an auction's collection phase treats a limit order priced through the
+/// opposite side of the orders already collected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AuctionCrossingPolicy {
+    /// The order joins the pool and takes part in the uncross like any other
+    #[default]
+    Accept,
+    /// The order is refused, so the pool never crosses before the uncross
+    Reject,
+}
+
+/// Whether a fill may leave either order holding a quantity that is not a
+/// whole number of lots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PartialLotPolicy {
+    /// Fills take whatever the two orders can trade
+    #[default]
+    AllowOddResidual,
+    /// Each fill is rounded down to a whole number of lots, so a partly
+    /// filled order is left with a clean residual. A fill that rounds down to
+    /// nothing ends matching at that level, so no later order there trades
+    /// ahead of it.
+    RoundDown,
+}
+
+/// Sizing constraints the book imposes on every allocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationRules {
+    pub lot_size: Decimal,
+    /// When set, each fill is truncated down to a multiple of this increment
+    pub min_fill_increment: Option<Decimal>,
+}
+
+impl AllocationRules {
+    /// The most that can trade between an incoming and a resting quantity, truncated
+    /// to the fill increment so neither side is left an awkward remainder; zero
+    /// means the two orders cannot trade with each other
+    pub fn tradable(&self, incoming: Decimal, resting: Decimal) -> Decimal {
+        let quantity = incoming.min(resting);
+        match self.min_fill_increment {
+            Some(increment) if increment > Decimal::ZERO => quantity - quantity % increment,
+            _ => quantity,
+        }
+    }
+}
+
+/// Decides how an incoming order's quantity is shared among the orders resting
+/// at a single price level. The book handles price priority, trade creation and
+/// bookkeeping; a strategy only sizes the fills.
+pub trait MatchingStrategy: fmt::Debug + Send + Sync {
+    /// Quantity to trade against each order in `level`, in queue order. Entries
+    /// past the end of the returned vector are treated as zero. Allocating
+    /// nothing stops matching at this level.
+    fn allocate(&self, incoming: Decimal, level: &[Order], rules: &AllocationRules) -> Vec<Decimal>;
+}
+
+/// Price-time priority: earliest resting order fills first
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FifoStrategy;
+
+impl MatchingStrategy for FifoStrategy {
+    fn allocate(&self, incoming: Decimal, level: &[Order], rules: &AllocationRules) -> Vec<Decimal> {
+        let mut allocations = Vec::new();
+        let mut left = incoming;
+
+        for resting in level {
+            let quantity = rules.tradable(left, resting.remaining_quantity);
+            allocations.push(quantity);
+            left -= quantity;
+
+            // Later orders cannot trade while an earlier one still has quantity left
+            if quantity < resting.remaining_quantity || left.is_zero() {
+                break;
+            }
+        }
+
+        allocations
+    }
+}
+
+/// Each resting order receives a share proportional to its size. Shares are
+/// rounded down to whole lots; whatever rounding leaves over is handed out in
+/// time priority so the level fills as far as possible.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProRataStrategy;
+
+impl MatchingStrategy for ProRataStrategy {
+    fn allocate(&self, incoming: Decimal, level: &[Order], rules: &AllocationRules) -> Vec<Decimal> {
+        let level_quantity = saturating_total(level.iter().map(|o| o.remaining_quantity));
+        if incoming >= level_quantity {
+            return level.iter().map(|o| o.remaining_quantity).collect();
+        }
+
+        let lot = if rules.lot_size > Decimal::ZERO { rules.lot_size } else { Decimal::ONE };
+        let mut allocations: Vec<Decimal> = level
+            .iter()
+            .map(|o| {
+                let share = incoming * o.remaining_quantity / level_quantity;
+                share - share % lot
+            })
+            .collect();
+
+        let mut leftover = incoming - allocations.iter().copied().sum::<Decimal>();
+        for (allocation, resting) in allocations.iter_mut().zip(level) {
+            if leftover.is_zero() {
+                break;
+            }
+            let extra = leftover.min(resting.remaining_quantity - *allocation);
+            *allocation += extra;
+            leftover -= extra;
+        }
+
+        allocations
+    }
+}
+
+/// Halts the book when activity in the latest window is far above its recent
+/// average. Trade volume and cancel counts are measured separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnomalyGuard {
+    pub window: Duration,
+    /// Number of windows before the latest one that form the baseline average
+    pub baseline_windows: u32,
+    /// Halt once the latest window exceeds the baseline average by this factor
+    pub multiple: Decimal,
+    /// The latest window must also reach this much activity, so a quiet
+    /// baseline does not turn a handful of events into a halt
+    pub min_activity: Decimal,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BookConfig {
+    pub odd_lot_policy: OddLotPolicy,
+    pub stop_trigger: StopTrigger,
+    /// When set, each fill is truncated down to a multiple of this increment
+    pub min_fill_increment: Option<Decimal>,
+    /// When set, the book halts for volatility if the spread exceeds this after an operation
+    pub max_spread: Option<Decimal>,
+    pub internalization: InternalizationPolicy,
+    pub matching_algo: MatchingAlgo,
+    /// When set, short sells may only take liquidity on an uptick while short sales are restricted
+    pub uptick_rule: bool,
+    /// When set, events are buffered and only published by `flush_events` or once this many are pending
+    pub event_batch_size: Option<usize>,
+    /// When set, an order that would rest at a level already holding this many orders is rejected
+    pub max_orders_per_level: Option<usize>,
+    /// When set, limit orders priced more aggressively than this many basis points
+    /// through the mark price are rejected
+    pub price_collar_bps: Option<Decimal>,
+    pub market_remainder: MarketRemainderPolicy,
+    /// When set, orders cannot be cancelled until they have rested this long
+    pub min_resting_time: Option<Duration>,
+    pub anomaly_guard: Option<AnomalyGuard>,
+    pub self_trade: SelfTradePolicy,
+    /// How an auction shares the executable volume among orders at the last price it reaches
+    pub auction_allocation: MatchingAlgo,
+    pub auction_crossing: AuctionCrossingPolicy,
+    /// When set, limit orders tagged `MARKET_MAKER_TAG` must rest at least this
+    /// many ticks inside their side of the opposite touch
+    pub min_quote_distance_ticks: Option<u32>,
+    /// How far back `estimated_fill_time` looks to measure the execution rate;
+    /// the whole tape when unset
+    pub fill_rate_window: Option<Duration>,
+    /// How `average_fill_price` rounds to the display scale
+    pub average_price_rounding: RoundingMode,
+    pub partial_lot: PartialLotPolicy,
+    /// How far back `time_weighted_avg_spread` can look; a day when unset
+    pub spread_history: Option<Duration>,
+}
+
+/// An order held off the book until the reference price reaches `stop_price`
+#[derive(Debug, Clone)]
+pub struct StopOrder {
+    pub order: Order,
+    pub stop_price: Decimal,
+}
+
+/// Which end of a side's price map holds the best price.
+///
+/// Levels are keyed by `Decimal`, whose ordering is numeric across zero, so a
+/// side's best price is always an extreme of its map: the numerically highest
+/// bid and the numerically lowest ask, even when prices are negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceOrdering {
+    /// Best price is the lowest key (asks)
+    Ascending,
+    /// Best price is the highest key (bids)
+    Descending,
+}
+
+impl PriceOrdering {
+    pub fn for_side(side: &OrderSide) -> Self {
+        match side {
+            OrderSide::BUY => PriceOrdering::Descending,
+            OrderSide::SELL => PriceOrdering::Ascending,
+        }
+    }
+
+    /// Whether `a` is a strictly better price than `b` under this ordering
+    pub fn is_better(self, a: Decimal, b: Decimal) -> bool {
+        match self {
+            PriceOrdering::Ascending => a < b,
+            PriceOrdering::Descending => a > b,
+        }
+    }
+
+    /// Iterates `levels` from the best price to the worst
+    pub fn best_first<'a, V>(
+        self,
+        levels: &'a BTreeMap<Decimal, V>,
+    ) -> Box<dyn Iterator<Item = (&'a Decimal, &'a V)> + 'a> {
+        match self {
+            PriceOrdering::Ascending => Box::new(levels.iter()),
+            PriceOrdering::Descending => Box::new(levels.iter().rev()),
+        }
+    }
+}
+
+/// Aggregated resting interest at one price
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepthLevel {
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub order_count: usize,
+}
+
+/// Price levels on each side, best first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookDepth {
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+/// Displayed quantity at every price level, used as the baseline for `diff`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookSnapshot {
+    pub bids: BTreeMap<Decimal, Decimal>,
+    pub asks: BTreeMap<Decimal, Decimal>,
+}
+
+/// A price level that was added, changed or removed; removals carry zero quantity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LevelChange {
+    pub side: OrderSide,
+    pub price: Decimal,
+    pub new_quantity: Decimal,
+}
+
+/// What `repair` removed from the price levels
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Orders resting despite a FILLED, CANCELLED or REJECTED status
+    pub terminal: Vec<Uuid>,
+    /// Live orders resting with nothing left to trade
+    pub exhausted: Vec<Uuid>,
+}
+
+impl RepairReport {
+    pub fn is_clean(&self) -> bool {
+        self.terminal.is_empty() && self.exhausted.is_empty()
+    }
+}
+
+/// Market-data summary of the trades in a rolling window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TradeStats {
+    pub count: usize,
+    pub volume: Decimal,
+    /// `None` along with `high` and `low` when nothing traded in the window
+    pub vwap: Option<Decimal>,
+    pub high: Option<Decimal>,
+    pub low: Option<Decimal>,
+}
+
+/// One broker's order flow, for surveillance. An amendment counts only for the
+/// size it changes: quantity added as submitted, quantity removed as cancelled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BrokerActivity {
+    /// Quantity sent in orders that passed validation
+    pub submitted: Decimal,
+    pub filled_volume: Decimal,
+    /// Quantity taken off the book unfilled, whatever the reason
+    pub cancelled_volume: Decimal,
+    /// `cancelled_volume / filled_volume`; `None` until something fills.
+    /// Persistently high values are a classic sign of spoofing.
+    pub cancel_to_fill_ratio: Option<Decimal>,
+}
+
+/// Snapshot of the lit book's shape for health dashboards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookDiagnostics {
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+    pub total_orders: usize,
+    /// Hidden quantity included
+    pub total_bid_volume: Decimal,
+    pub total_ask_volume: Decimal,
+    pub spread: Option<Decimal>,
+    /// Best bid at or above best ask
+    pub is_crossed: bool,
+    /// How long the longest-resting order has been in the book, by the book's clock
+    pub oldest_order_age: Option<Duration>,
+}
+
+/// What a market order would get from the lit book as it stands
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MarketFillEstimate {
+    /// Less than requested if the book is not deep enough
+    pub filled_quantity: Decimal,
+    pub notional: Decimal,
+    /// `None` along with `worst_price` when nothing would fill
+    pub avg_price: Option<Decimal>,
+    pub worst_price: Option<Decimal>,
+}
+
+/// A market order's estimated fill with taker fees added in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TotalCostEstimate {
+    pub notional: Decimal,
+    pub fees: Decimal,
+    /// Cash out for a buy (notional plus fees), cash in for a sell (notional less fees)
+    pub all_in: Decimal,
+}
+
+/// An order's trades together with how it matched, from `add_order_detailed`
+#[derive(Debug, Clone)]
+pub struct ExecutionReport {
+    pub trades: Vec<Trade>,
+    /// Distinct prices the order traded at
+    pub levels_swept: usize,
+    /// Distinct resting orders the order traded against
+    pub counterparties: usize,
+    pub notional: Decimal,
+}
+
+/// Why a market order did not fill completely
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketOrderReason {
+    /// The opposite side was empty, so nothing traded
+    NoLiquidity,
+    /// Part of the order traded before the opposite side ran out
+    LiquidityExhausted,
+}
+
+/// How a market order from `submit_market` ended up
+#[derive(Debug, Clone)]
+pub struct MarketOrderOutcome {
+    pub status: OrderStatus,
+    pub filled_quantity: Decimal,
+    pub trades: Vec<Trade>,
+    /// `None` when the order filled in full
+    pub reason: Option<MarketOrderReason>,
+}
+
+/// What one `check_stops` call did
+#[derive(Debug, Clone, Default)]
+pub struct StopCheckOutcome {
+    /// Trades the triggered stops made, in execution order
+    pub trades: Vec<Trade>,
+    /// Triggered stops the book refused, with the reason. They are dropped
+    /// from `stop_orders`, so the caller decides whether to resubmit them.
+    pub rejected: Vec<(Uuid, OrderError)>,
+}
+
+/// A broken book invariant found by `health_check`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookInvariantViolation {
+    /// The best bid is at or above the best ask
+    Crossed { bid: Decimal, ask: Decimal },
+    /// A price level with no orders in it
+    EmptyLevel { side: OrderSide, price: Decimal },
+    NegativeRemaining(Uuid),
+    /// A live order with nothing left, or a filled one with quantity left
+    StatusMismatch { order_id: Uuid, status: OrderStatus, remaining: Decimal },
+    /// An order in a price level that `orders` does not know about
+    Untracked(Uuid),
+    /// An order whose level copy and `orders` copy disagree
+    CopiesDiffer(Uuid),
+    /// A live order in `orders` that is not in any price level
+    NotResting(Uuid),
+}
+
+/// What `cancel_order` would do to the book, computed without cancelling
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CancelPreview {
+    pub side: OrderSide,
+    pub price: Decimal,
+    /// Remaining quantity the cancel takes off the book
+    pub quantity_freed: Decimal,
+    /// Total quantity left resting at the level afterwards
+    pub level_quantity_after: Decimal,
+    /// True if the order is the last one at its price
+    pub level_removed: bool,
+}
+
+/// How a trade printed relative to the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickDirection {
+    Up,
+    Down,
+    Zero,
+}
+
+/// Why continuous trading was stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    /// Halted by an operator via `halt`
+    Manual,
+    /// The spread widened past `BookConfig::max_spread`
+    Volatility,
+    /// Trade volume or cancel rate spiked past `BookConfig::anomaly_guard`
+    Anomaly,
+}
+
+/// Notifications emitted as the book processes orders, drained with `drain_events`
+#[derive(Debug, Clone)]
+pub enum BookEvent {
+    Trade(Trade),
+    /// An incoming order rested without taking any liquidity
+    Accepted { order_id: Uuid },
+    /// An incoming order took some liquidity and rested the remainder
+    RestedAfterPartial { order_id: Uuid, filled: Decimal, remaining: Decimal },
+    Halted { reason: HaltReason },
+    Resumed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderError {
+    OddLot { quantity: Decimal, lot_size: Decimal },
+    InvalidTickSize { price: Decimal, tick_size: Decimal },
+    InstrumentNotActive(InstrumentStatus),
+    InternalInconsistency(String),
+    Halted(HaltReason),
+    ShortSaleRestricted,
+    LevelFull { price: Decimal, max_orders: usize },
+    PriceOutOfBand { price: Decimal, reference: Decimal },
+    DuplicateOrderId(Uuid),
+    /// The order is unknown or no longer resting
+    NotCancellable(Uuid),
+    TooSoonToCancel { order_id: Uuid, remaining: Duration },
+    CurrencyMismatch { expected: String, found: String },
+    InsufficientFunds { required: Decimal, available: Decimal },
+    WouldLockMarket { price: Decimal, touch: Decimal },
+    /// The order is unknown or no longer resting
+    NotAmendable(Uuid),
+    /// Amended quantities must stay above zero
+    InvalidQuantity(Decimal),
+    /// The async intake's consumer has stopped, so the order was not processed
+    IntakeClosed,
+    /// Market, IOC and FOK orders need continuous matching, which is off while an auction collects orders.
+    /// Crossing limit orders are refused too under `AuctionCrossingPolicy::Reject`.
+    NoContinuousMatchingInAuction,
+    /// The order is for a different instrument than the book trades
+    InstrumentMismatch { expected: Uuid, found: Uuid },
+    /// The order is being matched and cannot be changed until matching finishes
+    OrderLocked(Uuid),
+    /// Tick size and lot size must both be positive
+    InvalidInstrument { tick_size: Decimal, lot_size: i32 },
+    /// The balance provider could not lock the extra cash or inventory an amendment needs
+    BalanceUnavailable(Uuid),
+    /// A two-sided quote's bid must sit below its ask, at least `min_quote_distance_ticks` apart
+    QuoteTooNarrow { bid: Decimal, ask: Decimal },
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::OddLot { quantity, lot_size } => {
+                write!(f, "quantity {} is below the lot size of {}", quantity, lot_size)
+            }
+            OrderError::InvalidTickSize { price, tick_size } => {
+                write!(f, "price {} is not a multiple of the tick size {}", price, tick_size)
+            }
+            OrderError::InstrumentNotActive(status) => {
+                write!(f, "instrument is not accepting orders (status {:?})", status)
+            }
+            OrderError::InternalInconsistency(detail) => {
+                write!(f, "internal inconsistency: {}", detail)
+            }
+            OrderError::Halted(reason) => write!(f, "trading is halted ({:?})", reason),
+            OrderError::ShortSaleRestricted => {
+                write!(f, "short sales may only execute on an uptick or rest above the bid")
+            }
+            OrderError::LevelFull { price, max_orders } => {
+                write!(f, "price level {} already holds the maximum of {} orders", price, max_orders)
+            }
+            OrderError::PriceOutOfBand { price, reference } => {
+                write!(f, "price {} is outside the collar around reference price {}", price, reference)
+            }
+            OrderError::DuplicateOrderId(id) => write!(f, "an order with id {} already exists", id),
+            OrderError::NotCancellable(id) => write!(f, "order {} is not resting and cannot be cancelled", id),
+            OrderError::TooSoonToCancel { order_id, remaining } => {
+                write!(f, "order {} cannot be cancelled for another {}", order_id, remaining)
+            }
+            OrderError::CurrencyMismatch { expected, found } => {
+                write!(f, "expected a {} position but was given {}", expected, found)
+            }
+            OrderError::InsufficientFunds { required, available } => {
+                write!(f, "order needs {} but only {} is available", required, available)
+            }
+            OrderError::WouldLockMarket { price, touch } => {
+                write!(f, "quote at {} is too close to the opposite touch at {}", price, touch)
+            }
+            OrderError::NotAmendable(id) => write!(f, "order {} is not resting and cannot be amended", id),
+            OrderError::InvalidQuantity(quantity) => write!(f, "quantity {} must be greater than zero", quantity),
+            OrderError::IntakeClosed => write!(f, "order intake is no longer running"),
+            OrderError::NoContinuousMatchingInAuction => {
+                write!(f, "orders cannot execute immediately while the book is collecting for an auction")
+            }
+            OrderError::InstrumentMismatch { expected, found } => {
+                write!(f, "order is for instrument {} but this book trades {}", found, expected)
+            }
+            OrderError::OrderLocked(order_id) => write!(f, "order {} is being matched", order_id),
+            OrderError::InvalidInstrument { tick_size, lot_size } => {
+                write!(f, "instrument needs a positive tick size and lot size, got {} and {}", tick_size, lot_size)
+            }
+            OrderError::BalanceUnavailable(order_id) => {
+                write!(f, "not enough available balance to back the amendment of order {}", order_id)
+            }
+            OrderError::QuoteTooNarrow { bid, ask } => {
+                write!(f, "quote bid {} must sit below its ask {} by the minimum quote distance", bid, ask)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+/// Activity measured by the anomaly guard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Activity {
+    TradeVolume,
+    Cancel,
+}
+
+/// Which set of price levels an order rests in or matches against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Venue {
+    Lit,
+    OddLot,
+    Internal(Uuid),
+}
+
+/// Sums quantities, clamping at `Decimal::MAX` instead of panicking on overflow.
+/// Quantities are never negative, so the only way out of range is upwards.
+fn saturating_total(quantities: impl Iterator<Item = Decimal>) -> Decimal {
+    quantities.fold(Decimal::ZERO, |total, quantity| total.saturating_add(quantity))
+}
+
+/// Checks the parts of an instrument's definition a book relies on: a zero
+/// tick size would make every price fail tick validation, and a zero lot
+/// size would make every order an odd lot
+pub fn validate_instrument(instrument: &Instrument) -> Result<(), OrderError> {
+    if instrument.tick_size <= Decimal::ZERO || instrument.lot_size <= 0 {
+        return Err(OrderError::InvalidInstrument {
+            tick_size: instrument.tick_size,
+            lot_size: instrument.lot_size,
+        });
+    }
+    Ok(())
+}
+
+/// `None` if there are no trades or the notional does not fit in a `Decimal`
+fn vwap_of<'a>(mut trades: impl Iterator<Item = &'a Trade>) -> Option<Decimal> {
+    let (notional, volume) = trades.try_fold((Decimal::ZERO, Decimal::ZERO), |(notional, volume), trade| {
+        Some((
+            notional.checked_add(trade.price.checked_mul(trade.quantity)?)?,
+            volume.checked_add(trade.quantity)?,
+        ))
+    })?;
+
+    if volume.is_zero() {
+        None
+    } else {
+        Some(notional / volume)
+    }
+}
+
+/// Books a fill against an order, keeping its running fill totals current
+fn apply_fill(order: &mut Order, quantity: Decimal, price: Decimal) {
+    let filled_before = order.filled_quantity;
+    order.filled_quantity += quantity;
+    order.avg_fill_price = Some(match order.avg_fill_price {
+        Some(avg) => (avg * filled_before + price * quantity) / order.filled_quantity,
+        None => price,
+    });
+
+    order.remaining_quantity -= quantity;
+    order.status = if order.remaining_quantity.is_zero() {
+        OrderStatus::FILLED
+    } else {
+        OrderStatus::PARTIAL
+    };
+}
+
+static EMPTY_LEVELS: BTreeMap<Decimal, Vec<Order>> = BTreeMap::new();
 
 #[derive(Debug)]
 pub struct OrderBook {
     instrument_id: Uuid,
+    currency: String,
+    tick_size: Option<Decimal>,
+    display_scale: Option<u32>,
+    lot_size: Decimal,
+    status: InstrumentStatus,
+    config: BookConfig,
     bids: BTreeMap<Decimal, Vec<Order>>,
     asks: BTreeMap<Decimal, Vec<Order>>,
+    odd_bids: BTreeMap<Decimal, Vec<Order>>,
+    odd_asks: BTreeMap<Decimal, Vec<Order>>,
+    internal_bids: HashMap<Uuid, BTreeMap<Decimal, Vec<Order>>>,
+    internal_asks: HashMap<Uuid, BTreeMap<Decimal, Vec<Order>>>,
     orders: HashMap<Uuid, Order>,
+    frozen_prices: HashSet<Decimal>,
+    tape: Vec<Trade>,
+    tape_positions: HashMap<Uuid, usize>,
+    order_trade_ids: HashMap<Uuid, Vec<Uuid>>,
+    last_trade_price: Option<Decimal>,
+    last_tick_direction: Option<TickDirection>,
+    // The spread as it stood when the order now being matched arrived
+    pre_trade_spread: Option<Decimal>,
+    stop_orders: Vec<StopOrder>,
+    events: Vec<BookEvent>,
+    pending_events: Vec<BookEvent>,
+    halt: Option<HaltReason>,
+    activity: VecDeque<(DateTime<Utc>, Activity, Decimal)>,
+    short_sale_restricted: bool,
+    clock: Arc<dyn Clock>,
+    balances: Option<Arc<dyn BalanceProvider>>,
+    matching_strategy: Option<Arc<dyn MatchingStrategy>>,
+    phase: TradingPhase,
+    // Order ids of each market maker's current two-sided quote
+    quotes: HashMap<Uuid, Vec<Uuid>>,
+    // Spread each time it changed, oldest first
+    spread_samples: VecDeque<(DateTime<Utc>, Option<Decimal>)>,
+    // Trade-at-settlement orders waiting for `run_settlement_print`, oldest first
+    tas_queue: Vec<Uuid>,
+    broker_activity: HashMap<Uuid, BrokerActivity>,
+    // Orders in the middle of being matched, which must not be cancelled or amended
+    locked_orders: HashSet<Uuid>,
+    // Queue sequence numbers of orders added with `add_order_with_priority`
+    replay_sequences: HashMap<Uuid, u64>,
 }
 
 impl OrderBook {
     pub fn new(instrument_id: Uuid) -> Self {
+        Self::with_config(instrument_id, BookConfig::default())
+    }
+
+    pub fn with_config(instrument_id: Uuid, config: BookConfig) -> Self {
         Self {
             instrument_id,
+            currency: DEFAULT_CURRENCY.to_string(),
+            tick_size: None,
+            display_scale: None,
+            lot_size: Decimal::ONE,
+            status: InstrumentStatus::ACTIVE,
+            config,
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
+            odd_bids: BTreeMap::new(),
+            odd_asks: BTreeMap::new(),
+            internal_bids: HashMap::new(),
+            internal_asks: HashMap::new(),
             orders: HashMap::new(),
+            frozen_prices: HashSet::new(),
+            tape: Vec::new(),
+            tape_positions: HashMap::new(),
+            order_trade_ids: HashMap::new(),
+            last_trade_price: None,
+            last_tick_direction: None,
+            pre_trade_spread: None,
+            stop_orders: Vec::new(),
+            events: Vec::new(),
+            pending_events: Vec::new(),
+            halt: None,
+            activity: VecDeque::new(),
+            short_sale_restricted: false,
+            clock: Arc::new(SystemClock),
+            balances: None,
+            matching_strategy: None,
+            phase: TradingPhase::default(),
+            quotes: HashMap::new(),
+            spread_samples: VecDeque::new(),
+            tas_queue: Vec::new(),
+            broker_activity: HashMap::new(),
+            locked_orders: HashSet::new(),
+            replay_sequences: HashMap::new(),
         }
     }
 
-    pub fn add_order(&mut self, mut order: Order) -> Vec<Trade> {
-        let mut trades = Vec::new();
+    /// Builds a book that validates orders against the instrument's tick size,
+    /// lot size and trading status.
+    pub fn for_instrument(instrument: &Instrument) -> Result<Self, OrderError> {
+        validate_instrument(instrument)?;
+
+        let mut book = Self::new(instrument.id);
+        book.tick_size = Some(instrument.tick_size);
+        book.lot_size = Decimal::from(instrument.lot_size);
+        book.status = instrument.status.clone();
+        book.currency = instrument.currency.clone();
+        Ok(book)
+    }
+
+    pub fn instrument_id(&self) -> Uuid {
+        self.instrument_id
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// `price * quantity` in the book's currency
+    pub fn notional(&self, price: Decimal, quantity: Decimal) -> Money {
+        Money {
+            amount: price * quantity,
+            currency: self.currency.clone(),
+        }
+    }
+
+    /// Checks that `cash` can pay for `order`. Only buys need cash, and only a
+    /// position in the book's own currency counts. Market buys are sized at the
+    /// worst price they could reach on the current book.
+    pub fn check_cash(&self, order: &Order, cash: &CashPosition) -> Result<(), OrderError> {
+        if order.side != OrderSide::BUY {
+            return Ok(());
+        }
+        if cash.currency != self.currency {
+            return Err(OrderError::CurrencyMismatch {
+                expected: self.currency.clone(),
+                found: cash.currency.clone(),
+            });
+        }
+
+        let price = order
+            .price
+            .or_else(|| self.clearing_price_for_quantity(OrderSide::BUY, order.remaining_quantity))
+            .or_else(|| self.levels(&OrderSide::SELL, Venue::Lit).keys().next_back().copied())
+            .unwrap_or(Decimal::ZERO);
+        let required = self.notional(price, order.remaining_quantity).amount;
+        let available = cash.total_balance - cash.locked_balance;
+
+        if required > available {
+            Err(OrderError::InsufficientFunds { required, available })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn tick_size(&self) -> Option<Decimal> {
+        self.tick_size
+    }
+
+    /// Decimal places prices are shown with in `depth`: the override from
+    /// `set_display_scale` if any, otherwise the tick size's own scale
+    /// (0.01 shows two places). `None` leaves prices as stored.
+    pub fn display_scale(&self) -> Option<u32> {
+        self.display_scale.or(self.tick_size.map(|tick_size| tick_size.scale()))
+    }
+
+    pub fn set_display_scale(&mut self, scale: Option<u32>) {
+        self.display_scale = scale;
+    }
+
+    pub fn lot_size(&self) -> Decimal {
+        self.lot_size
+    }
+
+    pub fn status(&self) -> &InstrumentStatus {
+        &self.status
+    }
+
+    /// Whether two books hold the same instrument and the same resting orders,
+    /// level by level in the same queue order, and agree on every order they
+    /// know about. Configuration, the tape and events are not compared.
+    pub fn structurally_eq(&self, other: &OrderBook) -> bool {
+        self.instrument_id == other.instrument_id
+            && self.bids == other.bids
+            && self.asks == other.asks
+            && self.orders == other.orders
+    }
+
+    pub fn config(&self) -> &BookConfig {
+        &self.config
+    }
+
+    pub fn config_mut(&mut self) -> &mut BookConfig {
+        &mut self.config
+    }
+
+    /// Matches with `strategy` instead of the one selected by `BookConfig::matching_algo`
+    pub fn set_matching_strategy(&mut self, strategy: Arc<dyn MatchingStrategy>) {
+        self.matching_strategy = Some(strategy);
+    }
+
+    /// Replaces the time source used for trade timestamps and order ages
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Releases locked balances to `provider` whenever a resting order is
+    /// cancelled or reduced, and locks the extra an amendment needs
+    pub fn set_balance_provider(&mut self, provider: Arc<dyn BalanceProvider>) {
+        self.balances = Some(provider);
+    }
+
+    pub fn matching_algo(&self) -> MatchingAlgo {
+        self.config.matching_algo
+    }
+
+    pub fn set_lot_size(&mut self, lot_size: Decimal) {
+        self.lot_size = lot_size;
+    }
+
+    /// Pauses trading at `price`. Orders at a frozen level keep resting but are
+    /// skipped by the matcher, and new orders priced there rest without matching.
+    pub fn freeze_price(&mut self, price: Decimal) {
+        self.frozen_prices.insert(price);
+    }
+
+    pub fn unfreeze_price(&mut self, price: Decimal) {
+        self.frozen_prices.remove(&price);
+    }
+
+    pub fn is_price_frozen(&self, price: Decimal) -> bool {
+        self.frozen_prices.contains(&price)
+    }
+
+    /// Matches `order` against the book and rests any limit remainder.
+    ///
+    /// The returned trades are in execution order: better-priced levels come
+    /// before worse ones, and within a level earlier resting orders come first.
+    /// Ledger and settlement code rely on this ordering.
+    pub fn add_order(&mut self, mut order: Order) -> Result<Vec<Trade>, OrderError> {
+        self.validate_order(&order)?;
+        let activity = self.broker_activity.entry(order.broker_id).or_default();
+        activity.submitted = activity.submitted.saturating_add(order.remaining_quantity);
+
         order.status = OrderStatus::PENDING;
+        let order_id = order.id;
+        // Captured before any level is touched, so every fill of a sweep
+        // reports the spread the order actually faced
+        self.pre_trade_spread = self.spread();
+
+        if order.order_type == OrderType::TAS {
+            self.tas_queue.push(order_id);
+            self.orders.insert(order_id, order);
+            self.emit_rest_event(order_id);
+            return Ok(Vec::new());
+        }
+
+        if self.phase != TradingPhase::Continuous {
+            self.queue_for_auction(order)?;
+            self.emit_rest_event(order_id);
+            return Ok(Vec::new());
+        }
+
+        if order.time_in_force == TimeInForce::FOK && self.simulated_fill(&order) < order.remaining_quantity {
+            order.status = OrderStatus::CANCELLED;
+            order.cancel_reason = Some(CancelReason::ImmediateOrCancel);
+            self.note_cancelled(order.broker_id, order.remaining_quantity);
+            self.orders.insert(order.id, order);
+            return Ok(Vec::new());
+        }
+
+        let trades = self.execute(order)?;
+        self.emit_rest_event(order_id);
+        self.sample_spread();
+        self.check_spread();
+
+        Ok(trades)
+    }
 
-        match order.order_type {
+    // Matches an accepted order in continuous trading, rests any remainder and
+    // books the trades. Shared by `add_order` and by amendments that lose
+    // their place, which must not look like new orders to anything else.
+    fn execute(&mut self, order: Order) -> Result<Vec<Trade>, OrderError> {
+        let mut trades = Vec::new();
+        let order_id = order.id;
+
+        self.lock_order(order_id);
+        let processed = match order.order_type {
             OrderType::LIMIT => self.process_limit_order(order, &mut trades),
             OrderType::MARKET => self.process_market_order(order, &mut trades),
+            OrderType::TAS => unreachable!("trade-at-settlement orders never match on arrival"),
+        };
+        self.unlock_order(order_id);
+        processed?;
+
+        for trade in &trades {
+            self.record_trade(trade);
         }
 
-        trades
+        let mut touched: Vec<Uuid> = trades.iter().flat_map(|t| [t.buyer_order_id, t.seller_order_id]).collect();
+        touched.push(order_id);
+        self.reconcile(&touched);
+
+        Ok(trades)
     }
 
-    fn process_limit_order(&mut self, mut order: Order, trades: &mut Vec<Trade>) {
-        let price = order.price.expect("Limit orders must have a price");
-        let side = order.side.clone();
+    /// `add_order` for a market order, saying whether a shortfall was down to
+    /// an empty book or to liquidity running out part way
+    pub fn submit_market(&mut self, order: Order) -> Result<MarketOrderOutcome, OrderError> {
+        let order_id = order.id;
+        let trades = self.add_order(order)?;
+        let order = self
+            .orders
+            .get(&order_id)
+            .ok_or_else(|| OrderError::InternalInconsistency(format!("market order {} was not stored", order_id)))?;
 
-        loop {
-            let matching_order_opt = match side {
-                OrderSide::BUY => self.get_best_ask(),
-                OrderSide::SELL => self.get_best_bid(),
-            };
+        let reason = if order.remaining_quantity.is_zero() {
+            None
+        } else if order.filled_quantity.is_zero() {
+            Some(MarketOrderReason::NoLiquidity)
+        } else {
+            Some(MarketOrderReason::LiquidityExhausted)
+        };
 
-            match matching_order_opt {
-                Some((best_price, matched_order)) if self.prices_match(side.clone(), price, best_price) => {
-                    let trade_quantity = order.remaining_quantity.min(matched_order.remaining_quantity);
+        Ok(MarketOrderOutcome {
+            status: order.status.clone(),
+            filled_quantity: order.filled_quantity,
+            trades,
+            reason,
+        })
+    }
 
-                    trades.push(self.create_trade(
-                        &order,
-                        &matched_order,
-                        best_price,
-                        trade_quantity
-                    ));
+    /// `add_order`, with the trades summarised for routing feedback
+    pub fn add_order_detailed(&mut self, order: Order) -> Result<ExecutionReport, OrderError> {
+        let order_id = order.id;
+        let trades = self.add_order(order)?;
 
-                    order.remaining_quantity -= trade_quantity;
-                    order.status = if order.remaining_quantity == Decimal::ZERO {
-                        OrderStatus::FILLED
-                    } else {
-                        OrderStatus::PARTIAL
-                    };
+        let levels: HashSet<Decimal> = trades.iter().map(|trade| trade.price).collect();
+        let counterparties: HashSet<Uuid> = trades
+            .iter()
+            .map(|trade| {
+                if trade.buyer_order_id == order_id {
+                    trade.seller_order_id
+                } else {
+                    trade.buyer_order_id
+                }
+            })
+            .collect();
 
-                    self.update_matched_order(&matched_order, trade_quantity, best_price, side.clone());
+        Ok(ExecutionReport {
+            levels_swept: levels.len(),
+            counterparties: counterparties.len(),
+            notional: trades.iter().map(|trade| trade.price * trade.quantity).sum(),
+            trades,
+        })
+    }
 
-                    if order.remaining_quantity == Decimal::ZERO {
-                        break;
-                    }
-                }
-                _ => break,
-            }
+    /// Takes up to `quantity` from the opposite side without building an `Order`
+    /// by hand: a market order when `limit_price` is `None`, otherwise an IOC
+    /// limit. Nothing from a sweep is left resting.
+    pub fn sweep(
+        &mut self,
+        side: OrderSide,
+        quantity: Decimal,
+        limit_price: Option<Decimal>,
+        broker_id: Uuid,
+    ) -> Result<Vec<Trade>, OrderError> {
+        let order = self.new_order(broker_id, side, limit_price, quantity, TimeInForce::IOC);
+        self.add_order(order)
+    }
+
+    /// Replaces `broker_id`'s two-sided quote in one step: the broker's
+    /// previous quote is pulled from both sides, then the new bid and ask are
+    /// placed as GTC limits tagged `MARKET_MAKER_TAG`. Both legs are validated first, so a quote the book
+    /// would refuse leaves the old one standing. Returns any trades either leg made on arrival.
+    ///
+    /// The legs are checked against the book as it will be once the old
+    /// quote is gone, so a new quote may sit where the old one faced it. They
+    /// are checked against each other before anything is pulled: a bid at or
+    /// above the ask, or closer to it than `min_quote_distance_ticks`, is refused.
+    pub fn quote(
+        &mut self,
+        broker_id: Uuid,
+        bid_price: Decimal,
+        bid_qty: Decimal,
+        ask_price: Decimal,
+        ask_qty: Decimal,
+    ) -> Result<Vec<Trade>, OrderError> {
+        let distance = match (self.config.min_quote_distance_ticks, self.tick_size) {
+            (Some(ticks), Some(tick)) => tick * Decimal::from(ticks),
+            _ => Decimal::ZERO,
+        };
+        if bid_price >= ask_price || ask_price - bid_price < distance {
+            return Err(OrderError::QuoteTooNarrow {
+                bid: bid_price,
+                ask: ask_price,
+            });
         }
 
-        if order.remaining_quantity > Decimal::ZERO {
-            match side {
-                OrderSide::BUY => self.bids.entry(price)
-                    .or_insert_with(Vec::new)
-                    .push(order.clone()),
-                OrderSide::SELL => self.asks.entry(price)
-                    .or_insert_with(Vec::new)
-                    .push(order.clone()),
+        let mut bid = self.new_order(broker_id, OrderSide::BUY, Some(bid_price), bid_qty, TimeInForce::GTC);
+        let mut ask = self.new_order(broker_id, OrderSide::SELL, Some(ask_price), ask_qty, TimeInForce::GTC);
+        bid.tags.push(MARKET_MAKER_TAG.to_string());
+        ask.tags.push(MARKET_MAKER_TAG.to_string());
+
+        let previous = self.quotes.get(&broker_id).cloned().unwrap_or_default();
+        let lifted = self.lift_from_levels(&previous);
+        let checked = [&bid, &ask]
+            .into_iter()
+            .try_for_each(|leg| self.validate_order(leg).and_then(|()| self.pre_trade_checks(leg)));
+        self.put_back(lifted);
+        checked?;
+
+        for order_id in previous {
+            self.remove_resting(order_id, CancelReason::UserRequested);
+        }
+        self.quotes.remove(&broker_id);
+
+        // Both legs passed every check above, so only a halt the bid itself
+        // sets off can stop the ask; the quote then records the bid alone
+        let (bid_id, ask_id) = (bid.id, ask.id);
+        let mut trades = self.add_order(bid)?;
+        self.quotes.insert(broker_id, vec![bid_id]);
+        trades.extend(self.add_order(ask)?);
+        self.quotes.insert(broker_id, vec![bid_id, ask_id]);
+        Ok(trades)
+    }
+
+    // Takes `order_ids` out of their levels without cancelling them, noting
+    // where each sat so `put_back` restores the queue exactly
+    fn lift_from_levels(&mut self, order_ids: &[Uuid]) -> Vec<(Venue, usize, Order)> {
+        let mut lifted = Vec::new();
+        for order_id in order_ids {
+            let Some(order) = self.orders.get(order_id) else {
+                continue;
+            };
+            let (Some(price), side, venue) = (order.price, order.side.clone(), self.located_venue(order)) else {
+                continue;
+            };
+            let levels = self.levels_mut(&side, venue);
+            let Some(level) = levels.get_mut(&price) else {
+                continue;
+            };
+            if let Some(pos) = level.iter().position(|o| o.id == *order_id) {
+                lifted.push((venue, pos, level.remove(pos)));
+                if level.is_empty() {
+                    levels.remove(&price);
+                }
             }
         }
+        lifted
+    }
 
-        self.orders.insert(order.id, order);
+    fn put_back(&mut self, lifted: Vec<(Venue, usize, Order)>) {
+        // In reverse, so every order goes back to the index it came from
+        for (venue, pos, order) in lifted.into_iter().rev() {
+            let price = order.price.expect("Lifted orders were resting at a price");
+            let level = self.levels_mut(&order.side, venue).entry(price).or_default();
+            level.insert(pos.min(level.len()), order);
+        }
     }
 
-    fn process_market_order(&mut self, mut order: Order, trades: &mut Vec<Trade>) {
-        let side = order.side.clone();
+    fn new_order(
+        &self,
+        broker_id: Uuid,
+        side: OrderSide,
+        limit_price: Option<Decimal>,
+        quantity: Decimal,
+        time_in_force: TimeInForce,
+    ) -> Order {
+        let now = self.clock.now();
+        Order {
+            id: Uuid::new_v4(),
+            broker_id,
+            instrument_id: self.instrument_id,
+            order_type: if limit_price.is_some() { OrderType::LIMIT } else { OrderType::MARKET },
+            side,
+            time_in_force,
+            status: OrderStatus::PENDING,
+            price: limit_price,
+            original_quantity: quantity,
+            remaining_quantity: quantity,
+            filled_quantity: Decimal::ZERO,
+            avg_fill_price: None,
+            hidden: false,
+            is_short: false,
+            tags: Vec::new(),
+            cancel_reason: None,
+            memo: None,
+            interact_with_hidden: true,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Ends the trading session: every resting or pending-stop `DAY` order is
+    /// cancelled while `GTC` orders carry over to the next session.
+    pub fn close_session(&mut self) -> Vec<Order> {
+        self.stop_orders.retain(|stop| stop.order.time_in_force != TimeInForce::DAY);
+
+        let mut day_orders: Vec<(chrono::DateTime<Utc>, Uuid)> = self
+            .orders
+            .values()
+            .filter(|order| order.time_in_force == TimeInForce::DAY)
+            .filter(|order| order.status == OrderStatus::PENDING || order.status == OrderStatus::PARTIAL)
+            .map(|order| (order.created_at, order.id))
+            .collect();
+        day_orders.sort();
+
+        day_orders
+            .into_iter()
+            .filter_map(|(_, order_id)| self.remove_resting(order_id, CancelReason::Expiry))
+            .collect()
+    }
+
+    /// Stops accepting new orders until `resume` is called. Cancels are still allowed.
+    pub fn halt(&mut self, reason: HaltReason) {
+        if self.halt.is_none() {
+            self.halt = Some(reason);
+            self.emit(BookEvent::Halted { reason });
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.halt.take().is_some() {
+            self.emit(BookEvent::Resumed);
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halt.is_some()
+    }
+
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        self.halt
+    }
+
+    /// Stops continuous matching: from now on limit orders are only collected,
+    /// until `run_opening_auction` uncrosses them
+    pub fn begin_pre_open(&mut self) {
+        self.phase = TradingPhase::PreOpen;
+    }
+
+    /// Switches from continuous matching to frequent batch auctions. The
+    /// caller runs `run_batch` at the end of every interval;
+    /// `run_opening_auction` uncrosses one last time and returns to
+    /// continuous trading.
+    pub fn begin_batch_auctions(&mut self) {
+        self.phase = TradingPhase::Batch;
+    }
+
+    pub fn trading_phase(&self) -> TradingPhase {
+        self.phase
+    }
+
+    /// Uncrosses the lit book at the single price that executes the most
+    /// volume and opens continuous trading. Ties go to the price leaving the
+    /// least volume unmatched, then to the lowest price. Every order priced
+    /// through the clearing price fills at it; the side with more interest is
+    /// filled in price priority, sharing out the last level it reaches per
+    /// `BookConfig::auction_allocation`. Frozen levels take no part. Returns
+    /// `None` for the price if the book does not cross; if a trade cannot be
+    /// made, the book is left untouched and stays in its auction phase.
+    pub fn run_opening_auction(&mut self) -> Result<(Option<Decimal>, Vec<Trade>), OrderError> {
+        let result = self.run_batch()?;
+        self.phase = TradingPhase::Continuous;
+        Ok(result)
+    }
+
+    /// Uncrosses everything collected since the last batch at a single
+    /// price, chosen and allocated as in `run_opening_auction`, and keeps
+    /// collecting for the next one. Returns `None` for the price if the book
+    /// does not cross.
+    pub fn run_batch(&mut self) -> Result<(Option<Decimal>, Vec<Trade>), OrderError> {
+        match self.auction_clearing_price(None) {
+            Some(price) => Ok((Some(price), self.uncross_at(price)?)),
+            None => Ok((None, Vec::new())),
+        }
+    }
+
+    /// Uncrosses the orders collected for the close, as `run_batch` does
+    /// except that prices still tied after volume and imbalance go to the one
+    /// nearest `reference_price`, typically the last trade, before the lowest.
+    /// Collect the closing orders with `begin_pre_open` first; the book stays
+    /// in whatever phase it is in.
+    pub fn run_closing_auction(&mut self, reference_price: Option<Decimal>) -> Result<(Option<Decimal>, Vec<Trade>), OrderError> {
+        match self.auction_clearing_price(reference_price) {
+            Some(price) => Ok((Some(price), self.uncross_at(price)?)),
+            None => Ok((None, Vec::new())),
+        }
+    }
+
+    fn queue_for_auction(&mut self, order: Order) -> Result<(), OrderError> {
+        if order.order_type == OrderType::MARKET || matches!(order.time_in_force, TimeInForce::IOC | TimeInForce::FOK) {
+            return Err(OrderError::NoContinuousMatchingInAuction);
+        }
+        if self.config.auction_crossing == AuctionCrossingPolicy::Reject && self.is_marketable(order.side.clone(), order.price) {
+            return Err(OrderError::NoContinuousMatchingInAuction);
+        }
+        self.check_level_capacity(&order)?;
+
+        let venue = self.resting_venue(&order);
+        self.rest_order(order.clone(), venue);
+        self.orders.insert(order.id, order);
+        Ok(())
+    }
+
+    // Volume executable at `price`, and how much interest it leaves unmatched.
+    // Frozen levels are left out, as the matcher leaves them out.
+    fn auction_volume_at(&self, price: Decimal) -> (Decimal, Decimal) {
+        let live = |(level_price, _): &(&Decimal, &Vec<Order>)| !self.frozen_prices.contains(*level_price);
+        let demand = saturating_total(self.bids.range(price..).filter(live).flat_map(|(_, level)| level).map(|o| o.remaining_quantity));
+        let supply = saturating_total(self.asks.range(..=price).filter(live).flat_map(|(_, level)| level).map(|o| o.remaining_quantity));
+        (demand.min(supply), (demand - supply).abs())
+    }
+
+    // Most volume, then least imbalance, then nearest `reference` if given,
+    // then lowest price
+    fn auction_clearing_price(&self, reference: Option<Decimal>) -> Option<Decimal> {
+        let distance = |price: Decimal| reference.map_or(Decimal::ZERO, |reference| (price - reference).abs());
+
+        self.bids
+            .keys()
+            .chain(self.asks.keys())
+            .filter(|price| !self.frozen_prices.contains(price))
+            .map(|&price| (price, self.auction_volume_at(price)))
+            .filter(|(_, (volume, _))| !volume.is_zero())
+            .min_by(|(a_price, (a_volume, a_imbalance)), (b_price, (b_volume, b_imbalance))| {
+                b_volume
+                    .cmp(a_volume)
+                    .then(a_imbalance.cmp(b_imbalance))
+                    .then(distance(*a_price).cmp(&distance(*b_price)))
+                    .then(a_price.cmp(b_price))
+            })
+            .map(|(price, _)| price)
+    }
+
+    fn uncross_at(&mut self, price: Decimal) -> Result<Vec<Trade>, OrderError> {
+        let (volume, _) = self.auction_volume_at(price);
+        let buys = self.auction_allocations(OrderSide::BUY, price, volume);
+        let sells = self.auction_allocations(OrderSide::SELL, price, volume);
+        self.cross_at(price, buys, sells)
+    }
+
+    // Trades the `(order id, quantity)` lists against each other in order at
+    // `price`, books the fills and records the trades. Every trade is made
+    // before any fill is booked, so an error leaves the book as it was.
+    fn cross_at(
+        &mut self,
+        price: Decimal,
+        mut buys: Vec<(Uuid, Decimal)>,
+        mut sells: Vec<(Uuid, Decimal)>,
+    ) -> Result<Vec<Trade>, OrderError> {
+        // Both sides fill at the one price, so any pairing of buyers with sellers is fair
+        let mut trades = Vec::new();
+        let mut fills: HashMap<Uuid, Decimal> = HashMap::new();
+        let (mut next_buy, mut next_sell) = (0, 0);
+        while next_buy < buys.len() && next_sell < sells.len() {
+            let (buy_id, sell_id) = (buys[next_buy].0, sells[next_sell].0);
+            let quantity = buys[next_buy].1.min(sells[next_sell].1);
+            let trade = self.create_trade(&self.orders[&buy_id], &self.orders[&sell_id], price, quantity)?;
+            // Neither side aggressed, so there is no memo to carry
+            trades.push(Trade { memo: None, ..trade });
+            *fills.entry(buy_id).or_default() += quantity;
+            *fills.entry(sell_id).or_default() += quantity;
+
+            buys[next_buy].1 -= quantity;
+            sells[next_sell].1 -= quantity;
+            if buys[next_buy].1.is_zero() {
+                next_buy += 1;
+            }
+            if sells[next_sell].1.is_zero() {
+                next_sell += 1;
+            }
+        }
+
+        // There is no continuous market to take a spread from
+        self.pre_trade_spread = None;
+
+        let touched: Vec<Uuid> = fills.keys().copied().collect();
+        for (order_id, quantity) in fills {
+            if let Some(order) = self.orders.get_mut(&order_id) {
+                apply_fill(order, quantity, price);
+            }
+        }
+        self.reconcile(&touched);
+
+        for trade in &trades {
+            self.record_trade(trade);
+        }
+        self.sample_spread();
+        Ok(trades)
+    }
+
+    /// Executes the queued trade-at-settlement orders against each other at
+    /// `settlement_price`, whatever limits they carry, buyers and sellers each
+    /// in time order. What cannot be matched expires, since it was only good
+    /// for this settlement. If a trade cannot be made, nothing executes and
+    /// the queue is kept.
+    pub fn run_settlement_print(&mut self, settlement_price: Decimal) -> Result<Vec<Trade>, OrderError> {
+        let queue = self.tas_queue.clone();
+        let waiting = |side: OrderSide| {
+            queue
+                .iter()
+                .filter_map(|id| self.orders.get(id))
+                .filter(|o| o.side == side && (o.status == OrderStatus::PENDING || o.status == OrderStatus::PARTIAL))
+                .map(|o| (o.id, o.remaining_quantity))
+                .collect::<Vec<_>>()
+        };
+        let (buys, sells) = (waiting(OrderSide::BUY), waiting(OrderSide::SELL));
+
+        let trades = self.cross_at(settlement_price, buys, sells)?;
+
+        self.tas_queue.clear();
+        for order_id in queue {
+            self.remove_resting(order_id, CancelReason::Expiry);
+        }
+        Ok(trades)
+    }
+
+    // Walks `side` best price first, handing out `volume` with the auction
+    // allocation strategy. Returns `(order id, quantity)` in priority order.
+    fn auction_allocations(&self, side: OrderSide, price: Decimal, volume: Decimal) -> Vec<(Uuid, Decimal)> {
+        let strategy = self.config.auction_allocation.strategy();
+        let rules = AllocationRules {
+            lot_size: self.lot_size,
+            min_fill_increment: self.config.min_fill_increment,
+        };
+
+        let mut allocations = Vec::new();
+        let mut left = volume;
+        for (&level_price, level) in self.levels_best_first(&side, Venue::Lit) {
+            if left.is_zero() || !self.prices_match(side.clone(), level_price, price) {
+                break;
+            }
+            if self.frozen_prices.contains(&level_price) {
+                continue;
+            }
+            for (order, quantity) in level.iter().zip(strategy.allocate(left, level, &rules)) {
+                if quantity > Decimal::ZERO {
+                    allocations.push((order.id, quantity));
+                    left -= quantity;
+                }
+            }
+        }
+        allocations
+    }
+
+    /// Puts the instrument into (or out of) the restricted state that triggers the uptick rule
+    pub fn set_short_sale_restricted(&mut self, restricted: bool) {
+        self.short_sale_restricted = restricted;
+    }
+
+    pub fn is_short_sale_restricted(&self) -> bool {
+        self.short_sale_restricted
+    }
+
+    // Without a tick size a quote only has to stay off the touch itself
+    fn check_quote_distance(&self, order: &Order) -> Result<(), OrderError> {
+        let (Some(ticks), Some(price)) = (self.config.min_quote_distance_ticks, order.price) else {
+            return Ok(());
+        };
+        if !order.tags.iter().any(|tag| tag == MARKET_MAKER_TAG) {
+            return Ok(());
+        }
+
+        let distance = self.tick_size.map_or(Decimal::ZERO, |tick| tick * Decimal::from(ticks));
+        let (touch, too_close) = match order.side {
+            OrderSide::BUY => match self.best_ask() {
+                Some(ask) => (ask, price >= ask || price > ask - distance),
+                None => return Ok(()),
+            },
+            OrderSide::SELL => match self.best_bid() {
+                Some(bid) => (bid, price <= bid || price < bid + distance),
+                None => return Ok(()),
+            },
+        };
+
+        if too_close {
+            Err(OrderError::WouldLockMarket { price, touch })
+        } else {
+            Ok(())
+        }
+    }
+
+    // A restricted short sell can still rest passively, but may only take
+    // liquidity if the last trade was an uptick
+    fn check_short_sale(&self, order: &Order) -> Result<(), OrderError> {
+        let restricted = self.config.uptick_rule && self.short_sale_restricted;
+        if !restricted || !order.is_short || order.side != OrderSide::SELL {
+            return Ok(());
+        }
+        if self.last_tick_direction == Some(TickDirection::Up) || self.immediately_fillable(order).is_zero() {
+            return Ok(());
+        }
+        Err(OrderError::ShortSaleRestricted)
+    }
+
+    // Compares the latest window with the average of the windows before it and
+    // halts on a spike. Only as much history as the baseline needs is kept.
+    fn note_activity(&mut self, kind: Activity, amount: Decimal) {
+        let Some(guard) = self.config.anomaly_guard else {
+            return;
+        };
+
+        let now = self.clock.now();
+        let window_start = now - guard.window;
+        let baseline_start = window_start - guard.window * guard.baseline_windows as i32;
+
+        self.activity.push_back((now, kind, amount));
+        while self.activity.front().is_some_and(|(time, _, _)| *time < baseline_start) {
+            self.activity.pop_front();
+        }
+
+        let (mut latest, mut baseline) = (Decimal::ZERO, Decimal::ZERO);
+        for (time, _, amount) in self.activity.iter().filter(|(_, k, _)| *k == kind) {
+            if *time >= window_start {
+                latest += amount;
+            } else {
+                baseline += amount;
+            }
+        }
+
+        let average = baseline / Decimal::from(guard.baseline_windows.max(1));
+        if latest >= guard.min_activity && latest > average * guard.multiple {
+            self.halt(HaltReason::Anomaly);
+        }
+    }
+
+    // Records the spread whenever it changes, for `time_weighted_avg_spread`.
+    // A change that was superseded before the history window began is
+    // dropped; the one in force at the start of the window is kept.
+    fn sample_spread(&mut self) {
+        let spread = self.spread();
+        if self.spread_samples.back().is_some_and(|&(_, last)| last == spread) {
+            return;
+        }
+
+        let now = self.clock.now();
+        self.spread_samples.push_back((now, spread));
+        let history = self.config.spread_history.unwrap_or_else(|| Duration::hours(DEFAULT_SPREAD_HISTORY_HOURS));
+        while self.spread_samples.get(1).is_some_and(|&(next, _)| next <= now - history) {
+            self.spread_samples.pop_front();
+        }
+    }
+
+    /// Average spread since `since`, each value weighted by how long it was
+    /// in force up to now. Time the book spent one-sided is left out; `None`
+    /// if there was no two-sided market in the period. Spreads superseded
+    /// longer than `BookConfig::spread_history` ago are forgotten, and their
+    /// time is left out too.
+    pub fn time_weighted_avg_spread(&self, since: DateTime<Utc>) -> Option<Decimal> {
+        let now = self.clock.now();
+        let mut weighted = Decimal::ZERO;
+        let mut total = Decimal::ZERO;
+
+        for (i, &(start, spread)) in self.spread_samples.iter().enumerate() {
+            let end = self.spread_samples.get(i + 1).map_or(now, |&(next, _)| next);
+            let Some(spread) = spread else {
+                continue;
+            };
+
+            let Some(micros) = (end - start.max(since)).num_microseconds().filter(|&micros| micros > 0) else {
+                continue;
+            };
+            let duration = Decimal::from(micros);
+            weighted += spread * duration;
+            total += duration;
+        }
+
+        (!total.is_zero()).then(|| weighted / total)
+    }
+
+    // Liquidity evaporating on either side shows up as a blown-out spread
+    fn check_spread(&mut self) {
+        let (Some(max_spread), Some(spread)) = (self.config.max_spread, self.spread()) else {
+            return;
+        };
+        if spread > max_spread {
+            self.halt(HaltReason::Volatility);
+        }
+    }
+
+    /// Takes all published events since the last call, oldest first
+    pub fn drain_events(&mut self) -> Vec<BookEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Publishes any events held back by `BookConfig::event_batch_size`
+    pub fn flush_events(&mut self) {
+        self.events.append(&mut self.pending_events);
+    }
+
+    fn emit(&mut self, event: BookEvent) {
+        let Some(batch_size) = self.config.event_batch_size else {
+            self.events.push(event);
+            return;
+        };
+
+        self.pending_events.push(event);
+        if self.pending_events.len() >= batch_size {
+            self.flush_events();
+        }
+    }
+
+    // Every order is held twice, in its level and in `orders`. After matching,
+    // make each touched order's status agree with its remaining quantity, take
+    // completed orders off their level and bring the level copy in line.
+    fn reconcile(&mut self, touched: &[Uuid]) {
+        for order_id in touched {
+            let Some(order) = self.orders.get_mut(order_id) else {
+                continue;
+            };
+
+            let live = matches!(order.status, OrderStatus::PENDING | OrderStatus::PARTIAL);
+            if live && order.remaining_quantity.is_zero() {
+                order.status = OrderStatus::FILLED;
+            } else if order.status == OrderStatus::PENDING && order.remaining_quantity < order.original_quantity {
+                order.status = OrderStatus::PARTIAL;
+            }
+
+            let order = order.clone();
+            let Some(price) = order.price else {
+                continue;
+            };
+            let venue = self.located_venue(&order);
+            let levels = self.levels_mut(&order.side, venue);
+            let Some(level) = levels.get_mut(&price) else {
+                continue;
+            };
+
+            if let Some(pos) = level.iter().position(|o| o.id == order.id) {
+                if matches!(order.status, OrderStatus::PENDING | OrderStatus::PARTIAL) {
+                    level[pos] = order;
+                } else {
+                    level.remove(pos);
+                    if level.is_empty() {
+                        levels.remove(&price);
+                    }
+                }
+            }
+        }
+    }
+
+    fn emit_rest_event(&mut self, order_id: Uuid) {
+        let Some(order) = self.orders.get(&order_id) else {
+            return;
+        };
+        if order.status != OrderStatus::PENDING && order.status != OrderStatus::PARTIAL {
+            return;
+        }
+
+        let filled = order.original_quantity - order.remaining_quantity;
+        let event = if filled > Decimal::ZERO {
+            BookEvent::RestedAfterPartial {
+                order_id,
+                filled,
+                remaining: order.remaining_quantity,
+            }
+        } else {
+            BookEvent::Accepted { order_id }
+        };
+        self.emit(event);
+    }
+
+    /// Every trade executed by this book, in execution order
+    pub fn trades(&self) -> &[Trade] {
+        &self.tape
+    }
+
+    /// Ids of the trades an order participated in, on either side
+    pub fn trade_ids_for_order(&self, order_id: Uuid) -> &[Uuid] {
+        self.order_trade_ids
+            .get(&order_id)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    pub fn trades_for_order(&self, order_id: Uuid) -> Vec<&Trade> {
+        self.trade_ids_for_order(order_id)
+            .iter()
+            .filter_map(|trade_id| self.tape_positions.get(trade_id))
+            .map(|&position| &self.tape[position])
+            .collect()
+    }
+
+    /// Trades on the tape priced within `[low, high]`, in execution order
+    pub fn trades_in_price_range(&self, low: Decimal, high: Decimal) -> Vec<&Trade> {
+        self.tape.iter().filter(|trade| (low..=high).contains(&trade.price)).collect()
+    }
+
+    /// A copy of the tape for persisting market data across restarts
+    pub fn tape_snapshot(&self) -> Vec<Trade> {
+        self.tape.clone()
+    }
+
+    /// Replaces the tape with `trades` and rebuilds everything derived from it:
+    /// per-order trade lookups, the last price and the tick direction.
+    /// No events are emitted for restored trades.
+    pub fn restore_tape(&mut self, trades: Vec<Trade>) {
+        self.tape.clear();
+        self.tape_positions.clear();
+        self.order_trade_ids.clear();
+        self.last_trade_price = None;
+        self.last_tick_direction = None;
+
+        for trade in trades {
+            self.append_to_tape(trade);
+        }
+    }
+
+    /// Volume-weighted average price over the whole tape; `None` if nothing has
+    /// traded or the notional does not fit in a `Decimal`
+    pub fn vwap(&self) -> Option<Decimal> {
+        vwap_of(self.tape.iter())
+    }
+
+    /// Value of every order resting on the bid side, hidden, odd-lot and
+    /// internal orders included: the sum of `price * remaining_quantity`.
+    /// Saturates at `Decimal::MAX` rather than overflowing.
+    pub fn bid_notional(&self) -> Decimal {
+        self.side_notional(OrderSide::BUY)
+    }
+
+    /// The ask-side counterpart of `bid_notional`
+    pub fn ask_notional(&self) -> Decimal {
+        self.side_notional(OrderSide::SELL)
+    }
+
+    fn side_notional(&self, side: OrderSide) -> Decimal {
+        self.all_levels()
+            .filter(|(level_side, _)| *level_side == side)
+            .flat_map(|(_, levels)| levels.iter())
+            .flat_map(|(&price, orders)| orders.iter().map(move |order| price.checked_mul(order.remaining_quantity)))
+            .try_fold(Decimal::ZERO, |total, notional| total.checked_add(notional?))
+            .unwrap_or(Decimal::MAX)
+    }
+
+    /// Volume-weighted average price of `trades`, e.g. one swept market
+    /// order's fills, to full precision
+    pub fn exact_average_fill_price(&self, trades: &[Trade]) -> Option<Decimal> {
+        vwap_of(trades.iter())
+    }
+
+    /// `exact_average_fill_price` rounded to the book's display scale with
+    /// `BookConfig::average_price_rounding`, for client reports. Left exact
+    /// when the book has no display scale.
+    pub fn average_fill_price(&self, trades: &[Trade]) -> Option<Decimal> {
+        let exact = self.exact_average_fill_price(trades)?;
+        Some(match self.display_scale() {
+            Some(scale) => self.config.average_price_rounding.round(exact, scale),
+            None => exact,
+        })
+    }
+
+    /// Summary of the trades executed in the `window` up to and including `now`
+    pub fn trade_stats(&self, window: Duration, now: DateTime<Utc>) -> TradeStats {
+        let in_window: Vec<&Trade> = self
+            .tape
+            .iter()
+            .filter(|trade| trade.execution_time > now - window && trade.execution_time <= now)
+            .collect();
+
+        TradeStats {
+            count: in_window.len(),
+            volume: saturating_total(in_window.iter().map(|trade| trade.quantity)),
+            vwap: vwap_of(in_window.iter().copied()),
+            high: in_window.iter().map(|trade| trade.price).max(),
+            low: in_window.iter().map(|trade| trade.price).min(),
+        }
+    }
+
+    /// Submitted, filled and cancelled volume for `broker_id` since the book
+    /// was created; all zero for a broker the book has not seen
+    pub fn broker_activity(&self, broker_id: Uuid) -> BrokerActivity {
+        let activity = self.broker_activity.get(&broker_id).copied().unwrap_or_default();
+        BrokerActivity {
+            cancel_to_fill_ratio: activity.cancelled_volume.checked_div(activity.filled_volume),
+            ..activity
+        }
+    }
+
+    fn note_cancelled(&mut self, broker_id: Uuid, quantity: Decimal) {
+        let activity = self.broker_activity.entry(broker_id).or_default();
+        activity.cancelled_volume = activity.cancelled_volume.saturating_add(quantity);
+    }
+
+    fn record_trade(&mut self, trade: &Trade) {
+        self.note_activity(Activity::TradeVolume, trade.quantity);
+        for broker_id in [trade.buyer_broker_id, trade.seller_broker_id] {
+            let activity = self.broker_activity.entry(broker_id).or_default();
+            activity.filled_volume = activity.filled_volume.saturating_add(trade.quantity);
+        }
+        self.append_to_tape(trade.clone());
+        self.emit(BookEvent::Trade(trade.clone()));
+    }
+
+    fn append_to_tape(&mut self, trade: Trade) {
+        self.tape_positions.insert(trade.id, self.tape.len());
+        self.order_trade_ids.entry(trade.buyer_order_id).or_default().push(trade.id);
+        self.order_trade_ids.entry(trade.seller_order_id).or_default().push(trade.id);
+        if let Some(previous) = self.last_trade_price {
+            self.last_tick_direction = Some(match trade.price.cmp(&previous) {
+                std::cmp::Ordering::Greater => TickDirection::Up,
+                std::cmp::Ordering::Less => TickDirection::Down,
+                std::cmp::Ordering::Equal => TickDirection::Zero,
+            });
+        }
+        self.last_trade_price = Some(trade.price);
+        self.tape.push(trade);
+    }
+
+    pub fn last_price(&self) -> Option<Decimal> {
+        self.last_trade_price
+    }
+
+    /// Direction of the latest trade against the previous one; `None` until two trades print
+    pub fn last_tick_direction(&self) -> Option<TickDirection> {
+        self.last_tick_direction
+    }
+
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.displayed_levels(&OrderSide::BUY).next().map(|(&price, _)| price)
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.displayed_levels(&OrderSide::SELL).next().map(|(&price, _)| price)
+    }
+
+    // Market data only shows levels with displayed interest
+    fn displayed_levels(&self, side: &OrderSide) -> impl Iterator<Item = (&Decimal, &Vec<Order>)> {
+        self.levels_best_first(side, Venue::Lit)
+            .filter(|(_, orders)| orders.iter().any(|o| !o.hidden))
+    }
+
+    pub fn bbo(&self) -> (Option<Decimal>, Option<Decimal>) {
+        (self.best_bid(), self.best_ask())
+    }
+
+    /// Whether an order on `side` at `price` would trade on arrival against
+    /// the lit book. `None` is a market order, which is marketable whenever
+    /// the other side has a tradable level.
+    pub fn is_marketable(&self, side: OrderSide, price: Option<Decimal>) -> bool {
+        let Some(best) = self.best_tradable_price(&side.opposite(), Venue::Lit) else {
+            return false;
+        };
+        price.is_none_or(|price| !self.is_price_frozen(price) && self.prices_match(side, price, best))
+    }
+
+    /// The best displayed prices once `broker_id`'s own orders are taken out,
+    /// i.e. the external market that broker faces
+    pub fn bbo_excluding_broker(&self, broker_id: Uuid) -> (Option<Decimal>, Option<Decimal>) {
+        let best_external = |side: OrderSide| {
+            self.levels_best_first(&side, Venue::Lit)
+                .find(|(_, orders)| orders.iter().any(|o| !o.hidden && o.broker_id != broker_id))
+                .map(|(&price, _)| price)
+        };
+
+        (best_external(OrderSide::BUY), best_external(OrderSide::SELL))
+    }
+
+    /// `broker_id`'s best price on `side` of the lit book and the total they
+    /// have resting there, hidden orders included, as they are the broker's own
+    pub fn best_resting_for_broker(&self, broker_id: Uuid, side: OrderSide) -> Option<(Decimal, Decimal)> {
+        self.levels_best_first(&side, Venue::Lit).find_map(|(&price, orders)| {
+            let mut own = orders.iter().filter(|o| o.broker_id == broker_id).peekable();
+            own.peek()?;
+            Some((price, saturating_total(own.map(|o| o.remaining_quantity))))
+        })
+    }
+
+    pub fn get_order(&self, order_id: Uuid) -> Option<&Order> {
+        self.orders.get(&order_id)
+    }
+
+    /// The top `levels` displayed price levels on each side of the main book
+    pub fn depth(&self, levels: usize) -> BookDepth {
+        let display_scale = self.display_scale();
+        let to_level = |(&price, orders): (&Decimal, &Vec<Order>)| DepthLevel {
+            // Only ever padded, so the value is unchanged; just the decimals shown
+            price: match display_scale {
+                Some(scale) if scale > price.scale() => {
+                    let mut shown = price;
+                    shown.rescale(scale);
+                    shown
+                }
+                _ => price,
+            },
+            quantity: saturating_total(orders.iter().filter(|o| !o.hidden).map(|o| o.remaining_quantity)),
+            order_count: orders.iter().filter(|o| !o.hidden).count(),
+        };
+
+        BookDepth {
+            bids: self.displayed_levels(&OrderSide::BUY).take(levels).map(to_level).collect(),
+            asks: self.displayed_levels(&OrderSide::SELL).take(levels).map(to_level).collect(),
+        }
+    }
+
+    /// Displayed volume on `side` grouped into price buckets of `bucket_size`,
+    /// best bucket first. Bids round down and asks round up to their bucket so
+    /// a bucket never shows a better price than the orders inside it. A
+    /// non-positive `bucket_size` yields no buckets.
+    pub fn bucketed_depth(&self, side: OrderSide, bucket_size: Decimal) -> Vec<(Decimal, Decimal)> {
+        if bucket_size <= Decimal::ZERO {
+            return Vec::new();
+        }
+
+        let depth = self.depth(usize::MAX);
+        let levels = match side {
+            OrderSide::BUY => depth.bids,
+            OrderSide::SELL => depth.asks,
+        };
+
+        let mut buckets: Vec<(Decimal, Decimal)> = Vec::new();
+        for level in levels {
+            let offset = level.price % bucket_size;
+            let bucket = match side {
+                OrderSide::BUY => level.price - offset,
+                OrderSide::SELL if offset.is_zero() => level.price,
+                OrderSide::SELL => level.price - offset + bucket_size,
+            };
+
+            match buckets.last_mut() {
+                Some((price, quantity)) if *price == bucket => *quantity = quantity.saturating_add(level.quantity),
+                _ => buckets.push((bucket, level.quantity)),
+            }
+        }
+
+        buckets
+    }
+
+    pub fn snapshot(&self) -> BookSnapshot {
+        let depth = self.depth(usize::MAX);
+        let to_map = |levels: Vec<DepthLevel>| levels.into_iter().map(|l| (l.price, l.quantity)).collect();
+
+        BookSnapshot {
+            bids: to_map(depth.bids),
+            asks: to_map(depth.asks),
+        }
+    }
+
+    /// Level changes since `previous`, bids then asks, each in ascending price order
+    pub fn diff(&self, previous: &BookSnapshot) -> Vec<LevelChange> {
+        fn diff_side(
+            side: OrderSide,
+            previous: &BTreeMap<Decimal, Decimal>,
+            current: &BTreeMap<Decimal, Decimal>,
+        ) -> Vec<LevelChange> {
+            let prices: BTreeMap<Decimal, ()> = previous.keys().chain(current.keys()).map(|&p| (p, ())).collect();
+
+            prices
+                .into_keys()
+                .filter_map(|price| {
+                    let before = previous.get(&price).copied().unwrap_or(Decimal::ZERO);
+                    let after = current.get(&price).copied().unwrap_or(Decimal::ZERO);
+                    (before != after).then(|| LevelChange { side: side.clone(), price, new_quantity: after })
+                })
+                .collect()
+        }
+
+        let current = self.snapshot();
+        let mut changes = diff_side(OrderSide::BUY, &previous.bids, &current.bids);
+        changes.extend(diff_side(OrderSide::SELL, &previous.asks, &current.asks));
+        changes
+    }
+
+    // Liquidity the order could take right now across the venues it matches in
+    fn immediately_fillable(&self, order: &Order) -> Decimal {
+        if order.price.is_some_and(|price| self.is_price_frozen(price)) {
+            return Decimal::ZERO;
+        }
+
+        let resting_side = order.side.opposite();
+        let quantities = self.matching_venues(order).into_iter().flat_map(|venue| {
+            self.levels_best_first(&resting_side, venue)
+                .take_while(|(&price, _)| {
+                    order.price.is_none_or(|limit| self.prices_match(order.side.clone(), limit, price))
+                })
+                .filter(|(price, _)| !self.is_price_frozen(**price))
+                .flat_map(|(_, orders)| orders.iter())
+                .filter(move |resting| !self.passes_over(order, resting, venue))
+                .map(|resting| resting.remaining_quantity)
+        });
+
+        saturating_total(quantities)
+    }
+
+    // A dry run of `match_at_venue` across the order's venues: how much would
+    // trade right now once the strategy, fill increment, lot rounding and
+    // skipped orders have had their say. The book itself is left untouched.
+    fn simulated_fill(&self, order: &Order) -> Decimal {
+        if order.price.is_some_and(|price| self.is_price_frozen(price)) {
+            return Decimal::ZERO;
+        }
+
+        let strategy = self.matching_strategy();
+        let rules = self.allocation_rules();
+        let mut order = order.clone();
+        let wanted = order.remaining_quantity;
+        let resting_side = order.side.opposite();
+
+        for venue in self.matching_venues(&order) {
+            let levels = self
+                .levels_best_first(&resting_side, venue)
+                .filter(|(price, _)| !self.is_price_frozen(**price))
+                .take_while(|(&price, _)| {
+                    order.price.is_none_or(|limit| self.prices_match(order.side.clone(), limit, price))
+                });
+
+            'levels: for (_, level) in levels {
+                let mut level = level.clone();
+                while level.iter().any(|resting| !self.passes_over(&order, resting, venue)) {
+                    let allocations = self.allocate(strategy.as_ref(), &order, &level, &rules, venue);
+                    let sizes = self.fill_sizes(order.remaining_quantity, &level, &allocations);
+                    let filled = saturating_total(sizes.iter().copied());
+                    // Matching gives up on the whole venue here, so the dry run does too
+                    if filled.is_zero() {
+                        break 'levels;
+                    }
+
+                    order.remaining_quantity -= filled;
+                    if order.remaining_quantity.is_zero() {
+                        return wanted;
+                    }
+                    for (resting, size) in level.iter_mut().zip(sizes) {
+                        resting.remaining_quantity -= size;
+                    }
+                    level.retain(|resting| !resting.remaining_quantity.is_zero());
+                }
+            }
+        }
+
+        wanted - order.remaining_quantity
+    }
+
+    /// Quantity-weighted average price of everything resting on `side`.
+    /// `None` when the side is empty or its notional does not fit in a `Decimal`.
+    pub fn weighted_avg_price(&self, side: OrderSide) -> Option<Decimal> {
+        let (notional, quantity) = self.levels(&side, Venue::Lit).values().flatten().try_fold(
+            (Decimal::ZERO, Decimal::ZERO),
+            |(notional, quantity), order| {
+                let price = order.price.expect("Resting orders must have a price");
+                Some((
+                    notional.checked_add(price.checked_mul(order.remaining_quantity)?)?,
+                    quantity.checked_add(order.remaining_quantity)?,
+                ))
+            },
+        )?;
+
+        if quantity.is_zero() {
+            None
+        } else {
+            Some(notional / quantity)
+        }
+    }
+
+    /// Number of brokers with at least one order resting on `side`
+    pub fn distinct_brokers(&self, side: OrderSide) -> usize {
+        self.broker_volumes(&side).len()
+    }
+
+    /// The largest single broker's fraction of the volume resting on `side`
+    pub fn largest_broker_share(&self, side: OrderSide) -> Option<Decimal> {
+        let volumes = self.broker_volumes(&side);
+        let total = saturating_total(volumes.values().copied());
+        if total.is_zero() {
+            return None;
+        }
+        volumes.into_values().max().map(|largest| largest / total)
+    }
+
+    // Resting volume per broker across every venue on one side
+    fn broker_volumes(&self, side: &OrderSide) -> HashMap<Uuid, Decimal> {
+        let mut volumes: HashMap<Uuid, Decimal> = HashMap::new();
+        for order in self.live_orders().filter(|o| o.side == *side) {
+            let volume = volumes.entry(order.broker_id).or_default();
+            *volume = volume.saturating_add(order.remaining_quantity);
+        }
+        volumes
+    }
+
+    /// How much an incoming limit order on `side` at `limit_price` could execute
+    /// immediately against the main book, honouring frozen levels
+    pub fn fillable_quantity(&self, side: OrderSide, limit_price: Decimal) -> Decimal {
+        if self.is_price_frozen(limit_price) {
+            return Decimal::ZERO;
+        }
+
+        saturating_total(
+            self.levels_best_first(&side.opposite(), Venue::Lit)
+                .take_while(|(&price, _)| self.prices_match(side.clone(), limit_price, price))
+                .filter(|(price, _)| !self.is_price_frozen(**price))
+                .flat_map(|(_, orders)| orders.iter())
+                .map(|order| order.remaining_quantity),
+        )
+    }
+
+    /// The worst price an incoming order on `side` reaches while sweeping the
+    /// main book for `quantity`, i.e. the limit price that guarantees a full fill.
+    /// `None` if the book is not deep enough.
+    pub fn clearing_price_for_quantity(&self, side: OrderSide, quantity: Decimal) -> Option<Decimal> {
+        let mut remaining = quantity;
+
+        for (&price, orders) in self.levels_best_first(&side.opposite(), Venue::Lit) {
+            if self.is_price_frozen(price) {
+                continue;
+            }
+            remaining -= saturating_total(orders.iter().map(|o| o.remaining_quantity)).min(remaining);
+            if remaining.is_zero() {
+                return Some(price);
+            }
+        }
+
+        None
+    }
+
+    /// Walks the lit book as a market order on `side` for `quantity` would,
+    /// skipping frozen levels, without changing anything
+    pub fn estimate_market_fill(&self, side: OrderSide, quantity: Decimal) -> MarketFillEstimate {
+        let mut estimate = MarketFillEstimate::default();
+
+        for (&price, orders) in self.levels_best_first(&side.opposite(), Venue::Lit) {
+            let remaining = quantity - estimate.filled_quantity;
+            if remaining <= Decimal::ZERO {
+                break;
+            }
+            if self.is_price_frozen(price) {
+                continue;
+            }
+
+            let taken = saturating_total(orders.iter().map(|o| o.remaining_quantity)).min(remaining);
+            estimate.filled_quantity += taken;
+            estimate.notional += price * taken;
+            estimate.worst_price = Some(price);
+        }
+
+        if !estimate.filled_quantity.is_zero() {
+            estimate.avg_price = Some(estimate.notional / estimate.filled_quantity);
+        }
+        estimate
+    }
+
+    /// The full depth as it would stand after a market order on `side` for
+    /// `quantity` swept the opposite side, with the real book left untouched.
+    /// Levels the sweep clears drop out and the last one it reaches shows only
+    /// what would be left; resting orders are taken in queue order.
+    pub fn depth_after_sweep(&self, side: OrderSide, quantity: Decimal) -> BookDepth {
+        let mut depth = self.depth(usize::MAX);
+        let levels = match side {
+            OrderSide::BUY => &mut depth.asks,
+            OrderSide::SELL => &mut depth.bids,
+        };
+
+        let mut remaining = quantity;
+        for (&price, orders) in self.levels_best_first(&side.opposite(), Venue::Lit) {
+            if remaining <= Decimal::ZERO {
+                break;
+            }
+            if self.is_price_frozen(price) {
+                continue;
+            }
+
+            let (mut quantity_left, mut count_left) = (Decimal::ZERO, 0);
+            for order in orders {
+                let taken = order.remaining_quantity.min(remaining);
+                remaining -= taken;
+                if !order.hidden && taken < order.remaining_quantity {
+                    quantity_left += order.remaining_quantity - taken;
+                    count_left += 1;
+                }
+            }
+
+            if let Some(pos) = levels.iter().position(|level| level.price == price) {
+                if count_left == 0 {
+                    levels.remove(pos);
+                } else {
+                    levels[pos].quantity = quantity_left;
+                    levels[pos].order_count = count_left;
+                }
+            }
+        }
+        depth
+    }
+
+    /// The all-in cost of a market order on `side` for `quantity`: the
+    /// estimated fill's notional with the schedule's taker fee on top
+    pub fn estimate_total_cost(&self, side: OrderSide, quantity: Decimal, fee_schedule: &FeeSchedule) -> TotalCostEstimate {
+        let notional = self.estimate_market_fill(side.clone(), quantity).notional;
+        let fees = fee_schedule.taker_fee(notional);
+        let all_in = match side {
+            OrderSide::BUY => notional + fees,
+            OrderSide::SELL => notional - fees,
+        };
+
+        TotalCostEstimate { notional, fees, all_in }
+    }
+
+    pub fn spread(&self) -> Option<Decimal> {
+        match (self.best_bid(), self.best_ask()) {
+            (Some(bid), Some(ask)) => Some(ask - bid),
+            _ => None,
+        }
+    }
+
+    /// Level counts, volumes, spread and order age of the lit book in one read
+    pub fn diagnostics(&self) -> BookDiagnostics {
+        let volume = |levels: &BTreeMap<Decimal, Vec<Order>>| {
+            saturating_total(levels.values().flatten().map(|o| o.remaining_quantity))
+        };
+        let resting = self.bids.values().chain(self.asks.values()).flatten();
+        let (best_bid, best_ask) = self.bbo();
+
+        BookDiagnostics {
+            bid_levels: self.bids.len(),
+            ask_levels: self.asks.len(),
+            total_orders: resting.clone().count(),
+            total_bid_volume: volume(&self.bids),
+            total_ask_volume: volume(&self.asks),
+            spread: self.spread(),
+            is_crossed: matches!((best_bid, best_ask), (Some(bid), Some(ask)) if bid >= ask),
+            oldest_order_age: resting.map(|o| o.created_at).min().map(|created_at| self.clock.now() - created_at),
+        }
+    }
+
+    /// The spread as basis points of the mid, comparable across instruments
+    /// trading at different prices. `None` if either side is empty or the mid is zero.
+    pub fn spread_bps(&self) -> Option<Decimal> {
+        let mid = self.mid_price().filter(|mid| !mid.is_zero())?;
+        Some(self.spread()? / mid * Decimal::from(10_000))
+    }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        match (self.best_bid(), self.best_ask()) {
+            (Some(bid), Some(ask)) => Some((bid + ask) / Decimal::TWO),
+            _ => None,
+        }
+    }
+
+    /// A fair price that leans toward the side under pressure. With `B` and
+    /// `A` the displayed bid and ask quantity over the top `levels` of each
+    /// side, it is
+    ///
+    /// `best_bid + (best_ask - best_bid) * B / (A + B)`
+    ///
+    /// the microprice with depth beyond the touch counted in. A heavier bid
+    /// side pushes it up toward the ask, since that is where the price is
+    /// likelier to move; equal depth gives the mid. `None` unless both sides
+    /// have displayed liquidity in those levels.
+    pub fn pressure_adjusted_price(&self, levels: usize) -> Option<Decimal> {
+        let depth = self.depth(levels);
+        let (best_bid, best_ask) = (depth.bids.first()?.price, depth.asks.first()?.price);
+        let bid_volume = saturating_total(depth.bids.iter().map(|level| level.quantity));
+        let ask_volume = saturating_total(depth.asks.iter().map(|level| level.quantity));
+
+        let bid_share = bid_volume.checked_div(bid_volume.checked_add(ask_volume)?)?;
+        Some(best_bid + (best_ask - best_bid) * bid_share)
+    }
+
+    /// Single reference price for valuing positions: the mid when the book is
+    /// two-sided, otherwise the last trade price
+    pub fn mark_price(&self) -> Option<Decimal> {
+        self.mid_price().or(self.last_trade_price)
+    }
+
+    /// Holds `order` off the book until `check_stops` sees the configured
+    /// reference price reach `stop_price` (at or above for buys, at or below for sells).
+    /// A stop without a price becomes a market order when triggered.
+    pub fn add_stop_order(&mut self, order: Order, stop_price: Decimal) -> Result<(), OrderError> {
+        self.validate_order(&order)?;
+        if self.stop_orders.iter().any(|stop| stop.order.id == order.id) {
+            return Err(OrderError::DuplicateOrderId(order.id));
+        }
+        self.stop_orders.push(StopOrder { order, stop_price });
+        Ok(())
+    }
+
+    pub fn stop_orders(&self) -> &[StopOrder] {
+        &self.stop_orders
+    }
+
+    /// Submits every stop whose trigger condition holds against the current
+    /// reference price, repeating until no further stops fire since triggered
+    /// stops can move the reference themselves. Each stop is submitted on its
+    /// own, so one the book refuses (say because an earlier stop halted it)
+    /// neither loses the others nor the trades already made. A refused stop
+    /// has fired and is reported in `rejected` rather than kept.
+    pub fn check_stops(&mut self) -> StopCheckOutcome {
+        let mut outcome = StopCheckOutcome::default();
+
+        loop {
+            let (triggered, waiting): (Vec<StopOrder>, Vec<StopOrder>) = std::mem::take(&mut self.stop_orders)
+                .into_iter()
+                .partition(|stop| self.stop_triggered(stop));
+            self.stop_orders = waiting;
+
+            if triggered.is_empty() {
+                break;
+            }
+
+            for stop in triggered {
+                let order_id = stop.order.id;
+                match self.add_order(stop.order) {
+                    Ok(trades) => outcome.trades.extend(trades),
+                    Err(error) => outcome.rejected.push((order_id, error)),
+                }
+            }
+        }
+
+        outcome
+    }
+
+    fn stop_triggered(&self, stop: &StopOrder) -> bool {
+        let reference = match self.config.stop_trigger {
+            StopTrigger::LastTrade => self.last_trade_price,
+            StopTrigger::Mid => self.mid_price(),
+            StopTrigger::BidAsk => match stop.order.side {
+                OrderSide::BUY => self.best_ask(),
+                OrderSide::SELL => self.best_bid(),
+            },
+        };
+
+        match (reference, &stop.order.side) {
+            (Some(price), OrderSide::BUY) => price >= stop.stop_price,
+            (Some(price), OrderSide::SELL) => price <= stop.stop_price,
+            (None, _) => false,
+        }
+    }
+
+    fn validate_order(&self, order: &Order) -> Result<(), OrderError> {
+        if let Some(reason) = self.halt {
+            return Err(OrderError::Halted(reason));
+        }
+
+        if self.status != InstrumentStatus::ACTIVE {
+            return Err(OrderError::InstrumentNotActive(self.status.clone()));
+        }
+
+        if order.instrument_id != self.instrument_id {
+            return Err(OrderError::InstrumentMismatch {
+                expected: self.instrument_id,
+                found: order.instrument_id,
+            });
+        }
+
+        if self.orders.contains_key(&order.id) {
+            return Err(OrderError::DuplicateOrderId(order.id));
+        }
+
+        self.validate_terms(order)
+    }
+
+    // The checks on an order's own price and size, shared with amendments
+    fn validate_terms(&self, order: &Order) -> Result<(), OrderError> {
+        if let (Some(price), Some(tick_size)) = (order.price, self.tick_size) {
+            if !(price % tick_size).is_zero() {
+                return Err(OrderError::InvalidTickSize { price, tick_size });
+            }
+        }
+
+        if self.config.odd_lot_policy == OddLotPolicy::Reject && self.is_odd_lot(order) {
+            return Err(OrderError::OddLot {
+                quantity: order.original_quantity,
+                lot_size: self.lot_size,
+            });
+        }
+
+        self.check_price_collar(order)
+    }
+
+    // Only the aggressive direction is collared: a buy far above the reference or
+    // a sell far below it. Without a mid or last trade there is nothing to compare to.
+    fn check_price_collar(&self, order: &Order) -> Result<(), OrderError> {
+        let (Some(bps), Some(price), Some(reference)) = (self.config.price_collar_bps, order.price, self.mark_price())
+        else {
+            return Ok(());
+        };
+
+        let band = reference * bps / Decimal::from(10_000);
+        let out_of_band = match order.side {
+            OrderSide::BUY => price > reference + band,
+            OrderSide::SELL => price < reference - band,
+        };
+
+        if out_of_band {
+            Err(OrderError::PriceOutOfBand { price, reference })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn is_odd_lot(&self, order: &Order) -> bool {
+        order.original_quantity < self.lot_size
+    }
+
+    fn is_internalized(&self, broker_id: Uuid) -> bool {
+        match &self.config.internalization {
+            InternalizationPolicy::Disabled => false,
+            InternalizationPolicy::Brokers(brokers) => brokers.contains(&broker_id),
+        }
+    }
+
+    fn public_venue(&self, order: &Order) -> Venue {
+        if self.config.odd_lot_policy == OddLotPolicy::SeparateBook && self.is_odd_lot(order) {
+            Venue::OddLot
+        } else {
+            Venue::Lit
+        }
+    }
+
+    fn resting_venue(&self, order: &Order) -> Venue {
+        if self.is_internalized(order.broker_id) {
+            Venue::Internal(order.broker_id)
+        } else {
+            self.public_venue(order)
+        }
+    }
+
+    // Where `order` actually rests. Usually `resting_venue`, but an amendment
+    // can leave an order sitting in a venue it would no longer be sent to,
+    // such as a lit order reduced below a lot under `OddLotPolicy::SeparateBook`.
+    fn located_venue(&self, order: &Order) -> Venue {
+        let expected = self.resting_venue(order);
+        let Some(price) = order.price else {
+            return expected;
+        };
+        [expected, Venue::Lit, Venue::OddLot, Venue::Internal(order.broker_id)]
+            .into_iter()
+            .find(|&venue| {
+                self.levels(&order.side, venue)
+                    .get(&price)
+                    .is_some_and(|level| level.iter().any(|o| o.id == order.id))
+            })
+            .unwrap_or(expected)
+    }
+
+    // Internalized flow tries the broker's own book before the public one
+    fn matching_venues(&self, order: &Order) -> Vec<Venue> {
+        if self.is_internalized(order.broker_id) {
+            vec![Venue::Internal(order.broker_id), self.public_venue(order)]
+        } else {
+            vec![self.public_venue(order)]
+        }
+    }
+
+    fn levels(&self, side: &OrderSide, venue: Venue) -> &BTreeMap<Decimal, Vec<Order>> {
+        match (side, venue) {
+            (OrderSide::BUY, Venue::Lit) => &self.bids,
+            (OrderSide::SELL, Venue::Lit) => &self.asks,
+            (OrderSide::BUY, Venue::OddLot) => &self.odd_bids,
+            (OrderSide::SELL, Venue::OddLot) => &self.odd_asks,
+            (OrderSide::BUY, Venue::Internal(broker_id)) => {
+                self.internal_bids.get(&broker_id).unwrap_or(&EMPTY_LEVELS)
+            }
+            (OrderSide::SELL, Venue::Internal(broker_id)) => {
+                self.internal_asks.get(&broker_id).unwrap_or(&EMPTY_LEVELS)
+            }
+        }
+    }
+
+    fn levels_mut(&mut self, side: &OrderSide, venue: Venue) -> &mut BTreeMap<Decimal, Vec<Order>> {
+        match (side, venue) {
+            (OrderSide::BUY, Venue::Lit) => &mut self.bids,
+            (OrderSide::SELL, Venue::Lit) => &mut self.asks,
+            (OrderSide::BUY, Venue::OddLot) => &mut self.odd_bids,
+            (OrderSide::SELL, Venue::OddLot) => &mut self.odd_asks,
+            (OrderSide::BUY, Venue::Internal(broker_id)) => self.internal_bids.entry(broker_id).or_default(),
+            (OrderSide::SELL, Venue::Internal(broker_id)) => self.internal_asks.entry(broker_id).or_default(),
+        }
+    }
+
+    fn process_limit_order(&mut self, mut order: Order, trades: &mut Vec<Trade>) -> Result<(), OrderError> {
+        self.pre_trade_checks(&order)?;
+
+        for venue in self.matching_venues(&order) {
+            if order.remaining_quantity == Decimal::ZERO {
+                break;
+            }
+            self.match_at_venue(&mut order, venue, trades)?;
+        }
+
+        if order.remaining_quantity > Decimal::ZERO {
+            match order.time_in_force {
+                TimeInForce::IOC | TimeInForce::FOK => {
+                    order.status = OrderStatus::CANCELLED;
+                    order.cancel_reason = Some(CancelReason::ImmediateOrCancel);
+                    self.note_cancelled(order.broker_id, order.remaining_quantity);
+                }
+                TimeInForce::GTC | TimeInForce::DAY => {
+                    let venue = self.resting_venue(&order);
+                    self.rest_order(order.clone(), venue);
+                }
+            }
+        }
+
+        self.orders.insert(order.id, order);
+        Ok(())
+    }
+
+    // The checks a limit order has to pass against the current book, on top
+    // of `validate_order`, before it may match or rest
+    fn pre_trade_checks(&self, order: &Order) -> Result<(), OrderError> {
+        self.check_short_sale(order)?;
+        self.check_level_capacity(order)?;
+        self.check_quote_distance(order)
+    }
+
+    // Only orders that would rest without trading count against the cap, so
+    // marketable orders always reach the level they cross
+    fn check_level_capacity(&self, order: &Order) -> Result<(), OrderError> {
+        let Some(max_orders) = self.config.max_orders_per_level else {
+            return Ok(());
+        };
+        if matches!(order.time_in_force, TimeInForce::IOC | TimeInForce::FOK) {
+            return Ok(());
+        }
+
+        let price = order.price.expect("Limit orders must have a price");
+        let resting = self
+            .levels(&order.side, self.resting_venue(order))
+            .get(&price)
+            .map_or(0, |orders| orders.len());
+
+        if resting >= max_orders && self.immediately_fillable(order).is_zero() {
+            Err(OrderError::LevelFull { price, max_orders })
+        } else {
+            Ok(())
+        }
+    }
+
+    // Displayed orders take precedence over hidden ones at the same price, so a
+    // displayed order queues ahead of any hidden orders already resting there.
+    // Within each group replayed orders queue by their sequence number, ahead
+    // of orders that arrived live.
+    fn rest_order(&mut self, order: Order, venue: Venue) {
+        let price = order.price.expect("Limit orders must have a price");
+        let priority = |o: &Order| (o.hidden, self.replay_sequences.get(&o.id).copied().unwrap_or(u64::MAX));
+
+        let key = priority(&order);
+        let pos = self
+            .levels(&order.side, venue)
+            .get(&price)
+            .map_or(0, |level| level.iter().position(|o| priority(o) > key).unwrap_or(level.len()));
+        self.levels_mut(&order.side, venue).entry(price).or_default().insert(pos, order);
+    }
+
+    /// `add_order` for rebuilding a book from history: if the order rests, it
+    /// queues at its level by `seq` rather than arrival, ahead of any order
+    /// with a later sequence number and of every order added live. The
+    /// order's own `created_at` is kept as its timestamp.
+    pub fn add_order_with_priority(&mut self, order: Order, seq: u64) -> Result<Vec<Trade>, OrderError> {
+        let order_id = order.id;
+        if self.orders.contains_key(&order_id) {
+            return Err(OrderError::DuplicateOrderId(order_id));
+        }
+
+        self.replay_sequences.insert(order_id, seq);
+        self.add_order(order).inspect_err(|_| {
+            self.replay_sequences.remove(&order_id);
+        })
+    }
+
+    /// Matching priority at a price level as `(order id, displayed)` pairs
+    pub fn queue_order_with_visibility(&self, side: OrderSide, price: Decimal) -> Vec<(Uuid, bool)> {
+        self.levels(&side, Venue::Lit)
+            .get(&price)
+            .map(|orders| orders.iter().map(|o| (o.id, !o.hidden)).collect())
+            .unwrap_or_default()
+    }
+
+    fn process_market_order(&mut self, mut order: Order, trades: &mut Vec<Trade>) -> Result<(), OrderError> {
+        self.check_short_sale(&order)?;
+
+        for venue in self.matching_venues(&order) {
+            if order.remaining_quantity == Decimal::ZERO {
+                break;
+            }
+            self.match_at_venue(&mut order, venue, trades)?;
+        }
+
+        if order.remaining_quantity > Decimal::ZERO {
+            let last_fill = trades.last().map(|trade| trade.price);
+            match (order.time_in_force.clone(), self.config.market_remainder, last_fill) {
+                (TimeInForce::IOC | TimeInForce::FOK, _, _) => {
+                    order.status = OrderStatus::CANCELLED;
+                    order.cancel_reason = Some(CancelReason::ImmediateOrCancel);
+                    self.note_cancelled(order.broker_id, order.remaining_quantity);
+                }
+                (_, MarketRemainderPolicy::RestAtLastTrade, Some(price)) => {
+                    let mut remainder = order.clone();
+                    remainder.order_type = OrderType::LIMIT;
+                    remainder.price = Some(price);
+                    // The fills already stand, so a refusal only rejects the remainder
+                    let allowed = self
+                        .check_price_collar(&remainder)
+                        .and_then(|()| self.check_level_capacity(&remainder));
+                    match allowed {
+                        Ok(()) => {
+                            order = remainder;
+                            let venue = self.resting_venue(&order);
+                            self.rest_order(order.clone(), venue);
+                        }
+                        Err(_) => order.status = OrderStatus::REJECTED,
+                    }
+                }
+                _ => order.status = OrderStatus::REJECTED,
+            }
+        }
+
+        self.orders.insert(order.id, order);
+        Ok(())
+    }
+
+    // Takes liquidity from one venue until the order fills, runs out of
+    // counterparties or, for limit orders, reaches its price. Each level is taken
+    // out of the book and filled in place as the strategy allocates it.
+    fn match_at_venue(&mut self, order: &mut Order, venue: Venue, trades: &mut Vec<Trade>) -> Result<(), OrderError> {
+        let side = order.side.clone();
+        let resting_side = side.opposite();
+        let strategy = self.matching_strategy();
+        let rules = self.allocation_rules();
+
+        while order.remaining_quantity > Decimal::ZERO {
+            if order.price.is_some_and(|price| self.is_price_frozen(price)) {
+                break;
+            }
+
+            let Some(level_price) = self.best_matchable_price(order, venue) else {
+                break;
+            };
+            if !order.price.is_none_or(|price| self.prices_match(side.clone(), price, level_price)) {
+                break;
+            }
+
+            let mut level = self.levels_mut(&resting_side, venue).remove(&level_price).unwrap_or_default();
+            // The resting side of the match is locked too while its level is out of the book
+            let matched: Vec<Uuid> = level.iter().map(|resting| resting.id).collect();
+            matched.iter().for_each(|&id| self.lock_order(id));
+            let allocations = self.allocate(strategy.as_ref(), order, &level, &rules, venue);
+            let filled = self.fill_level(order, &mut level, &allocations, level_price, trades);
+            matched.iter().for_each(|&id| self.unlock_order(id));
+            if !level.is_empty() {
+                self.levels_mut(&resting_side, venue).insert(level_price, level);
+            }
+
+            // Stop once the strategy can no longer allocate anything at the best level
+            if !filled? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Like `best_tradable_price`, but also passes over levels holding nothing
+    // but orders this order may not trade with
+    fn best_matchable_price(&self, order: &Order, venue: Venue) -> Option<Decimal> {
+        self.levels_best_first(&order.side.opposite(), venue)
+            .filter(|(price, _)| !self.frozen_prices.contains(price))
+            .find(|(_, level)| level.iter().any(|resting| !self.passes_over(order, resting, venue)))
+            .map(|(&price, _)| price)
+    }
+
+    // Resting orders the incoming order may not trade with: its own broker's
+    // under `SelfTradePolicy::Skip`, and hidden ones if it opted out of them
+    fn passes_over(&self, order: &Order, resting: &Order, venue: Venue) -> bool {
+        let self_match = self.config.self_trade == SelfTradePolicy::Skip
+            && !matches!(venue, Venue::Internal(_))
+            && resting.broker_id == order.broker_id;
+        self_match || (resting.hidden && !order.interact_with_hidden)
+    }
+
+    // Skipped orders get a zero allocation and the strategy divides the
+    // incoming quantity among the rest of the level
+    fn allocate(
+        &self,
+        strategy: &dyn MatchingStrategy,
+        order: &Order,
+        level: &[Order],
+        rules: &AllocationRules,
+        venue: Venue,
+    ) -> Vec<Decimal> {
+        if !level.iter().any(|resting| self.passes_over(order, resting, venue)) {
+            return strategy.allocate(order.remaining_quantity, level, rules);
+        }
+
+        let eligible: Vec<Order> = level
+            .iter()
+            .filter(|resting| !self.passes_over(order, resting, venue))
+            .cloned()
+            .collect();
+        let mut allocations = strategy.allocate(order.remaining_quantity, &eligible, rules).into_iter();
+
+        level
+            .iter()
+            .map(|resting| {
+                if self.passes_over(order, resting, venue) {
+                    Decimal::ZERO
+                } else {
+                    allocations.next().unwrap_or_default()
+                }
+            })
+            .collect()
+    }
+
+    fn allocation_rules(&self) -> AllocationRules {
+        AllocationRules {
+            lot_size: self.lot_size,
+            min_fill_increment: self.config.min_fill_increment,
+        }
+    }
+
+    fn matching_strategy(&self) -> Arc<dyn MatchingStrategy> {
+        match &self.matching_strategy {
+            Some(strategy) => strategy.clone(),
+            None => self.config.matching_algo.strategy(),
+        }
+    }
+
+    // Executes `allocations` against the level in queue order and drops resting
+    // orders that complete. Returns false if nothing was allocated.
+    fn fill_level(
+        &mut self,
+        order: &mut Order,
+        level: &mut Vec<Order>,
+        allocations: &[Decimal],
+        price: Decimal,
+        trades: &mut Vec<Trade>,
+    ) -> Result<bool, OrderError> {
+        let sizes = self.fill_sizes(order.remaining_quantity, level, allocations);
+
+        // Every trade is made before any order is filled, so a level that
+        // cannot trade in full is left exactly as it was
+        let level_trades = level
+            .iter()
+            .zip(&sizes)
+            .filter(|(_, &trade_quantity)| trade_quantity > Decimal::ZERO)
+            .map(|(resting, &trade_quantity)| self.create_trade(order, resting, price, trade_quantity))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (resting, trade_quantity) in level.iter_mut().zip(sizes) {
+            if trade_quantity <= Decimal::ZERO {
+                continue;
+            }
+
+            apply_fill(order, trade_quantity, price);
+            apply_fill(resting, trade_quantity, price);
+            // The level copy is the one matching works on; the map entry is
+            // always replaced from it rather than patched separately
+            if let Some(stored) = self.orders.get_mut(&resting.id) {
+                *stored = resting.clone();
+            }
+        }
+
+        let any_filled = !level_trades.is_empty();
+        trades.extend(level_trades);
+        level.retain(|resting| !resting.remaining_quantity.is_zero());
+        Ok(any_filled)
+    }
+
+    // What actually trades against each order of `level` for `allocations`.
+    // Never trusts a strategy to stay within either order's size.
+    fn fill_sizes(&self, incoming: Decimal, level: &[Order], allocations: &[Decimal]) -> Vec<Decimal> {
+        let round_down = self.config.partial_lot == PartialLotPolicy::RoundDown && self.lot_size > Decimal::ZERO;
+        let mut left = incoming;
+        let mut sizes = Vec::with_capacity(allocations.len());
+
+        for (resting, &allocation) in level.iter().zip(allocations) {
+            let quantity = allocation.min(resting.remaining_quantity).min(left).max(Decimal::ZERO);
+            if !round_down {
+                left -= quantity;
+                sizes.push(quantity);
+                continue;
+            }
+
+            let rounded = quantity - quantity % self.lot_size;
+            // An earlier order that cannot take a whole lot keeps later ones
+            // from trading past it
+            if rounded.is_zero() && !quantity.is_zero() {
+                break;
+            }
+            left -= rounded;
+            sizes.push(rounded);
+        }
+
+        sizes
+    }
+
+    pub fn cancel_order(&mut self, order_id: Uuid) -> Result<Order, OrderError> {
+        if self.locked_orders.contains(&order_id) {
+            return Err(OrderError::OrderLocked(order_id));
+        }
+        let order = self
+            .orders
+            .get(&order_id)
+            .filter(|o| o.status == OrderStatus::PENDING || o.status == OrderStatus::PARTIAL)
+            .ok_or(OrderError::NotCancellable(order_id))?;
+
+        if let Some(min_resting_time) = self.config.min_resting_time {
+            let rested = self.clock.now() - order.created_at;
+            if rested < min_resting_time {
+                return Err(OrderError::TooSoonToCancel {
+                    order_id,
+                    remaining: min_resting_time - rested,
+                });
+            }
+        }
+
+        let cancelled = self
+            .remove_resting(order_id, CancelReason::UserRequested)
+            .ok_or(OrderError::NotCancellable(order_id))?;
+        self.note_activity(Activity::Cancel, Decimal::ONE);
+        Ok(cancelled)
+    }
+
+    // Marks an order as being matched until `unlock_order`: the incoming order
+    // for the whole of its processing, and each resting order while its level
+    // is being filled. Nothing can reach a `&mut OrderBook` mid-sweep today,
+    // but the guard keeps cancels and amends honest for any caller that
+    // interleaves with matching.
+    fn lock_order(&mut self, order_id: Uuid) {
+        self.locked_orders.insert(order_id);
+    }
+
+    fn unlock_order(&mut self, order_id: Uuid) {
+        self.locked_orders.remove(&order_id);
+    }
+
+    /// Cancels the order first in line on `side` of the lit book: best price,
+    /// then earliest. `None` if the side is empty or the order may not be cancelled yet.
+    pub fn cancel_best(&mut self, side: OrderSide) -> Option<Order> {
+        let order_id = self.levels_best_first(&side, Venue::Lit).next()?.1.first()?.id;
+        self.cancel_order(order_id).ok()
+    }
+
+    /// Changes a resting order's price and/or open quantity. Only a pure size
+    /// reduction keeps the order's place in the queue; any price change, up or
+    /// down, or a size increase sends it to the back of its new level, and a
+    /// new price that crosses the book trades on the spot. An amendment the
+    /// book refuses leaves the order exactly as and where it was.
+    ///
+    /// With a balance provider set, whatever the amendment frees is released
+    /// and any extra it needs is locked first.
+    pub fn amend_order(
+        &mut self,
+        order_id: Uuid,
+        new_price: Option<Decimal>,
+        new_quantity: Option<Decimal>,
+    ) -> Result<Vec<Trade>, OrderError> {
+        if let Some(reason) = self.halt {
+            return Err(OrderError::Halted(reason));
+        }
+        if self.locked_orders.contains(&order_id) {
+            return Err(OrderError::OrderLocked(order_id));
+        }
+        let order = self
+            .orders
+            .get(&order_id)
+            .filter(|o| o.status == OrderStatus::PENDING || o.status == OrderStatus::PARTIAL)
+            .filter(|o| o.order_type != OrderType::TAS)
+            .ok_or(OrderError::NotAmendable(order_id))?
+            .clone();
+
+        let quantity = new_quantity.unwrap_or(order.remaining_quantity);
+        if quantity <= Decimal::ZERO {
+            return Err(OrderError::InvalidQuantity(quantity));
+        }
+
+        let mut amended = order.clone();
+        amended.price = new_price.or(order.price);
+        amended.original_quantity = order.original_quantity - order.remaining_quantity + quantity;
+        amended.remaining_quantity = quantity;
+        amended.updated_at = self.clock.now();
+        self.validate_terms(&amended)?;
+
+        let keeps_priority = amended.price == order.price && quantity <= order.remaining_quantity;
+        if keeps_priority {
+            let venue = self.located_venue(&order);
+            let price = order.price.expect("Resting orders must have a price");
+            if let Some(resting) = self
+                .levels_mut(&order.side, venue)
+                .get_mut(&price)
+                .and_then(|level| level.iter_mut().find(|o| o.id == order_id))
+            {
+                *resting = amended.clone();
+            }
+            self.orders.insert(order_id, amended);
+            self.release_balance(&order, order.remaining_quantity - quantity);
+            self.note_cancelled(order.broker_id, order.remaining_quantity - quantity);
+            return Ok(Vec::new());
+        }
+
+        // Checked with the order lifted out of its level, so it does not count
+        // against itself, and put back in the same spot if anything refuses it
+        let lifted = self.lift_from_levels(&[order_id]);
+        let backing_change = self.backing(&amended, quantity) - self.backing(&order, order.remaining_quantity);
+        let checked = self.pre_trade_checks(&amended).and_then(|()| {
+            if backing_change > Decimal::ZERO && !self.lock_backing(&order, backing_change) {
+                return Err(OrderError::BalanceUnavailable(order_id));
+            }
+            Ok(())
+        });
+        if let Err(error) = checked {
+            self.put_back(lifted);
+            return Err(error);
+        }
+
+        // Re-entered behind everything at its new level, and free to match
+        let sequence = self.replay_sequences.remove(&order_id);
+        self.pre_trade_spread = self.spread();
+        let reentered = if self.phase == TradingPhase::Continuous {
+            self.execute(amended)
+        } else {
+            self.queue_for_auction(amended).map(|()| Vec::new())
+        };
+        let trades = match reentered {
+            Ok(trades) => trades,
+            Err(error) => {
+                if let Some(sequence) = sequence {
+                    self.replay_sequences.insert(order_id, sequence);
+                }
+                if backing_change > Decimal::ZERO {
+                    self.release_backing(&order, backing_change);
+                }
+                self.put_back(lifted);
+                self.orders.insert(order_id, order);
+                return Err(error);
+            }
+        };
+
+        if backing_change < Decimal::ZERO {
+            self.release_backing(&order, -backing_change);
+        }
+        // The same order carries on, so only the size it gained or gave up
+        // shows in the broker's activity
+        if quantity > order.remaining_quantity {
+            let activity = self.broker_activity.entry(order.broker_id).or_default();
+            activity.submitted = activity.submitted.saturating_add(quantity - order.remaining_quantity);
+        } else {
+            self.note_cancelled(order.broker_id, order.remaining_quantity - quantity);
+        }
+        self.sample_spread();
+        self.check_spread();
+
+        Ok(trades)
+    }
+
+    // What backs `quantity` of `order`: its notional for a buy, the shares
+    // themselves for a sell. A buy with no price locked nothing the book can
+    // work out.
+    fn backing(&self, order: &Order, quantity: Decimal) -> Decimal {
+        match order.side {
+            OrderSide::BUY => order.price.map_or(Decimal::ZERO, |price| self.notional(price, quantity).amount),
+            OrderSide::SELL => quantity,
+        }
+    }
+
+    // Hands back what backed `quantity` of `order`
+    fn release_balance(&self, order: &Order, quantity: Decimal) {
+        if quantity > Decimal::ZERO {
+            self.release_backing(order, self.backing(order, quantity));
+        }
+    }
+
+    // Hands back `amount` of the cash or shares behind `order`
+    fn release_backing(&self, order: &Order, amount: Decimal) {
+        let Some(balances) = &self.balances else {
+            return;
+        };
+        if amount <= Decimal::ZERO {
+            return;
+        }
+
+        match order.side {
+            OrderSide::BUY => balances.release_cash(order.broker_id, &self.currency, amount),
+            OrderSide::SELL => balances.release_inventory(order.broker_id, self.instrument_id, amount),
+        }
+    }
+
+    // Locks a further `amount` of cash or shares behind `order`. Without a
+    // provider there is nothing to lock, so it always succeeds.
+    fn lock_backing(&self, order: &Order, amount: Decimal) -> bool {
+        let Some(balances) = &self.balances else {
+            return true;
+        };
+
+        match order.side {
+            OrderSide::BUY => balances.lock_cash(order.broker_id, &self.currency, amount),
+            OrderSide::SELL => balances.lock_inventory(order.broker_id, self.instrument_id, amount),
+        }
+    }
+
+    /// Copies of every order resting in the lit book, laid out like a price
+    /// ladder: bids from the lowest level up to the best, then asks from the
+    /// best up, so the two most aggressive levels meet in the middle. Within
+    /// a level orders keep their queue order.
+    pub fn all_resting_orders(&self) -> Vec<Order> {
+        // Both maps are keyed in ascending price, which is exactly the ladder
+        self.levels(&OrderSide::BUY, Venue::Lit)
+            .values()
+            .chain(self.levels(&OrderSide::SELL, Venue::Lit).values())
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Quantity that trades before `order_id` can: everything resting at better
+    /// prices on its side plus whatever queues ahead of it at its own price.
+    /// `None` unless the order is resting in the lit book.
+    pub fn quantity_ahead(&self, order_id: Uuid) -> Option<Decimal> {
+        let order = self.orders.get(&order_id)?;
+        let price = order.price?;
+        let level = self.levels(&order.side, Venue::Lit).get(&price)?;
+        let position = level.iter().position(|o| o.id == order_id)?;
+
+        let better_levels = self
+            .levels_best_first(&order.side, Venue::Lit)
+            .take_while(|&(&level_price, _)| level_price != price)
+            .flat_map(|(_, orders)| orders);
+        Some(saturating_total(better_levels.chain(&level[..position]).map(|o| o.remaining_quantity)))
+    }
+
+    /// How much of resting order `order_id` an opposing market order for
+    /// `incoming_opposite_quantity` would fill right now, taking price-time
+    /// priority: only what is left once the `quantity_ahead` of it is used
+    /// up, capped at the order's remaining quantity. Zero for an order that is
+    /// not resting in the lit book.
+    pub fn implied_fill_for(&self, order_id: Uuid, incoming_opposite_quantity: Decimal) -> Decimal {
+        let (Some(order), Some(ahead)) = (self.orders.get(&order_id), self.quantity_ahead(order_id)) else {
+            return Decimal::ZERO;
+        };
+        (incoming_opposite_quantity - ahead).clamp(Decimal::ZERO, order.remaining_quantity)
+    }
+
+    /// How long until `order_id` fills if trading carries on at the recent
+    /// rate: its queue ahead plus its own remaining quantity, divided by the
+    /// volume traded at or through its price per unit time over
+    /// `BookConfig::fill_rate_window` up to `now`. `None` if the order is not
+    /// resting or no such volume has traded.
+    pub fn estimated_fill_time(&self, order_id: Uuid, now: DateTime<Utc>) -> Option<Duration> {
+        let order = self.orders.get(&order_id)?;
+        let price = order.price?;
+        let to_trade = self.quantity_ahead(order_id)? + order.remaining_quantity;
+
+        let start = match self.config.fill_rate_window {
+            Some(window) => now - window,
+            None => self.tape.first()?.execution_time,
+        };
+        let elapsed = Decimal::from((now - start).num_microseconds().filter(|&micros| micros > 0)?);
+        let volume = saturating_total(
+            self.tape
+                .iter()
+                .filter(|trade| trade.execution_time >= start && trade.execution_time <= now)
+                .filter(|trade| match order.side {
+                    OrderSide::BUY => trade.price <= price,
+                    OrderSide::SELL => trade.price >= price,
+                })
+                .map(|trade| trade.quantity),
+        );
+        if volume.is_zero() {
+            return None;
+        }
+
+        let micros = (to_trade * elapsed / volume).round();
+        Some(Duration::microseconds(i64::try_from(micros).ok()?))
+    }
+
+    /// Each resting order on `side` of the lit book mapped to its place in its
+    /// level's queue, 0 being next to trade
+    pub fn queue_positions(&self, side: OrderSide) -> HashMap<Uuid, usize> {
+        self.levels(&side, Venue::Lit)
+            .values()
+            .flat_map(|level| level.iter().enumerate().map(|(position, order)| (order.id, position)))
+            .collect()
+    }
+
+    // Takes a live order off its level and marks it cancelled, bypassing the
+    // checks that apply to participant-initiated cancels
+    fn remove_resting(&mut self, order_id: Uuid, reason: CancelReason) -> Option<Order> {
+        if let Some(order) = self.orders.get(&order_id) {
+            if order.status != OrderStatus::PENDING && order.status != OrderStatus::PARTIAL {
+                return None;
+            }
+
+            if order.order_type == OrderType::TAS {
+                self.tas_queue.retain(|id| *id != order_id);
+                let order = self.orders.get_mut(&order_id)?;
+                order.status = OrderStatus::CANCELLED;
+                order.cancel_reason = Some(reason);
+                let order = order.clone();
+                self.release_balance(&order, order.remaining_quantity);
+                self.note_cancelled(order.broker_id, order.remaining_quantity);
+                return Some(order);
+            }
+
+            let price = order.price.expect("Order should have a price");
+            let side = order.side.clone();
+            let venue = self.located_venue(order);
+
+            let book = self.levels_mut(&side, venue);
+
+            if let Some(orders) = book.get_mut(&price) {
+                if let Some(pos) = orders.iter().position(|o| o.id == order_id) {
+                    let cancelled_order = orders.remove(pos);
+                    if orders.is_empty() {
+                        book.remove(&price);
+                    }
+
+                    let mut updated_order = cancelled_order.clone();
+                    updated_order.status = OrderStatus::CANCELLED;
+                    updated_order.cancel_reason = Some(reason);
+                    self.orders.insert(order_id, updated_order.clone());
+                    self.release_balance(&updated_order, updated_order.remaining_quantity);
+                    self.note_cancelled(updated_order.broker_id, updated_order.remaining_quantity);
+                    self.sample_spread();
+                    self.check_spread();
+
+                    return Some(updated_order);
+                }
+            }
+        }
+        None
+    }
+
+    /// Previews `cancel_order` for a live order; `None` if it could not be cancelled
+    pub fn simulate_cancel(&self, order_id: Uuid) -> Option<CancelPreview> {
+        let order = self.orders.get(&order_id)?;
+        if order.status != OrderStatus::PENDING && order.status != OrderStatus::PARTIAL {
+            return None;
+        }
+
+        let price = order.price?;
+        let level = self.levels(&order.side, self.located_venue(order)).get(&price)?;
+        let resting = level.iter().find(|o| o.id == order_id)?;
+        let level_quantity = saturating_total(level.iter().map(|o| o.remaining_quantity));
+
+        Some(CancelPreview {
+            side: order.side.clone(),
+            price,
+            quantity_freed: resting.remaining_quantity,
+            level_quantity_after: level_quantity - resting.remaining_quantity,
+            level_removed: level.len() == 1,
+        })
+    }
+
+    /// Cancels every live order matching `predicate`, oldest first. Orders that
+    /// refuse the cancel, such as ones still inside `min_resting_time`, are skipped.
+    pub fn cancel_where(&mut self, predicate: impl Fn(&Order) -> bool) -> Vec<Order> {
+        let ids: Vec<Uuid> = self.live_orders().filter(|o| predicate(o)).map(|o| o.id).collect();
+        ids.into_iter().filter_map(|id| self.cancel_order(id).ok()).collect()
+    }
+
+    /// Live orders carrying `tag`, oldest first
+    pub fn orders_by_tag(&self, tag: &str) -> Vec<&Order> {
+        self.live_orders().filter(|o| o.tags.iter().any(|t| t == tag)).collect()
+    }
+
+    /// How long each resting order has been on the book at `now`, oldest first
+    pub fn order_ages(&self, now: DateTime<Utc>) -> Vec<Duration> {
+        self.live_orders().map(|o| now - o.created_at).collect()
+    }
+
+    pub fn oldest_resting(&self) -> Option<&Order> {
+        self.live_orders().next()
+    }
+
+    // Resting orders in time priority across every venue
+    fn live_orders(&self) -> impl Iterator<Item = &Order> {
+        let mut live: Vec<&Order> = self
+            .orders
+            .values()
+            .filter(|o| o.status == OrderStatus::PENDING || o.status == OrderStatus::PARTIAL)
+            .collect();
+        live.sort_by_key(|o| (o.created_at, o.id));
+        live.into_iter()
+    }
+
+    /// Removes orders that should never be resting, such as terminal orders or
+    /// ones with no remaining quantity left over from an inconsistent load
+    pub fn repair(&mut self) -> RepairReport {
+        let mut report = RepairReport::default();
+
+        for levels in self.all_levels_mut() {
+            for orders in levels.values_mut() {
+                orders.retain(|order| {
+                    if !matches!(order.status, OrderStatus::PENDING | OrderStatus::PARTIAL) {
+                        report.terminal.push(order.id);
+                        false
+                    } else if order.remaining_quantity <= Decimal::ZERO {
+                        report.exhausted.push(order.id);
+                        false
+                    } else {
+                        true
+                    }
+                });
+            }
+            levels.retain(|_, orders| !orders.is_empty());
+        }
+
+        // An exhausted order is complete even if storage never said so
+        for id in &report.exhausted {
+            if let Some(order) = self.orders.get_mut(id) {
+                order.status = OrderStatus::FILLED;
+            }
+        }
+
+        report
+    }
+
+    /// Runs every structural check on the book and reports what is wrong
+    /// instead of panicking. An empty result means the book is consistent.
+    /// A crossed book only counts in continuous trading, since collecting
+    /// crossing orders for the next uncross is what the auction phases are for.
+    pub fn health_check(&self) -> Vec<BookInvariantViolation> {
+        let mut violations = Vec::new();
+
+        let touch = (self.bids.keys().next_back(), self.asks.keys().next());
+        if let (TradingPhase::Continuous, (Some(&bid), Some(&ask))) = (self.phase, touch) {
+            if bid >= ask {
+                violations.push(BookInvariantViolation::Crossed { bid, ask });
+            }
+        }
+
+        let mut resting: HashSet<Uuid> = self.tas_queue.iter().copied().collect();
+        for (side, levels) in self.all_levels() {
+            for (&price, orders) in levels {
+                if orders.is_empty() {
+                    violations.push(BookInvariantViolation::EmptyLevel { side: side.clone(), price });
+                }
+                for order in orders {
+                    resting.insert(order.id);
+                    match self.orders.get(&order.id) {
+                        None => violations.push(BookInvariantViolation::Untracked(order.id)),
+                        Some(stored) if stored != order => {
+                            violations.push(BookInvariantViolation::CopiesDiffer(order.id))
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+        }
+
+        let mut orders: Vec<&Order> = self.orders.values().collect();
+        orders.sort_by_key(|o| (o.created_at, o.id));
+        for order in orders {
+            let live = matches!(order.status, OrderStatus::PENDING | OrderStatus::PARTIAL);
+            if order.remaining_quantity < Decimal::ZERO {
+                violations.push(BookInvariantViolation::NegativeRemaining(order.id));
+            }
+            if (live && order.remaining_quantity <= Decimal::ZERO)
+                || (order.status == OrderStatus::FILLED && !order.remaining_quantity.is_zero())
+            {
+                violations.push(BookInvariantViolation::StatusMismatch {
+                    order_id: order.id,
+                    status: order.status.clone(),
+                    remaining: order.remaining_quantity,
+                });
+            }
+            if live && !resting.contains(&order.id) {
+                violations.push(BookInvariantViolation::NotResting(order.id));
+            }
+        }
+
+        violations
+    }
+
+    fn all_levels(&self) -> impl Iterator<Item = (OrderSide, &BTreeMap<Decimal, Vec<Order>>)> {
+        [
+            (OrderSide::BUY, &self.bids),
+            (OrderSide::SELL, &self.asks),
+            (OrderSide::BUY, &self.odd_bids),
+            (OrderSide::SELL, &self.odd_asks),
+        ]
+        .into_iter()
+        .chain(self.internal_bids.values().map(|levels| (OrderSide::BUY, levels)))
+        .chain(self.internal_asks.values().map(|levels| (OrderSide::SELL, levels)))
+    }
+
+    fn all_levels_mut(&mut self) -> impl Iterator<Item = &mut BTreeMap<Decimal, Vec<Order>>> {
+        [&mut self.bids, &mut self.asks, &mut self.odd_bids, &mut self.odd_asks]
+            .into_iter()
+            .chain(self.internal_bids.values_mut())
+            .chain(self.internal_asks.values_mut())
+    }
+
+    /// Drops any empty price levels left behind after heavy churn and releases
+    /// spare capacity. Returns the number of levels removed.
+    pub fn compact(&mut self) -> usize {
+        fn compact_levels(levels: &mut BTreeMap<Decimal, Vec<Order>>) -> usize {
+            let before = levels.len();
+            levels.retain(|_, orders| !orders.is_empty());
+            for orders in levels.values_mut() {
+                orders.shrink_to_fit();
+            }
+            before - levels.len()
+        }
+
+        let mut removed = compact_levels(&mut self.bids)
+            + compact_levels(&mut self.asks)
+            + compact_levels(&mut self.odd_bids)
+            + compact_levels(&mut self.odd_asks);
+        for internal in [&mut self.internal_bids, &mut self.internal_asks] {
+            for levels in internal.values_mut() {
+                removed += compact_levels(levels);
+            }
+            internal.retain(|_, levels| !levels.is_empty());
+            internal.shrink_to_fit();
+        }
+
+        self.orders.shrink_to_fit();
+        self.tape_positions.shrink_to_fit();
+        self.order_trade_ids.shrink_to_fit();
+        removed
+    }
+
+    fn levels_best_first(&self, side: &OrderSide, venue: Venue) -> impl Iterator<Item = (&Decimal, &Vec<Order>)> {
+        PriceOrdering::for_side(side).best_first(self.levels(side, venue))
+    }
+
+    // Frozen levels are skipped so matching continues at the next tradable price
+    fn best_tradable_price(&self, side: &OrderSide, venue: Venue) -> Option<Decimal> {
+        self.levels_best_first(side, venue)
+            .map(|(&price, _)| price)
+            .find(|price| !self.frozen_prices.contains(price))
+    }
+
+    fn create_trade(&self, order: &Order, matched_order: &Order, price: Decimal, quantity: Decimal) -> Result<Trade, OrderError> {
+        self.check_trade_price(order, matched_order, price)?;
+
+        Ok(Trade {
+            id: Uuid::new_v4(),
+            instrument_id: self.instrument_id,
+            buyer_order_id: if order.side == OrderSide::BUY {
+                order.id
+            } else {
+                matched_order.id
+            },
+            seller_order_id: if order.side == OrderSide::SELL {
+                order.id
+            } else {
+                matched_order.id
+            },
+            buyer_broker_id: if order.side == OrderSide::BUY {
+                order.broker_id
+            } else {
+                matched_order.broker_id
+            },
+            seller_broker_id: if order.side == OrderSide::SELL {
+                order.broker_id
+            } else {
+                matched_order.broker_id
+            },
+            price,
+            quantity,
+            execution_time: self.clock.now(),
+            status: TradeStatus::PENDING_SETTLEMENT,
+            settlement_time: None,
+            spread_at_execution: self.pre_trade_spread,
+            memo: order.memo.clone(),
+        })
+    }
+
+    // Guards against a print outside either side's limit: buy limit >= trade price >= sell limit
+    fn check_trade_price(&self, order: &Order, matched_order: &Order, price: Decimal) -> Result<(), OrderError> {
+        let (buy_order, sell_order) = match order.side {
+            OrderSide::BUY => (order, matched_order),
+            OrderSide::SELL => (matched_order, order),
+        };
+
+        // Trade-at-settlement orders take the settlement price whatever their limit
+        let buyer_accepts = buy_order.order_type == OrderType::TAS || buy_order.price.is_none_or(|limit| limit >= price);
+        let seller_accepts = sell_order.order_type == OrderType::TAS || sell_order.price.is_none_or(|limit| price >= limit);
+
+        if buyer_accepts && seller_accepts {
+            Ok(())
+        } else {
+            Err(OrderError::InternalInconsistency(format!(
+                "trade price {} outside limits of buy order {} ({:?}) and sell order {} ({:?})",
+                price, buy_order.id, buy_order.price, sell_order.id, sell_order.price
+            )))
+        }
+    }
+
+    fn prices_match(&self, side: OrderSide, order_price: Decimal, book_price: Decimal) -> bool {
+        match side {
+            OrderSide::BUY => order_price >= book_price,
+            OrderSide::SELL => order_price <= book_price,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::clock::MockClock;
+    use rust_decimal_macros::dec;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+
+    // Helper function to print a visual separator
+    fn print_separator(test_name: &str) {
+        println!("\n{}", "=".repeat(50));
+        println!("🧪 TEST: {}", test_name);
+        println!("{}\n", "=".repeat(50));
+    }
+
+    // Helper function to visualize an order
+    fn visualize_order(prefix: &str, order: &Order) {
+        println!("📝 {} Order:", prefix);
+        println!("   ├─ ID: {}", order.id);
+        println!("   ├─ Type: {:?}", order.order_type);
+        println!("   ├─ Side: {:?}", order.side);
+        println!("   ├─ Price: {:?}", order.price);
+        println!("   ├─ Quantity: {}", order.original_quantity);
+        println!("   └─ Status: {:?}", order.status);
+    }
+
+    // Helper function to visualize a trade
+    fn visualize_trade(trade: &Trade) {
+        println!("\n🤝 Trade Executed:");
+        println!("   ├─ Price: {}", trade.price);
+        println!("   ├─ Quantity: {}", trade.quantity);
+        println!("   ├─ Buyer Order: {}", trade.buyer_order_id);
+        println!("   └─ Seller Order: {}", trade.seller_order_id);
+    }
+
+    // Helper function to visualize the order book state
+    fn visualize_order_book_state(order_book: &OrderBook) {
+        println!("\n📚 Order Book State:");
+        println!("   ├─ Bids: {:?}", order_book.bids);
+        println!("   ├─ Asks: {:?}", order_book.asks);
+        println!("   └─ Orders: {:?}", order_book.orders);
+    }
+
+    fn create_test_order(
+        id: &str,
+        broker_id: &str,
+        side: OrderSide,
+        order_type: OrderType,
+        price: Option<Decimal>,
+        quantity: Decimal,
+    ) -> Order {
+        Order {
+            id: Uuid::from_str(id).unwrap(),
+            broker_id: Uuid::from_str(broker_id).unwrap(),
+            instrument_id: Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap(),
+            order_type,
+            ..Order::for_test(side, price, quantity)
+        }
+    }
+
+    #[test]
+    fn test_limit_order_full_match() {
+        print_separator("Limit Order Full Match");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        // Create a sell limit order
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(10.0),
+        );
+
+        println!("➡️ Adding Sell Order to Book:");
+        visualize_order("SELL", &sell_order);
+
+        let trades = order_book.add_order(sell_order).unwrap();
+        println!("\n📚 Order Book State: No trades, order added to book");
+
+        // Create a matching buy order
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(10.0),
+        );
+
+        println!("\n➡️ Adding Buy Order:");
+        visualize_order("BUY", &buy_order);
+
+        let trades = order_book.add_order(buy_order).unwrap();
+
+        println!("\n💫 Result:");
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+        println!("📚 Order Book State: Empty (all orders matched)");
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, dec!(10.0));
+        assert_eq!(trades[0].price, dec!(100.0));
+    }
+
+    #[test]
+    fn test_limit_order_partial_match() {
+        print_separator("Limit Order Partial Match");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(10.0),
+        );
+
+        println!("➡️ Adding Sell Order to Book (Quantity: 10):");
+        visualize_order("SELL", &sell_order);
+
+        order_book.add_order(sell_order).unwrap();
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+
+        println!("\n➡️ Adding Buy Order (Quantity: 5):");
+        visualize_order("BUY", &buy_order);
+
+        let trades = order_book.add_order(buy_order).unwrap();
+
+        println!("\n💫 Result:");
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        if let Some(order) = order_book.orders.get(&Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap()) {
+            println!("\n📚 Order Book State:");
+            println!("   Remaining Sell Order:");
+            println!("   ├─ Quantity: {}", order.remaining_quantity);
+            println!("   └─ Status: {:?}", order.status);
+        };
+    }
+
+    #[test]
+    fn test_market_order_full_execution() {
+        print_separator("Market Order Full Execution");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(10.0),
+        );
+
+        println!("➡️ Adding Limit Sell Order to Book:");
+        visualize_order("SELL", &sell_order);
+
+        order_book.add_order(sell_order).unwrap();
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(10.0),
+        );
+
+        println!("\n➡️ Adding Market Buy Order:");
+        visualize_order("BUY", &buy_order);
+
+        let trades = order_book.add_order(buy_order).unwrap();
+
+        println!("\n💫 Result:");
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+        println!("\n📚 Order Book State: Empty (all orders matched)");
+    }
+
+    #[test]
+    fn test_multiple_price_levels() {
+        print_separator("Multiple Price Levels");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let sell_order_1 = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+
+        println!("➡️ Adding First Sell Order (Price: 100):");
+        visualize_order("SELL", &sell_order_1);
+
+        let sell_order_2 = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(5.0),
+        );
+
+        println!("\n➡️ Adding Second Sell Order (Price: 101):");
+        visualize_order("SELL", &sell_order_2);
+
+        order_book.add_order(sell_order_1).unwrap();
+        order_book.add_order(sell_order_2).unwrap();
+
+        println!("\n📚 Order Book State: Two sell orders at different prices");
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(10.0),
+        );
+
+        println!("\n➡️ Adding Buy Order (Quantity: 10, Price: 101):");
+        visualize_order("BUY", &buy_order);
+
+        let trades = order_book.add_order(buy_order).unwrap();
+
+        println!("\n💫 Results:");
+        for (i, trade) in trades.iter().enumerate() {
+            println!("\n🤝 Trade {} Executed:", i + 1);
+            visualize_trade(trade);
+        }
+        println!("\n📚 Order Book State: Empty (all orders matched)");
+    }
+
+    #[test]
+    fn test_cancel_pending_order() {
+        print_separator("Cancel Pending Order");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        // Create a sell limit order
+        let sell_order = Order {
+            instrument_id,
+            ..Order::for_test(OrderSide::SELL, Some(dec!(100.0)), dec!(10.0))
+        };
+
+        let order_id = sell_order.id;
+        visualize_order("SELL", &sell_order);
+
+        order_book.add_order(sell_order).unwrap();
+        visualize_order_book_state(&order_book);
+
+        // Cancel the order
+        let cancelled_order = order_book.cancel_order(order_id).unwrap();
+        visualize_order("CANCELLED", &cancelled_order);
+
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(cancelled_order.status, OrderStatus::CANCELLED);
+        assert_eq!(cancelled_order.remaining_quantity, dec!(10.0));
+        assert!(order_book.asks.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_partially_filled_order() {
+        print_separator("Cancel Partially Filled Order");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        // Create a sell limit order
+        let sell_order = Order {
+            instrument_id,
+            ..Order::for_test(OrderSide::SELL, Some(dec!(100.0)), dec!(10.0))
+        };
+
+        let sell_order_id = sell_order.id;
+        visualize_order("SELL", &sell_order);
+
+        order_book.add_order(sell_order).unwrap();
+
+        // Create a partial matching buy order
+        let buy_order = Order {
+            instrument_id,
+            ..Order::for_test(OrderSide::BUY, Some(dec!(100.0)), dec!(6.0))
+        };
+
+        visualize_order("BUY", &buy_order);
+
+        // This should partially fill the sell order
+        order_book.add_order(buy_order).unwrap();
+        visualize_order_book_state(&order_book);
+
+        // Cancel the partially filled sell order
+        let cancelled_order = order_book.cancel_order(sell_order_id).unwrap();
+        visualize_order("CANCELLED", &cancelled_order);
+
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(cancelled_order.status, OrderStatus::CANCELLED);
+        assert_eq!(cancelled_order.remaining_quantity, dec!(4.0));
+        assert!(order_book.asks.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_filled_order() {
+        print_separator("Cancel Filled Order");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        // Create a sell limit order
+        let sell_order = Order {
+            instrument_id,
+            status: OrderStatus::FILLED,
+            remaining_quantity: dec!(0.0),
+            ..Order::for_test(OrderSide::SELL, Some(dec!(100.0)), dec!(10.0))
+        };
+
+        let order_id = sell_order.id;
+        visualize_order("SELL", &sell_order);
+
+        order_book.add_order(sell_order).unwrap();
+        visualize_order_book_state(&order_book);
+
+        // Attempt to cancel the filled order
+        let cancelled_order = order_book.cancel_order(order_id);
+
+        if cancelled_order.is_err() {
+            println!("\n➡️ Attempt to Cancel Filled Order:");
+            println!("   └─ No order was cancelled (expected behavior).");
+        }
+
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(cancelled_order.unwrap_err(), OrderError::NotCancellable(order_id));
+    }
+
+    #[test]
+    fn test_frozen_best_ask_is_skipped() {
+        print_separator("Frozen Best Ask Is Skipped");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let frozen_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        let next_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(5.0),
+        );
+
+        order_book.add_order(frozen_sell).unwrap();
+        order_book.add_order(next_sell).unwrap();
+        order_book.freeze_price(dec!(100.0));
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(5.0),
+        );
+
+        println!("➡️ Adding Buy Order with best ask frozen:");
+        visualize_order("BUY", &buy_order);
+
+        let trades = order_book.add_order(buy_order).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(101.0));
+        assert_eq!(
+            trades[0].seller_order_id,
+            Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap()
+        );
+        assert_eq!(order_book.asks.get(&dec!(100.0)).map(Vec::len), Some(1));
+        assert!(!order_book.asks.contains_key(&dec!(101.0)));
+    }
+
+    #[test]
+    fn test_order_at_frozen_price_rests_until_unfrozen() {
+        print_separator("Order At Frozen Price Rests Until Unfrozen");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+        order_book.freeze_price(dec!(100.0));
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        visualize_order_book_state(&order_book);
+
+        assert!(trades.is_empty());
+        assert!(order_book.bids.contains_key(&dec!(100.0)));
+        assert!(order_book.asks.contains_key(&dec!(100.0)));
+
+        order_book.unfreeze_price(dec!(100.0));
+        assert!(!order_book.is_price_frozen(dec!(100.0)));
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        let trades = order_book.add_order(sell_order).unwrap();
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(100.0));
+    }
+
+    #[test]
+    fn test_odd_lot_rejected() {
+        print_separator("Odd Lot Rejected");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.set_lot_size(dec!(100));
+        order_book.config_mut().odd_lot_policy = OddLotPolicy::Reject;
+
+        let odd_lot = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(50),
+        );
+        visualize_order("BUY", &odd_lot);
+
+        let error = order_book.add_order(odd_lot).unwrap_err();
+
+        assert_eq!(error, OrderError::OddLot { quantity: dec!(50), lot_size: dec!(100) });
+        assert!(order_book.bids.is_empty());
+        assert!(order_book.orders.is_empty());
+    }
+
+    #[test]
+    fn test_odd_lot_allowed_into_main_book() {
+        print_separator("Odd Lot Allowed Into Main Book");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.set_lot_size(dec!(100));
+
+        let round_lot = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(100),
+        );
+        let odd_lot = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(30),
+        );
+
+        order_book.add_order(round_lot).unwrap();
+        let trades = order_book.add_order(odd_lot).unwrap();
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, dec!(30));
+        assert_eq!(order_book.asks[&dec!(100.0)][0].remaining_quantity, dec!(70));
+    }
+
+    #[test]
+    fn test_odd_lots_match_only_each_other_in_separate_book() {
+        print_separator("Odd Lots Match Only Each Other");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.set_lot_size(dec!(100));
+        order_book.config_mut().odd_lot_policy = OddLotPolicy::SeparateBook;
+
+        let round_lot_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(100),
+        );
+        let odd_lot_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(40),
+        );
+        order_book.add_order(round_lot_sell).unwrap();
+        order_book.add_order(odd_lot_sell).unwrap();
+
+        assert!(order_book.asks.contains_key(&dec!(100.0)));
+        assert!(order_book.odd_asks.contains_key(&dec!(101.0)));
+
+        // The odd-lot buy ignores the cheaper round-lot ask and trades at 101
+        let odd_lot_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(40),
+        );
+        let trades = order_book.add_order(odd_lot_buy).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(101.0));
+        assert_eq!(
+            trades[0].seller_order_id,
+            Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap()
+        );
+        assert!(order_book.odd_asks.is_empty());
+        assert_eq!(order_book.asks[&dec!(100.0)][0].remaining_quantity, dec!(100));
+
+        // A round-lot buy never reaches into the odd-lot book
+        let odd_lot_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(10),
+        );
+        order_book.add_order(odd_lot_sell).unwrap();
+
+        let round_lot_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000009",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(100),
+        );
+        let trades = order_book.add_order(round_lot_buy).unwrap();
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(100.0));
+        assert_eq!(order_book.odd_asks[&dec!(99.0)][0].remaining_quantity, dec!(10));
+    }
+
+    #[test]
+    fn test_trade_index_tracks_each_match() {
+        print_separator("Trade Index Tracks Each Match");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        let sell_id = Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap();
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(10.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+        assert!(order_book.trade_ids_for_order(sell_id).is_empty());
+
+        let first_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(4.0),
+        );
+        let first_trades = order_book.add_order(first_buy).unwrap();
+        assert_eq!(order_book.trade_ids_for_order(sell_id), &[first_trades[0].id]);
+
+        let second_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(6.0),
+        );
+        let second_trades = order_book.add_order(second_buy).unwrap();
+        for trade in order_book.trades() {
+            visualize_trade(trade);
+        }
+
+        assert_eq!(
+            order_book.trade_ids_for_order(sell_id),
+            &[first_trades[0].id, second_trades[0].id]
+        );
+
+        // The index must agree with a full scan of the tape for every order
+        for order_id in order_book.orders.keys() {
+            let scanned: Vec<Uuid> = order_book
+                .trades()
+                .iter()
+                .filter(|t| t.buyer_order_id == *order_id || t.seller_order_id == *order_id)
+                .map(|t| t.id)
+                .collect();
+            let indexed: Vec<Uuid> = order_book
+                .trades_for_order(*order_id)
+                .iter()
+                .map(|t| t.id)
+                .collect();
+
+            assert_eq!(order_book.trade_ids_for_order(*order_id), scanned.as_slice());
+            assert_eq!(indexed, scanned);
+        }
+    }
+
+    #[test]
+    fn test_last_trade_stop_fires_on_print() {
+        print_separator("Last Trade Stop Fires On Print");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, price) in [
+            ("00000000-0000-0000-0000-000000000002", dec!(100.0)),
+            ("00000000-0000-0000-0000-000000000006", dec!(102.0)),
+        ] {
+            let sell_order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(5.0),
+            );
+            order_book.add_order(sell_order).unwrap();
+        }
+
+        let stop_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000009",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(5.0),
+        );
+        order_book.add_stop_order(stop_buy, dec!(100.0)).unwrap();
+
+        // No trade has printed yet
+        assert!(order_book.check_stops().trades.is_empty());
+        assert_eq!(order_book.stop_orders().len(), 1);
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(buy_order).unwrap();
+
+        let trades = order_book.check_stops().trades;
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(102.0));
+        assert!(order_book.stop_orders().is_empty());
+    }
+
+    #[test]
+    fn test_mid_triggered_stop_fires_on_quote_change() {
+        print_separator("Mid Triggered Stop Fires On Quote Change");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.config_mut().stop_trigger = StopTrigger::Mid;
+
+        let bid = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(98.0)),
+            dec!(5.0),
+        );
+        let ask = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(102.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(bid).unwrap();
+        order_book.add_order(ask).unwrap();
+
+        let stop_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(95.0)),
+            dec!(2.0),
+        );
+        order_book.add_stop_order(stop_sell, dec!(99.0)).unwrap();
+
+        // Mid is 100, above the stop
+        assert!(order_book.check_stops().trades.is_empty());
+
+        // The ask is pulled and re-quoted lower, moving the mid to 99 with no trade
+        order_book
+            .cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap())
+            .unwrap();
+        let lower_ask = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(lower_ask).unwrap();
+
+        assert!(order_book.trades().is_empty());
+        assert_eq!(order_book.mid_price(), Some(dec!(99.0)));
+
+        let trades = order_book.check_stops().trades;
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(98.0));
+        assert_eq!(trades[0].quantity, dec!(2.0));
+        assert!(order_book.stop_orders().is_empty());
+    }
+
+    #[test]
+    fn test_bid_ask_triggered_stop_uses_opposite_touch() {
+        print_separator("Bid/Ask Triggered Stop");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.config_mut().stop_trigger = StopTrigger::BidAsk;
+
+        let stop_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(1.0),
+        );
+        order_book.add_stop_order(stop_buy, dec!(101.0)).unwrap();
+
+        let ask = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(ask).unwrap();
+        assert!(order_book.check_stops().trades.is_empty());
+
+        order_book
+            .cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap())
+            .unwrap();
+        let higher_ask = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(higher_ask).unwrap();
+
+        let trades = order_book.check_stops().trades;
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(101.0));
+    }
+
+    #[test]
+    fn test_book_for_instrument_validates_tick_and_lot() {
+        print_separator("Book For Instrument");
+
+        let instrument = Instrument {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap(),
+            symbol: "RSE".to_string(),
+            name: "Redmont Stock Exchange".to_string(),
+            r#type: InstrumentType::STOCK,
+            status: InstrumentStatus::ACTIVE,
+            lot_size: 10,
+            tick_size: dec!(0.05),
+            currency: "RMD".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let mut order_book = OrderBook::for_instrument(&instrument).unwrap();
+        order_book.config_mut().odd_lot_policy = OddLotPolicy::Reject;
+
+        assert_eq!(order_book.instrument_id(), instrument.id);
+        assert_eq!(order_book.tick_size(), Some(dec!(0.05)));
+        assert_eq!(order_book.lot_size(), dec!(10));
+
+        let off_tick = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.03)),
+            dec!(10),
+        );
+        assert_eq!(
+            order_book.add_order(off_tick).unwrap_err(),
+            OrderError::InvalidTickSize { price: dec!(100.03), tick_size: dec!(0.05) }
+        );
+
+        let odd_lot = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.05)),
+            dec!(5),
+        );
+        assert_eq!(
+            order_book.add_order(odd_lot).unwrap_err(),
+            OrderError::OddLot { quantity: dec!(5), lot_size: dec!(10) }
+        );
+
+        let valid = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.05)),
+            dec!(10),
+        );
+        assert!(order_book.add_order(valid).unwrap().is_empty());
+        assert_eq!(order_book.best_bid(), Some(dec!(100.05)));
+    }
+
+    #[test]
+    fn test_book_for_suspended_instrument_rejects_orders() {
+        print_separator("Book For Suspended Instrument");
+
+        let instrument = Instrument {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap(),
+            symbol: "RSE".to_string(),
+            name: "Redmont Stock Exchange".to_string(),
+            r#type: InstrumentType::STOCK,
+            status: InstrumentStatus::SUSPENDED,
+            lot_size: 1,
+            tick_size: dec!(0.01),
+            currency: "RMD".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let mut order_book = OrderBook::for_instrument(&instrument).unwrap();
+
+        let order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(10),
+        );
+
+        assert_eq!(
+            order_book.add_order(order).unwrap_err(),
+            OrderError::InstrumentNotActive(InstrumentStatus::SUSPENDED)
+        );
+    }
+
+    #[test]
+    fn test_depth_aggregates_levels_best_first() {
+        print_separator("Depth Aggregates Levels");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, side, price, quantity) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(99.0), dec!(5.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::BUY, dec!(99.0), dec!(3.0)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::BUY, dec!(98.0), dec!(7.0)),
+            ("00000000-0000-0000-0000-000000000008", OrderSide::SELL, dec!(101.0), dec!(2.0)),
+            ("00000000-0000-0000-0000-00000000000a", OrderSide::SELL, dec!(102.0), dec!(4.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        let depth = order_book.depth(1);
+        println!("📚 Depth: {:?}", depth);
+
+        assert_eq!(order_book.bbo(), (Some(dec!(99.0)), Some(dec!(101.0))));
+        assert_eq!(
+            depth.bids,
+            vec![DepthLevel { price: dec!(99.0), quantity: dec!(8.0), order_count: 2 }]
+        );
+        assert_eq!(
+            depth.asks,
+            vec![DepthLevel { price: dec!(101.0), quantity: dec!(2.0), order_count: 1 }]
+        );
+        assert_eq!(order_book.depth(10).bids.len(), 2);
+        assert!(order_book
+            .get_order(Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap())
+            .is_some());
+    }
+
+    #[test]
+    fn test_trade_prices_respect_both_limits() {
+        print_separator("Trade Prices Respect Both Limits");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, price) in [
+            ("00000000-0000-0000-0000-000000000002", dec!(100.0)),
+            ("00000000-0000-0000-0000-000000000006", dec!(101.0)),
+        ] {
+            let sell_order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(5.0),
+            );
+            order_book.add_order(sell_order).unwrap();
+        }
+
+        // A buy limited at 102 sweeps both levels; every print sits within both limits
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(102.0)),
+            dec!(10.0),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+            assert!(trade.price <= dec!(102.0));
+        }
+        assert_eq!(trades.len(), 2);
+
+        // A price outside the resting sell's limit is reported, not printed
+        let buy = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(102.0)),
+            dec!(1.0),
+        );
+        let sell = create_test_order(
+            "00000000-0000-0000-0000-000000000009",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(1.0),
+        );
+        assert!(order_book.check_trade_price(&buy, &sell, dec!(101.5)).is_ok());
+        assert!(matches!(
+            order_book.check_trade_price(&buy, &sell, dec!(100.5)),
+            Err(OrderError::InternalInconsistency(_))
+        ));
+        assert!(matches!(
+            order_book.create_trade(&buy, &sell, dec!(102.5), dec!(1.0)),
+            Err(OrderError::InternalInconsistency(_))
+        ));
+    }
+
+    #[test]
+    fn test_min_fill_increment_truncates_fills() {
+        print_separator("Min Fill Increment Truncates Fills");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.config_mut().min_fill_increment = Some(dec!(10));
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(25),
+        );
+        order_book.add_order(sell_order).unwrap();
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(100),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, dec!(20));
+
+        let buy = order_book
+            .get_order(Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap())
+            .unwrap();
+        assert_eq!(buy.remaining_quantity, dec!(80));
+        assert_eq!(buy.status, OrderStatus::PARTIAL);
+        assert_eq!(order_book.asks[&dec!(100.0)][0].remaining_quantity, dec!(5));
+    }
+
+    #[test]
+    fn test_rested_after_partial_event() {
+        print_separator("Rested After Partial Event");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        let sell_id = Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap();
+        let buy_id = Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap();
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(6.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+
+        let events = order_book.drain_events();
+        assert!(matches!(events.as_slice(), [BookEvent::Accepted { order_id }] if *order_id == sell_id));
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(10.0),
+        );
+        order_book.add_order(buy_order).unwrap();
+
+        let events = order_book.drain_events();
+        println!("📣 Events: {:?}", events);
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], BookEvent::Trade(trade) if trade.quantity == dec!(6.0)));
+        match &events[1] {
+            BookEvent::RestedAfterPartial { order_id, filled, remaining } => {
+                assert_eq!(*order_id, buy_id);
+                assert_eq!(*filled, dec!(6.0));
+                assert_eq!(*remaining, dec!(4.0));
+            }
+            other => panic!("expected RestedAfterPartial, got {:?}", other),
+        }
+        assert!(order_book.drain_events().is_empty());
+    }
+
+    #[test]
+    fn test_best_prices_across_negative_and_positive_levels() {
+        print_separator("Best Prices Across Negative And Positive Levels");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, side, price) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(-5.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::BUY, dec!(-1.5)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::BUY, dec!(-10.0)),
+            ("00000000-0000-0000-0000-000000000008", OrderSide::SELL, dec!(-0.5)),
+            ("00000000-0000-0000-0000-00000000000a", OrderSide::SELL, dec!(2.0)),
+            ("00000000-0000-0000-0000-00000000000c", OrderSide::SELL, dec!(0.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order_book.add_order(order).unwrap();
+        }
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(order_book.best_bid(), Some(dec!(-1.5)));
+        assert_eq!(order_book.best_ask(), Some(dec!(-0.5)));
+
+        let depth = order_book.depth(3);
+        let bid_prices: Vec<Decimal> = depth.bids.iter().map(|l| l.price).collect();
+        let ask_prices: Vec<Decimal> = depth.asks.iter().map(|l| l.price).collect();
+        assert_eq!(bid_prices, vec![dec!(-1.5), dec!(-5.0), dec!(-10.0)]);
+        assert_eq!(ask_prices, vec![dec!(-0.5), dec!(0.0), dec!(2.0)]);
+
+        // An incoming sell at -3 trades against the highest bid first
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-00000000000e",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(-3.0)),
+            dec!(1.0),
+        );
+        let trades = order_book.add_order(sell_order).unwrap();
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(-1.5));
+        assert_eq!(order_book.best_bid(), Some(dec!(-5.0)));
+        assert!(PriceOrdering::Descending.is_better(dec!(-1.5), dec!(-5.0)));
+        assert!(PriceOrdering::Ascending.is_better(dec!(-0.5), dec!(0.0)));
+    }
+
+    #[test]
+    fn test_fillable_quantity_stops_at_limit() {
+        print_separator("Fillable Quantity Stops At Limit");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, side, price, quantity) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::SELL, dec!(100.0), dec!(5.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::SELL, dec!(100.0), dec!(2.0)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::SELL, dec!(101.0), dec!(3.0)),
+            ("00000000-0000-0000-0000-000000000008", OrderSide::SELL, dec!(103.0), dec!(10.0)),
+            ("00000000-0000-0000-0000-00000000000a", OrderSide::BUY, dec!(99.0), dec!(4.0)),
+            ("00000000-0000-0000-0000-00000000000c", OrderSide::BUY, dec!(98.0), dec!(6.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        assert_eq!(order_book.fillable_quantity(OrderSide::BUY, dec!(99.5)), dec!(0));
+        assert_eq!(order_book.fillable_quantity(OrderSide::BUY, dec!(100.0)), dec!(7.0));
+        assert_eq!(order_book.fillable_quantity(OrderSide::BUY, dec!(102.0)), dec!(10.0));
+        assert_eq!(order_book.fillable_quantity(OrderSide::BUY, dec!(103.0)), dec!(20.0));
+        assert_eq!(order_book.fillable_quantity(OrderSide::SELL, dec!(98.5)), dec!(4.0));
+        assert_eq!(order_book.fillable_quantity(OrderSide::SELL, dec!(90.0)), dec!(10.0));
+
+        order_book.freeze_price(dec!(100.0));
+        assert_eq!(order_book.fillable_quantity(OrderSide::BUY, dec!(102.0)), dec!(3.0));
+    }
+
+    #[test]
+    fn test_trades_returned_in_execution_order() {
+        print_separator("Trades Returned In Execution Order");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        // Added worst price first so insertion order differs from priority order
+        let resting = [
+            ("00000000-0000-0000-0000-000000000010", dec!(102.0)),
+            ("00000000-0000-0000-0000-000000000011", dec!(100.0)),
+            ("00000000-0000-0000-0000-000000000012", dec!(101.0)),
+            ("00000000-0000-0000-0000-000000000013", dec!(100.0)),
+        ];
+        for (id, price) in resting {
+            let sell_order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(2.0),
+            );
+            order_book.add_order(sell_order).unwrap();
+        }
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(102.0)),
+            dec!(8.0),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        let executed: Vec<(Uuid, Decimal)> = trades.iter().map(|t| (t.seller_order_id, t.price)).collect();
+        let expected = vec![
+            (Uuid::from_str("00000000-0000-0000-0000-000000000011").unwrap(), dec!(100.0)),
+            (Uuid::from_str("00000000-0000-0000-0000-000000000013").unwrap(), dec!(100.0)),
+            (Uuid::from_str("00000000-0000-0000-0000-000000000012").unwrap(), dec!(101.0)),
+            (Uuid::from_str("00000000-0000-0000-0000-000000000010").unwrap(), dec!(102.0)),
+        ];
+        assert_eq!(executed, expected);
+        assert!(trades.windows(2).all(|pair| pair[0].price <= pair[1].price));
+    }
+
+    #[test]
+    fn test_mark_price_fallbacks() {
+        print_separator("Mark Price Fallbacks");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        assert_eq!(order_book.mark_price(), None);
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(10.0),
+        );
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(10.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+        order_book.add_order(buy_order).unwrap();
+        assert_eq!(order_book.mark_price(), Some(dec!(100.0)));
+
+        // Take out the whole bid so only the ask side remains
+        let take_bid = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::SELL,
+            OrderType::MARKET,
+            None,
+            dec!(10.0),
+        );
+        order_book.add_order(take_bid).unwrap();
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(order_book.best_bid(), None);
+        assert_eq!(order_book.mark_price(), Some(dec!(99.0)));
+    }
+
+    #[test]
+    fn test_wide_spread_triggers_volatility_halt() {
+        print_separator("Wide Spread Triggers Volatility Halt");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.config_mut().max_spread = Some(dec!(2.0));
+
+        for (id, side, price) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(99.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::SELL, dec!(100.0)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::SELL, dec!(105.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(5.0),
+            );
+            order_book.add_order(order).unwrap();
+        }
+        assert!(!order_book.is_halted());
+
+        // Pulling the 100 offer widens the spread to 6
+        order_book
+            .cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap())
+            .unwrap();
+        assert_eq!(order_book.spread(), Some(dec!(6.0)));
+        assert_eq!(order_book.halt_reason(), Some(HaltReason::Volatility));
+        assert!(order_book
+            .drain_events()
+            .iter()
+            .any(|event| matches!(event, BookEvent::Halted { reason: HaltReason::Volatility })));
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(104.0)),
+            dec!(2.0),
+        );
+        assert_eq!(
+            order_book.add_order(buy_order.clone()).unwrap_err(),
+            OrderError::Halted(HaltReason::Volatility)
+        );
+
+        // After resuming, an order that narrows the spread keeps the book trading
+        order_book.resume();
+        let trades = order_book.add_order(buy_order).unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(order_book.spread(), Some(dec!(1.0)));
+        assert!(!order_book.is_halted());
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-00000000000a",
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(104.0)),
+            dec!(1.0),
+        );
+        let trades = order_book.add_order(sell_order).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert!(!order_book.is_halted());
+    }
+
+    #[test]
+    fn test_internalized_buy_matches_own_sell_first() {
+        print_separator("Internalized Buy Matches Own Sell First");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let internalizer = Uuid::from_str("00000000-0000-0000-0000-000000000003").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.config_mut().internalization =
+            InternalizationPolicy::Brokers(HashSet::from([internalizer]));
+
+        // A cheaper public offer from another broker
+        let public_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000009",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(10.0),
+        );
+        let own_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(4.0),
+        );
+        order_book.add_order(public_sell).unwrap();
+        order_book.add_order(own_sell).unwrap();
+
+        // The internal sell is segregated from the public book
+        assert_eq!(order_book.best_ask(), Some(dec!(99.0)));
+        assert_eq!(order_book.depth(5).asks.len(), 1);
+
+        let own_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(6.0),
+        );
+        let trades = order_book.add_order(own_buy).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].seller_order_id, Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap());
+        assert_eq!(trades[0].quantity, dec!(4.0));
+        assert_eq!(trades[0].price, dec!(100.0));
+        assert_eq!(trades[1].seller_order_id, Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap());
+        assert_eq!(trades[1].quantity, dec!(2.0));
+        assert_eq!(trades[1].price, dec!(99.0));
+
+        // Public flow never reaches the internal book
+        let other_own_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(98.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(other_own_sell).unwrap();
+
+        let public_buy = create_test_order(
+            "00000000-0000-0000-0000-00000000000a",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(8.0),
+        );
+        let trades = order_book.add_order(public_buy).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(99.0));
+
+        let cancelled = order_book
+            .cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000008").unwrap())
+            .unwrap();
+        assert_eq!(cancelled.status, OrderStatus::CANCELLED);
+    }
+
+    #[test]
+    fn test_compact_removes_stale_levels() {
+        print_separator("Compact Removes Stale Levels");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let mut order_ids = Vec::new();
+        for i in 0..6u32 {
+            let (side, price) = if i % 2 == 0 {
+                (OrderSide::BUY, dec!(90) - Decimal::from(i))
+            } else {
+                (OrderSide::SELL, dec!(110) + Decimal::from(i))
+            };
+            let order = Order {
+                instrument_id,
+                ..Order::for_test(side, Some(price), dec!(1.0))
+            };
+            order_ids.push(order.id);
+            order_book.add_order(order).unwrap();
+        }
+        for order_id in &order_ids[..4] {
+            order_book.cancel_order(*order_id).unwrap();
+        }
+
+        // Simulate levels left empty by a path that drained them without cleanup
+        for price in [dec!(80), dec!(85)] {
+            order_book.bids.insert(price, Vec::new());
+        }
+        order_book.asks.insert(dec!(120), Vec::with_capacity(16));
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(order_book.compact(), 3);
+        assert_eq!(order_book.bids.len(), 1);
+        assert_eq!(order_book.asks.len(), 1);
+        assert!(order_book.bids.values().chain(order_book.asks.values()).all(|orders| !orders.is_empty()));
+        assert_eq!(order_book.compact(), 0);
+    }
+
+    #[test]
+    fn test_weighted_avg_price_favors_larger_levels() {
+        print_separator("Weighted Average Resting Price");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        assert_eq!(order_book.weighted_avg_price(OrderSide::BUY), None);
+
+        for (id, price, quantity) in [
+            ("00000000-0000-0000-0000-000000000002", dec!(100.0), dec!(30)),
+            ("00000000-0000-0000-0000-000000000004", dec!(99.0), dec!(10)),
+            ("00000000-0000-0000-0000-000000000006", dec!(100.0), dec!(20)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        // (100 * 50 + 99 * 10) / 60 = 5990 / 60
+        let expected = dec!(5990) / dec!(60);
+        let average = order_book.weighted_avg_price(OrderSide::BUY).unwrap();
+        println!("⚖️ Weighted average bid: {}", average);
+
+        assert_eq!(average, expected);
+        assert!(average > dec!(99.5));
+        assert_eq!(order_book.weighted_avg_price(OrderSide::SELL), None);
+    }
+
+    #[test]
+    fn test_close_session_cancels_day_orders_only() {
+        print_separator("Close Session Cancels Day Orders Only");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        let day_id = Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap();
+        let gtc_id = Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap();
+
+        let mut day_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(10.0),
+        );
+        day_order.time_in_force = TimeInForce::DAY;
+        let gtc_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(day_order).unwrap();
+        order_book.add_order(gtc_order).unwrap();
+
+        let cancelled = order_book.close_session();
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].id, day_id);
+        assert_eq!(order_book.get_order(day_id).unwrap().status, OrderStatus::CANCELLED);
+        assert_eq!(order_book.get_order(gtc_id).unwrap().status, OrderStatus::PENDING);
+        assert_eq!(order_book.bids[&dec!(99.0)].len(), 1);
+        assert_eq!(order_book.bids[&dec!(99.0)][0].id, gtc_id);
+
+        // The GTC order is still there to trade in the next session
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(5.0),
+        );
+        let trades = order_book.add_order(sell_order).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].buyer_order_id, gtc_id);
+    }
+
+    #[test]
+    fn test_ioc_and_fok_never_rest() {
+        print_separator("IOC And FOK Never Rest");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+
+        let mut fok_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(8.0),
+        );
+        fok_buy.time_in_force = TimeInForce::FOK;
+        let trades = order_book.add_order(fok_buy).unwrap();
+
+        assert!(trades.is_empty());
+        assert_eq!(
+            order_book.get_order(Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap()).unwrap().status,
+            OrderStatus::CANCELLED
+        );
+        assert_eq!(order_book.asks[&dec!(100.0)][0].remaining_quantity, dec!(5.0));
+
+        let mut ioc_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(8.0),
+        );
+        ioc_buy.time_in_force = TimeInForce::IOC;
+        let trades = order_book.add_order(ioc_buy).unwrap();
+
+        let ioc = order_book.get_order(Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap()).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, dec!(5.0));
+        assert_eq!(ioc.status, OrderStatus::CANCELLED);
+        assert_eq!(ioc.remaining_quantity, dec!(3.0));
+        assert!(order_book.bids.is_empty());
+    }
+
+    #[test]
+    fn test_matching_algo_allocations_are_reproducible() {
+        print_separator("Matching Algorithm Allocations");
+
+        fn run(algo: MatchingAlgo) -> Vec<(Uuid, Decimal, Decimal)> {
+            let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+            let config = BookConfig { matching_algo: algo, ..BookConfig::default() };
+            let mut order_book = OrderBook::with_config(instrument_id, config);
+            assert_eq!(order_book.matching_algo(), algo);
+
+            for (id, quantity) in [
+                ("00000000-0000-0000-0000-000000000002", dec!(30)),
+                ("00000000-0000-0000-0000-000000000004", dec!(10)),
+            ] {
+                let sell_order = create_test_order(
+                    id,
+                    "00000000-0000-0000-0000-000000000003",
+                    OrderSide::SELL,
+                    OrderType::LIMIT,
+                    Some(dec!(100.0)),
+                    quantity,
+                );
+                order_book.add_order(sell_order).unwrap();
+            }
+
+            let buy_order = create_test_order(
+                "00000000-0000-0000-0000-000000000006",
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                dec!(21),
+            );
+            let trades = order_book.add_order(buy_order).unwrap();
+            for trade in &trades {
+                visualize_trade(trade);
+            }
+
+            trades.iter().map(|t| (t.seller_order_id, t.price, t.quantity)).collect()
+        }
+
+        let first = Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap();
+        let second = Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap();
+
+        let fifo = run(MatchingAlgo::Fifo);
+        assert_eq!(fifo, vec![(first, dec!(100.0), dec!(21))]);
+        assert_eq!(run(MatchingAlgo::Fifo), fifo);
+
+        // 21 * 30/40 = 15.75 -> 15, 21 * 10/40 = 5.25 -> 5, the leftover lot goes to the earliest order
+        let pro_rata = run(MatchingAlgo::ProRata);
+        assert_eq!(pro_rata, vec![(first, dec!(100.0), dec!(16)), (second, dec!(100.0), dec!(5))]);
+        assert_eq!(run(MatchingAlgo::ProRata), pro_rata);
+    }
+
+    #[test]
+    fn test_displayed_orders_fill_before_hidden() {
+        print_separator("Displayed Orders Fill Before Hidden");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        // Interleaved arrival: hidden, displayed, hidden, displayed
+        let resting = [
+            ("00000000-0000-0000-0000-000000000010", true),
+            ("00000000-0000-0000-0000-000000000011", false),
+            ("00000000-0000-0000-0000-000000000012", true),
+            ("00000000-0000-0000-0000-000000000013", false),
+        ];
+        for (id, hidden) in resting {
+            let mut sell_order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                dec!(5.0),
+            );
+            sell_order.hidden = hidden;
+            order_book.add_order(sell_order).unwrap();
+        }
+
+        let id = |s: &str| Uuid::from_str(s).unwrap();
+        assert_eq!(
+            order_book.queue_order_with_visibility(OrderSide::SELL, dec!(100.0)),
+            vec![
+                (id("00000000-0000-0000-0000-000000000011"), true),
+                (id("00000000-0000-0000-0000-000000000013"), true),
+                (id("00000000-0000-0000-0000-000000000010"), false),
+                (id("00000000-0000-0000-0000-000000000012"), false),
+            ]
+        );
+
+        // Only displayed interest shows in market data
+        assert_eq!(order_book.depth(1).asks[0].quantity, dec!(10.0));
+        assert_eq!(order_book.depth(1).asks[0].order_count, 2);
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(15.0),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        let sellers: Vec<Uuid> = trades.iter().map(|t| t.seller_order_id).collect();
+        assert_eq!(
+            sellers,
+            vec![
+                id("00000000-0000-0000-0000-000000000011"),
+                id("00000000-0000-0000-0000-000000000013"),
+                id("00000000-0000-0000-0000-000000000010"),
+            ]
+        );
+
+        // With only a hidden order left, the level disappears from the quote
+        assert_eq!(order_book.best_ask(), None);
+        assert_eq!(order_book.fillable_quantity(OrderSide::BUY, dec!(100.0)), dec!(5.0));
+    }
+
+    #[test]
+    fn test_snapshot_diff_reports_level_changes() {
+        print_separator("Snapshot Diff");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, side, price) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(99.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::BUY, dec!(98.0)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::SELL, dec!(101.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(5.0),
+            );
+            order_book.add_order(order).unwrap();
+        }
+        let previous = order_book.snapshot();
+        assert!(order_book.diff(&previous).is_empty());
+
+        // Grow 99, remove 98 entirely, add a new ask at 102
+        for (id, side, price) in [
+            ("00000000-0000-0000-0000-000000000008", OrderSide::BUY, dec!(99.0)),
+            ("00000000-0000-0000-0000-00000000000a", OrderSide::SELL, dec!(102.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(2.0),
+            );
+            order_book.add_order(order).unwrap();
+        }
+        order_book
+            .cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap())
+            .unwrap();
+
+        let changes = order_book.diff(&previous);
+        println!("🔁 Changes: {:?}", changes);
+
+        assert_eq!(
+            changes,
+            vec![
+                LevelChange { side: OrderSide::BUY, price: dec!(98.0), new_quantity: dec!(0) },
+                LevelChange { side: OrderSide::BUY, price: dec!(99.0), new_quantity: dec!(7.0) },
+                LevelChange { side: OrderSide::SELL, price: dec!(102.0), new_quantity: dec!(2.0) },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_depth_saturates_instead_of_overflowing() {
+        print_separator("Saturating Depth");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let near_max = Decimal::MAX - dec!(1);
+        for id in ["00000000-0000-0000-0000-000000000002", "00000000-0000-0000-0000-000000000004"] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                near_max,
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        let depth = order_book.depth(1);
+        println!("📊 Level quantity: {}", depth.bids[0].quantity);
+
+        assert_eq!(depth.bids[0].quantity, Decimal::MAX);
+        assert_eq!(order_book.fillable_quantity(OrderSide::SELL, dec!(100.0)), Decimal::MAX);
+        assert_eq!(order_book.weighted_avg_price(OrderSide::BUY), None);
+    }
+
+    #[test]
+    fn test_orders_by_tag_and_cancel_where() {
+        print_separator("Order Tags");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, side, price, tag) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(98.0), "strategy-A"),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::BUY, dec!(99.0), "strategy-B"),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::SELL, dec!(101.0), "strategy-A"),
+            ("00000000-0000-0000-0000-000000000008", OrderSide::SELL, dec!(102.0), "strategy-A"),
+        ] {
+            let mut order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(5.0),
+            );
+            order.tags.push(tag.to_string());
+            order_book.add_order(order).unwrap();
+        }
+
+        let tagged: Vec<Decimal> = order_book.orders_by_tag("strategy-A").iter().filter_map(|o| o.price).collect();
+        println!("🏷️  strategy-A prices: {:?}", tagged);
+        assert_eq!(tagged.len(), 3);
+        assert!(tagged.contains(&dec!(98.0)) && tagged.contains(&dec!(101.0)) && tagged.contains(&dec!(102.0)));
+
+        let cancelled = order_book.cancel_where(|o| o.tags.iter().any(|t| t == "strategy-A"));
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(cancelled.len(), 3);
+        assert!(cancelled.iter().all(|o| o.status == OrderStatus::CANCELLED));
+        assert!(order_book.orders_by_tag("strategy-A").is_empty());
+        assert_eq!(order_book.bbo(), (Some(dec!(99.0)), None));
+        assert_eq!(order_book.orders_by_tag("strategy-B").len(), 1);
+    }
+
+    #[test]
+    fn test_repair_removes_terminal_and_exhausted_orders() {
+        print_separator("Repair Inconsistent Book");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let healthy = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(5.0),
+        );
+        let mut filled = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(5.0),
+        );
+        filled.status = OrderStatus::FILLED;
+        let mut exhausted = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(5.0),
+        );
+        exhausted.remaining_quantity = Decimal::ZERO;
+
+        // Simulate a load from storage that bypasses `add_order`
+        for order in [healthy, filled, exhausted] {
+            let price = order.price.unwrap();
+            order_book.orders.insert(order.id, order.clone());
+            order_book.levels_mut(&order.side, Venue::Lit).entry(price).or_default().push(order);
+        }
+
+        let report = order_book.repair();
+        println!("🩹 Repair report: {:?}", report);
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(report.terminal, vec![Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap()]);
+        assert_eq!(report.exhausted, vec![Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap()]);
+        assert_eq!(order_book.bids[&dec!(99.0)].len(), 1);
+        assert!(order_book.asks.is_empty());
+        assert_eq!(
+            order_book.get_order(Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap()).unwrap().status,
+            OrderStatus::FILLED
+        );
+        assert!(order_book.repair().is_clean());
+    }
+
+    #[test]
+    fn test_sweep_across_levels_fills_in_price_time_priority() {
+        print_separator("Multi-Level Sweep");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let resting = [
+            ("00000000-0000-0000-0000-000000000002", dec!(101.0), dec!(2.0)),
+            ("00000000-0000-0000-0000-000000000004", dec!(100.0), dec!(3.0)),
+            ("00000000-0000-0000-0000-000000000006", dec!(100.0), dec!(1.0)),
+            ("00000000-0000-0000-0000-000000000008", dec!(102.0), dec!(5.0)),
+        ];
+        for (id, price, quantity) in resting {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-00000000000a",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(102.0)),
+            dec!(8.0),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        let fills: Vec<(Uuid, Decimal, Decimal)> =
+            trades.iter().map(|t| (t.seller_order_id, t.price, t.quantity)).collect();
+        assert_eq!(
+            fills,
+            vec![
+                (Uuid::from_str(resting[1].0).unwrap(), dec!(100.0), dec!(3.0)),
+                (Uuid::from_str(resting[2].0).unwrap(), dec!(100.0), dec!(1.0)),
+                (Uuid::from_str(resting[0].0).unwrap(), dec!(101.0), dec!(2.0)),
+                (Uuid::from_str(resting[3].0).unwrap(), dec!(102.0), dec!(2.0)),
+            ]
+        );
+
+        // The partially filled order stays at the front with matching copies in both views
+        let partial = order_book.get_order(Uuid::from_str(resting[3].0).unwrap()).unwrap();
+        assert_eq!(partial.remaining_quantity, dec!(3.0));
+        assert_eq!(partial.status, OrderStatus::PARTIAL);
+        assert_eq!(order_book.asks[&dec!(102.0)][0].remaining_quantity, dec!(3.0));
+        assert_eq!(order_book.asks.len(), 1);
+        assert_eq!(
+            order_book.get_order(Uuid::from_str(resting[1].0).unwrap()).unwrap().status,
+            OrderStatus::FILLED
+        );
+    }
+
+    #[test]
+    fn test_deep_sweep_benchmark() {
+        print_separator("Deep Sweep Benchmark");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let levels = 200;
+        let orders_per_level = 50;
+        for level in 0..levels {
+            for _ in 0..orders_per_level {
+                let mut order = create_test_order(
+                    "00000000-0000-0000-0000-000000000002",
+                    "00000000-0000-0000-0000-000000000003",
+                    OrderSide::SELL,
+                    OrderType::LIMIT,
+                    Some(dec!(100.0) + Decimal::from(level)),
+                    dec!(1.0),
+                );
+                order.id = Uuid::new_v4();
+                order_book.add_order(order).unwrap();
+            }
+        }
+
+        let total = Decimal::from(levels * orders_per_level);
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-00000000000a",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            total,
+        );
+
+        let started = std::time::Instant::now();
+        let trades = order_book.add_order(buy_order).unwrap();
+        println!("⏱️  Swept {} orders across {} levels in {:?}", trades.len(), levels, started.elapsed());
+
+        assert_eq!(trades.len(), levels * orders_per_level);
+        assert!(order_book.asks.is_empty());
+    }
+
+    #[test]
+    fn test_last_tick_direction_follows_trade_prices() {
+        print_separator("Tick Direction");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let sequence = [
+            (dec!(100.0), None),
+            (dec!(101.0), Some(TickDirection::Up)),
+            (dec!(102.0), Some(TickDirection::Up)),
+            (dec!(102.0), Some(TickDirection::Zero)),
+            (dec!(101.0), Some(TickDirection::Down)),
+            (dec!(99.0), Some(TickDirection::Down)),
+        ];
+
+        for (price, expected) in sequence {
+            let sell_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order_book.add_order(sell_order).unwrap();
+
+            let buy_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::MARKET,
+                None,
+                dec!(1.0),
+            );
+            order_book.add_order(buy_order).unwrap();
+
+            println!("📈 Trade at {} -> {:?}", price, order_book.last_tick_direction());
+            assert_eq!(order_book.last_price(), Some(price));
+            assert_eq!(order_book.last_tick_direction(), expected);
+        }
+    }
+
+    #[test]
+    fn test_uptick_rule_blocks_short_sells_on_downtick() {
+        print_separator("Uptick Rule");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig { uptick_rule: true, ..BookConfig::default() };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+        order_book.set_short_sale_restricted(true);
+
+        let trade_at = |order_book: &mut OrderBook, price: Decimal| {
+            let bid = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order_book.add_order(bid).unwrap();
+            let sell = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::SELL,
+                OrderType::MARKET,
+                None,
+                dec!(1.0),
+            );
+            order_book.add_order(sell).unwrap();
+        };
+        let short_sell = |id: &str, price: Decimal| {
+            let mut order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order.is_short = true;
+            order
+        };
+
+        // Downtick: hitting the bid is blocked, resting above it is fine
+        trade_at(&mut order_book, dec!(100.0));
+        trade_at(&mut order_book, dec!(99.0));
+        assert_eq!(order_book.last_tick_direction(), Some(TickDirection::Down));
+
+        let bid = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(1.0),
+        );
+        order_book.add_order(bid).unwrap();
+
+        let blocked = short_sell("00000000-0000-0000-0000-000000000004", dec!(99.0));
+        assert_eq!(order_book.add_order(blocked).unwrap_err(), OrderError::ShortSaleRestricted);
+
+        let passive = short_sell("00000000-0000-0000-0000-000000000006", dec!(99.5));
+        assert!(order_book.add_order(passive).unwrap().is_empty());
+        assert_eq!(order_book.best_ask(), Some(dec!(99.5)));
+        order_book.cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap()).unwrap();
+
+        // Uptick: the same short sell may now take the bid
+        trade_at(&mut order_book, dec!(99.5));
+        assert_eq!(order_book.last_tick_direction(), Some(TickDirection::Up));
+
+        let allowed = short_sell("00000000-0000-0000-0000-000000000008", dec!(99.0));
+        let trades = order_book.add_order(allowed).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(99.0));
+    }
+
+    #[test]
+    fn test_events_are_buffered_until_flushed() {
+        print_separator("Event Batching");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig { event_batch_size: Some(4), ..BookConfig::default() };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+
+        for id in ["00000000-0000-0000-0000-000000000002", "00000000-0000-0000-0000-000000000004"] {
+            let sell_order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                dec!(1.0),
+            );
+            order_book.add_order(sell_order).unwrap();
+        }
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(1.0),
+        );
+        order_book.add_order(buy_order).unwrap();
+
+        // Two accepts and a trade are still below the batch size
+        assert!(order_book.drain_events().is_empty());
+
+        order_book.flush_events();
+        let events = order_book.drain_events();
+        println!("📣 Flushed: {:?}", events);
+
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], BookEvent::Accepted { order_id }
+            if *order_id == Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap()));
+        assert!(matches!(&events[1], BookEvent::Accepted { order_id }
+            if *order_id == Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap()));
+        assert!(matches!(&events[2], BookEvent::Trade(trade) if trade.quantity == dec!(1.0)));
+
+        // Reaching the batch size publishes without an explicit flush
+        for _ in 0..2 {
+            let buy_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(dec!(98.0)),
+                dec!(1.0),
+            );
+            order_book.add_order(buy_order).unwrap();
+        }
+        assert!(order_book.drain_events().is_empty());
+        order_book.halt(HaltReason::Manual);
+        order_book.resume();
+        assert_eq!(order_book.drain_events().len(), 4);
+    }
+
+    #[test]
+    fn test_clearing_price_for_quantity() {
+        print_separator("Clearing Price For Quantity");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, side, price) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::SELL, dec!(100.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::SELL, dec!(101.0)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::SELL, dec!(103.0)),
+            ("00000000-0000-0000-0000-000000000008", OrderSide::BUY, dec!(99.0)),
+            ("00000000-0000-0000-0000-00000000000a", OrderSide::BUY, dec!(97.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(5.0),
+            );
+            order_book.add_order(order).unwrap();
+        }
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(order_book.clearing_price_for_quantity(OrderSide::BUY, dec!(3.0)), Some(dec!(100.0)));
+        assert_eq!(order_book.clearing_price_for_quantity(OrderSide::BUY, dec!(5.0)), Some(dec!(100.0)));
+        assert_eq!(order_book.clearing_price_for_quantity(OrderSide::BUY, dec!(6.0)), Some(dec!(101.0)));
+        assert_eq!(order_book.clearing_price_for_quantity(OrderSide::BUY, dec!(15.0)), Some(dec!(103.0)));
+        assert_eq!(order_book.clearing_price_for_quantity(OrderSide::BUY, dec!(15.1)), None);
+        assert_eq!(order_book.clearing_price_for_quantity(OrderSide::SELL, dec!(7.0)), Some(dec!(97.0)));
+        assert_eq!(order_book.clearing_price_for_quantity(OrderSide::SELL, dec!(11.0)), None);
+    }
+
+    #[test]
+    fn test_max_orders_per_level_rejects_resting_orders() {
+        print_separator("Max Orders Per Level");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig { max_orders_per_level: Some(2), ..BookConfig::default() };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+
+        for id in ["00000000-0000-0000-0000-000000000002", "00000000-0000-0000-0000-000000000004"] {
+            let sell_order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                dec!(1.0),
+            );
+            order_book.add_order(sell_order).unwrap();
+        }
+
+        let overflow = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(1.0),
+        );
+        assert_eq!(
+            order_book.add_order(overflow).unwrap_err(),
+            OrderError::LevelFull { price: dec!(100.0), max_orders: 2 }
+        );
+        assert_eq!(order_book.depth(1).asks[0].order_count, 2);
+
+        // Another level still has room
+        let next_level = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(1.0),
+        );
+        assert!(order_book.add_order(next_level).is_ok());
+
+        // A crossing order still trades against the full level
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-00000000000a",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(1.0),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(100.0));
+        assert_eq!(order_book.depth(1).asks[0].order_count, 1);
+    }
+
+    #[test]
+    fn test_trades_in_price_range_is_inclusive() {
+        print_separator("Trades In Price Range");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for price in [dec!(99.0), dec!(100.0), dec!(101.0), dec!(102.0), dec!(100.5)] {
+            let sell_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order_book.add_order(sell_order).unwrap();
+            let buy_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::MARKET,
+                None,
+                dec!(1.0),
+            );
+            order_book.add_order(buy_order).unwrap();
+        }
+
+        let prices = |trades: Vec<&Trade>| trades.iter().map(|t| t.price).collect::<Vec<_>>();
+
+        assert_eq!(
+            prices(order_book.trades_in_price_range(dec!(100.0), dec!(101.0))),
+            vec![dec!(100.0), dec!(101.0), dec!(100.5)]
+        );
+        assert_eq!(prices(order_book.trades_in_price_range(dec!(102.0), dec!(102.0))), vec![dec!(102.0)]);
+        assert!(order_book.trades_in_price_range(dec!(103.0), dec!(110.0)).is_empty());
+        assert!(order_book.trades_in_price_range(dec!(101.0), dec!(100.0)).is_empty());
+    }
+
+    #[test]
+    fn test_simulate_cancel_matches_actual_cancel() {
+        print_separator("Simulate Cancel");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, price, quantity) in [
+            ("00000000-0000-0000-0000-000000000002", dec!(99.0), dec!(4.0)),
+            ("00000000-0000-0000-0000-000000000004", dec!(99.0), dec!(6.0)),
+            ("00000000-0000-0000-0000-000000000006", dec!(98.0), dec!(3.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        let shared_level = Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap();
+        let preview = order_book.simulate_cancel(shared_level).unwrap();
+        println!("🔍 Preview: {:?}", preview);
+        assert_eq!(
+            preview,
+            CancelPreview {
+                side: OrderSide::BUY,
+                price: dec!(99.0),
+                quantity_freed: dec!(4.0),
+                level_quantity_after: dec!(6.0),
+                level_removed: false,
+            }
+        );
+
+        // Previewing changes nothing; cancelling then matches the preview
+        assert_eq!(order_book.depth(1).bids[0].quantity, dec!(10.0));
+        let cancelled = order_book.cancel_order(shared_level).unwrap();
+        assert_eq!(cancelled.remaining_quantity, preview.quantity_freed);
+        assert_eq!(order_book.depth(1).bids[0].quantity, preview.level_quantity_after);
+
+        let last_at_level = Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap();
+        let preview = order_book.simulate_cancel(last_at_level).unwrap();
+        assert!(preview.level_removed);
+        assert_eq!(preview.level_quantity_after, dec!(0));
+        order_book.cancel_order(last_at_level).unwrap();
+        assert!(order_book.depth(2).bids.iter().all(|level| level.price != dec!(98.0)));
+
+        // Orders that are no longer live cannot be cancelled, so there is nothing to preview
+        assert_eq!(order_book.simulate_cancel(last_at_level), None);
+        assert_eq!(order_book.simulate_cancel(Uuid::new_v4()), None);
+    }
+
+    #[test]
+    fn test_price_collar_rejects_far_off_market_orders() {
+        print_separator("Price Collar");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig { price_collar_bps: Some(dec!(500)), ..BookConfig::default() };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+
+        for (id, side, price) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(99.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::SELL, dec!(101.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(5.0),
+            );
+            order_book.add_order(order).unwrap();
+        }
+        assert_eq!(order_book.mid_price(), Some(dec!(100.0)));
+
+        // 50% above mid is far outside a 5% collar
+        let fat_finger = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(150.0)),
+            dec!(1.0),
+        );
+        assert_eq!(
+            order_book.add_order(fat_finger).unwrap_err(),
+            OrderError::PriceOutOfBand { price: dec!(150.0), reference: dec!(100.0) }
+        );
+        assert_eq!(order_book.best_ask(), Some(dec!(101.0)));
+
+        let within_collar = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(105.0)),
+            dec!(1.0),
+        );
+        let trades = order_book.add_order(within_collar).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(101.0));
+
+        // Passive prices far from the market are not aggressive and stay allowed
+        let low_bid = create_test_order(
+            "00000000-0000-0000-0000-00000000000a",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(50.0)),
+            dec!(1.0),
+        );
+        assert!(order_book.add_order(low_bid).is_ok());
+    }
+
+    #[test]
+    fn test_market_remainder_rests_at_last_trade() {
+        print_separator("Market Remainder Rests At Last Trade");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig {
+            market_remainder: MarketRemainderPolicy::RestAtLastTrade,
+            ..BookConfig::default()
+        };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+
+        for (id, price) in [
+            ("00000000-0000-0000-0000-000000000002", dec!(101.0)),
+            ("00000000-0000-0000-0000-000000000004", dec!(102.0)),
+        ] {
+            let sell_order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(2.0),
+            );
+            order_book.add_order(sell_order).unwrap();
+        }
+
+        let buy_id = Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap();
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(7.0),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[1].price, dec!(102.0));
+
+        let rested = order_book.get_order(buy_id).unwrap();
+        assert_eq!(rested.order_type, OrderType::LIMIT);
+        assert_eq!(rested.price, Some(dec!(102.0)));
+        assert_eq!(rested.status, OrderStatus::PARTIAL);
+        assert_eq!(rested.remaining_quantity, dec!(3.0));
+        assert_eq!(order_book.best_bid(), Some(dec!(102.0)));
+        assert_eq!(order_book.depth(1).bids[0].quantity, dec!(3.0));
+
+        // Nothing to trade against means no fill price, so the order is still rejected
+        let unfilled = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(1.0),
+        );
+        assert!(order_book.add_order(unfilled).unwrap().is_empty());
+        assert_eq!(
+            order_book.get_order(Uuid::from_str("00000000-0000-0000-0000-000000000008").unwrap()).unwrap().status,
+            OrderStatus::REJECTED
+        );
+    }
+
+    #[test]
+    fn test_duplicate_order_id_is_rejected() {
+        print_separator("Duplicate Order Id");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let first = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(first).unwrap();
+
+        let duplicate = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(2.0),
+        );
+        let order_id = duplicate.id;
+        assert_eq!(order_book.add_order(duplicate).unwrap_err(), OrderError::DuplicateOrderId(order_id));
+
+        let original = order_book.get_order(order_id).unwrap();
+        assert_eq!(original.side, OrderSide::BUY);
+        assert_eq!(original.remaining_quantity, dec!(5.0));
+        assert_eq!(original.status, OrderStatus::PENDING);
+        assert_eq!(order_book.depth(1).bids[0].quantity, dec!(5.0));
+        assert!(order_book.trades().is_empty());
+    }
+
+    #[test]
+    fn test_restore_tape_rebuilds_derived_state() {
+        print_separator("Tape Snapshot And Restore");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (price, quantity) in [(dec!(100.0), dec!(2.0)), (dec!(102.0), dec!(1.0)), (dec!(101.0), dec!(1.0))] {
+            let sell_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(sell_order).unwrap();
+            let buy_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::MARKET,
+                None,
+                quantity,
+            );
+            order_book.add_order(buy_order).unwrap();
+        }
+
+        let snapshot = order_book.tape_snapshot();
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(order_book.vwap(), Some(dec!(100.75)));
+
+        let mut restored = OrderBook::new(instrument_id);
+        restored.restore_tape(snapshot.clone());
+
+        assert_eq!(restored.last_price(), Some(dec!(101.0)));
+        assert_eq!(restored.last_price(), order_book.last_price());
+        assert_eq!(restored.vwap(), order_book.vwap());
+        assert_eq!(restored.last_tick_direction(), Some(TickDirection::Down));
+        assert_eq!(restored.trades_for_order(snapshot[1].buyer_order_id).len(), 1);
+        assert!(restored.drain_events().is_empty());
+
+        restored.restore_tape(Vec::new());
+        assert_eq!(restored.last_price(), None);
+        assert_eq!(restored.vwap(), None);
+    }
+
+    #[test]
+    fn test_min_resting_time_blocks_early_cancels() {
+        print_separator("Cancel Grace Period");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig { min_resting_time: Some(Duration::milliseconds(500)), ..BookConfig::default() };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+
+        let start = Utc::now();
+        let clock = Arc::new(MockClock::new(start));
+        order_book.set_clock(clock.clone());
+
+        let mut order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(5.0),
+        );
+        order.created_at = start;
+        let order_id = order.id;
+        order_book.add_order(order).unwrap();
+
+        clock.advance(Duration::milliseconds(200));
+        assert_eq!(
+            order_book.cancel_order(order_id).unwrap_err(),
+            OrderError::TooSoonToCancel { order_id, remaining: Duration::milliseconds(300) }
+        );
+        assert_eq!(order_book.best_bid(), Some(dec!(99.0)));
+        assert!(order_book.cancel_where(|_| true).is_empty());
+
+        clock.advance(Duration::milliseconds(300));
+        let cancelled = order_book.cancel_order(order_id).unwrap();
+        assert_eq!(cancelled.status, OrderStatus::CANCELLED);
+        assert_eq!(order_book.best_bid(), None);
+    }
+
+    #[test]
+    fn test_bucketed_depth_groups_levels() {
+        print_separator("Bucketed Depth");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, side, price, quantity) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(100.10), dec!(1.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::BUY, dec!(100.20), dec!(2.0)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::BUY, dec!(100.40), dec!(4.0)),
+            ("00000000-0000-0000-0000-000000000008", OrderSide::SELL, dec!(100.60), dec!(3.0)),
+            ("00000000-0000-0000-0000-00000000000a", OrderSide::SELL, dec!(100.75), dec!(5.0)),
+            ("00000000-0000-0000-0000-00000000000c", OrderSide::SELL, dec!(100.90), dec!(6.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        let bids = order_book.bucketed_depth(OrderSide::BUY, dec!(0.25));
+        println!("🪣 Bid buckets: {:?}", bids);
+        assert_eq!(bids, vec![(dec!(100.25), dec!(4.0)), (dec!(100.00), dec!(3.0))]);
+
+        let asks = order_book.bucketed_depth(OrderSide::SELL, dec!(0.25));
+        println!("🪣 Ask buckets: {:?}", asks);
+        assert_eq!(asks, vec![(dec!(100.75), dec!(8.0)), (dec!(101.00), dec!(6.0))]);
+    }
+
+    #[test]
+    fn test_swapping_matching_strategy_changes_allocation() {
+        print_separator("Pluggable Matching Strategy");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let rest_level = |order_book: &mut OrderBook| -> (Uuid, Uuid) {
+            let mut ids = Vec::new();
+            for quantity in [dec!(2.0), dec!(8.0)] {
+                let sell_order = create_test_order(
+                    &Uuid::new_v4().to_string(),
+                    "00000000-0000-0000-0000-000000000003",
+                    OrderSide::SELL,
+                    OrderType::LIMIT,
+                    Some(dec!(100.0)),
+                    quantity,
+                );
+                ids.push(sell_order.id);
+                order_book.add_order(sell_order).unwrap();
+            }
+            (ids[0], ids[1])
+        };
+        let take = |order_book: &mut OrderBook| {
+            let buy_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::MARKET,
+                None,
+                dec!(5.0),
+            );
+            order_book.add_order(buy_order).unwrap()
+        };
+
+        // FIFO: the first order in the queue is filled before the larger one behind it
+        order_book.set_matching_strategy(Arc::new(FifoStrategy));
+        let (small, large) = rest_level(&mut order_book);
+        let trades = take(&mut order_book);
+        let fills: Vec<(Uuid, Decimal)> = trades.iter().map(|t| (t.seller_order_id, t.quantity)).collect();
+        assert_eq!(fills, vec![(small, dec!(2.0)), (large, dec!(3.0))]);
+        order_book.cancel_order(large).unwrap();
+
+        // Pro-rata on the same book: both orders share the fill by size
+        order_book.set_matching_strategy(Arc::new(ProRataStrategy));
+        let (small, large) = rest_level(&mut order_book);
+        let trades = take(&mut order_book);
+        let fills: Vec<(Uuid, Decimal)> = trades.iter().map(|t| (t.seller_order_id, t.quantity)).collect();
+        assert_eq!(fills, vec![(small, dec!(1.0)), (large, dec!(4.0))]);
+    }
+
+    #[test]
+    fn test_broker_concentration_per_side() {
+        print_separator("Broker Concentration");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let broker_a = "00000000-0000-0000-0000-00000000000a";
+        let broker_b = "00000000-0000-0000-0000-00000000000b";
+        let broker_c = "00000000-0000-0000-0000-00000000000c";
+        for (broker, side, price, quantity) in [
+            (broker_a, OrderSide::BUY, dec!(99.0), dec!(3.0)),
+            (broker_a, OrderSide::BUY, dec!(98.0), dec!(3.0)),
+            (broker_b, OrderSide::BUY, dec!(98.0), dec!(2.0)),
+            (broker_c, OrderSide::BUY, dec!(97.0), dec!(2.0)),
+            (broker_b, OrderSide::SELL, dec!(101.0), dec!(4.0)),
+        ] {
+            let order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                broker,
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        assert_eq!(order_book.distinct_brokers(OrderSide::BUY), 3);
+        assert_eq!(order_book.distinct_brokers(OrderSide::SELL), 1);
+
+        // Broker A holds 6 of the 10 resting on the bid
+        assert_eq!(order_book.largest_broker_share(OrderSide::BUY), Some(dec!(0.6)));
+        assert_eq!(order_book.largest_broker_share(OrderSide::SELL), Some(dec!(1)));
+
+        let empty_book = OrderBook::new(instrument_id);
+        assert_eq!(empty_book.distinct_brokers(OrderSide::BUY), 0);
+        assert_eq!(empty_book.largest_broker_share(OrderSide::BUY), None);
+    }
+
+    #[test]
+    fn test_partial_fills_update_running_totals() {
+        print_separator("Running Fill Totals");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(2.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+
+        // First partial fill at 100 as the aggressor; the rest of the order rests at 102
+        let buy_id = Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap();
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(102.0)),
+            dec!(10.0),
+        );
+        order_book.add_order(buy_order).unwrap();
+
+        let buy = order_book.get_order(buy_id).unwrap();
+        visualize_order("BUY", buy);
+        assert_eq!(buy.filled_quantity, dec!(2.0));
+        assert_eq!(buy.avg_fill_price, Some(dec!(100.0)));
+        assert_eq!(buy.status, OrderStatus::PARTIAL);
+
+        // Second partial fill at 102 while resting
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(102.0)),
+            dec!(6.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+
+        // (2 * 100 + 6 * 102) / 8
+        let buy = order_book.get_order(buy_id).unwrap();
+        visualize_order("BUY", buy);
+        assert_eq!(buy.filled_quantity, dec!(8.0));
+        assert_eq!(buy.avg_fill_price, Some(dec!(101.5)));
+        assert_eq!(buy.remaining_quantity, dec!(2.0));
+        assert_eq!(buy.status, OrderStatus::PARTIAL);
+        assert_eq!(order_book.bids[&dec!(102.0)][0].avg_fill_price, Some(dec!(101.5)));
+
+        let seller = order_book.get_order(Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap()).unwrap();
+        assert_eq!(seller.filled_quantity, dec!(6.0));
+        assert_eq!(seller.avg_fill_price, Some(dec!(102.0)));
+        assert_eq!(seller.status, OrderStatus::FILLED);
+    }
+
+    #[test]
+    fn test_bbo_excluding_broker() {
+        print_separator("BBO Excluding Broker");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let broker = "00000000-0000-0000-0000-00000000000a";
+        let other = "00000000-0000-0000-0000-00000000000b";
+        for (broker_id, side, price) in [
+            (broker, OrderSide::BUY, dec!(99.5)),
+            (other, OrderSide::BUY, dec!(99.0)),
+            (broker, OrderSide::SELL, dec!(101.0)),
+            (other, OrderSide::SELL, dec!(101.0)),
+            (other, OrderSide::SELL, dec!(102.0)),
+        ] {
+            let order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                broker_id,
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        assert_eq!(order_book.bbo(), (Some(dec!(99.5)), Some(dec!(101.0))));
+
+        // The broker's own best bid hides the external 99.0; the shared ask level stays
+        let broker_id = Uuid::from_str(broker).unwrap();
+        assert_eq!(order_book.bbo_excluding_broker(broker_id), (Some(dec!(99.0)), Some(dec!(101.0))));
+
+        let other_id = Uuid::from_str(other).unwrap();
+        assert_eq!(order_book.bbo_excluding_broker(other_id), (Some(dec!(99.5)), Some(dec!(101.0))));
+        assert_eq!(order_book.bbo_excluding_broker(Uuid::new_v4()), order_book.bbo());
+    }
+
+    #[test]
+    fn test_sweep_matches_like_add_order() {
+        print_separator("Sweep");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let build_book = || {
+            let mut order_book = OrderBook::new(instrument_id);
+            for (id, price) in [
+                ("00000000-0000-0000-0000-000000000002", dec!(100.0)),
+                ("00000000-0000-0000-0000-000000000004", dec!(101.0)),
+                ("00000000-0000-0000-0000-000000000006", dec!(102.0)),
+            ] {
+                let sell_order = create_test_order(
+                    id,
+                    "00000000-0000-0000-0000-000000000003",
+                    OrderSide::SELL,
+                    OrderType::LIMIT,
+                    Some(price),
+                    dec!(2.0),
+                );
+                order_book.add_order(sell_order).unwrap();
+            }
+            order_book
+        };
+        let fills = |trades: &[Trade]| trades.iter().map(|t| (t.seller_order_id, t.price, t.quantity)).collect::<Vec<_>>();
+        let broker_id = Uuid::from_str("00000000-0000-0000-0000-000000000005").unwrap();
+
+        // Limited sweep against an equivalent IOC limit order
+        let mut swept = build_book();
+        let sweep_trades = swept.sweep(OrderSide::BUY, dec!(5.0), Some(dec!(101.0)), broker_id).unwrap();
+
+        let mut submitted = build_book();
+        let mut order = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(5.0),
+        );
+        order.time_in_force = TimeInForce::IOC;
+        let order_trades = submitted.add_order(order).unwrap();
+
+        assert_eq!(fills(&sweep_trades), fills(&order_trades));
+        assert_eq!(sweep_trades.len(), 2);
+        assert_eq!(swept.bbo(), submitted.bbo());
+        assert_eq!(swept.best_bid(), None);
+
+        // Unlimited sweep against a market order
+        let mut swept = build_book();
+        let sweep_trades = swept.sweep(OrderSide::BUY, dec!(5.0), None, broker_id).unwrap();
+
+        let mut submitted = build_book();
+        let order = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(5.0),
+        );
+        let order_trades = submitted.add_order(order).unwrap();
+
+        assert_eq!(fills(&sweep_trades), fills(&order_trades));
+        assert_eq!(sweep_trades.last().unwrap().price, dec!(102.0));
+        assert!(sweep_trades.iter().all(|t| t.buyer_broker_id == broker_id));
+    }
+
+    #[test]
+    fn test_cash_check_requires_matching_currency() {
+        print_separator("Currency-Aware Cash Check");
+
+        let instrument = Instrument {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap(),
+            symbol: "RSE".to_string(),
+            name: "Redmont Stock Exchange".to_string(),
+            r#type: InstrumentType::STOCK,
+            status: InstrumentStatus::ACTIVE,
+            lot_size: 1,
+            tick_size: dec!(0.01),
+            currency: "EUR".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let order_book = OrderBook::for_instrument(&instrument).unwrap();
+        assert_eq!(order_book.currency(), "EUR");
+        assert_eq!(order_book.notional(dec!(10.0), dec!(3.0)), Money { amount: dec!(30.0), currency: "EUR".to_string() });
+
+        let cash = |currency: &str| CashPosition {
+            id: Uuid::new_v4(),
+            broker_id: Uuid::from_str("00000000-0000-0000-0000-000000000003").unwrap(),
+            currency: currency.to_string(),
+            total_balance: dec!(1000.0),
+            locked_balance: dec!(100.0),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+
+        assert_eq!(
+            order_book.check_cash(&buy_order, &cash("RMD")).unwrap_err(),
+            OrderError::CurrencyMismatch { expected: "EUR".to_string(), found: "RMD".to_string() }
+        );
+        assert!(order_book.check_cash(&buy_order, &cash("EUR")).is_ok());
+
+        let mut too_large = buy_order.clone();
+        too_large.remaining_quantity = dec!(10.0);
+        assert_eq!(
+            order_book.check_cash(&too_large, &cash("EUR")).unwrap_err(),
+            OrderError::InsufficientFunds { required: dec!(1000.0), available: dec!(900.0) }
+        );
+    }
+
+    #[test]
+    fn test_cancel_burst_halts_until_resumed() {
+        print_separator("Anomaly Guard");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig {
+            anomaly_guard: Some(AnomalyGuard {
+                window: Duration::seconds(1),
+                baseline_windows: 5,
+                multiple: dec!(3),
+                min_activity: dec!(5),
+            }),
+            ..BookConfig::default()
+        };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+        let clock = Arc::new(MockClock::new(Utc::now()));
+        order_book.set_clock(clock.clone());
+
+        let place = |order_book: &mut OrderBook| {
+            let order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(dec!(99.0)),
+                dec!(1.0),
+            );
+            let order_id = order.id;
+            order_book.add_order(order).unwrap();
+            order_id
+        };
+
+        // One cancel a second sets a quiet baseline
+        for _ in 0..5 {
+            let order_id = place(&mut order_book);
+            order_book.cancel_order(order_id).unwrap();
+            clock.advance(Duration::seconds(1));
+        }
+        assert!(!order_book.is_halted());
+
+        // Ten cancels inside one window is far above the one-a-second average
+        let burst: Vec<Uuid> = (0..10).map(|_| place(&mut order_book)).collect();
+        for order_id in burst {
+            order_book.cancel_order(order_id).unwrap();
+        }
+        assert_eq!(order_book.halt_reason(), Some(HaltReason::Anomaly));
+
+        let rejected = create_test_order(
+            &Uuid::new_v4().to_string(),
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(1.0),
+        );
+        assert_eq!(order_book.add_order(rejected).unwrap_err(), OrderError::Halted(HaltReason::Anomaly));
+
+        order_book.resume();
+        assert!(!order_book.is_halted());
+        place(&mut order_book);
+        assert_eq!(order_book.best_bid(), Some(dec!(99.0)));
+    }
+
+    #[test]
+    fn test_order_ages_and_oldest_resting() {
+        print_separator("Resting Order Ages");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        let now = Utc::now();
+
+        for (id, side, price, age) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(99.0), Duration::seconds(30)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::SELL, dec!(101.0), Duration::minutes(10)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::BUY, dec!(98.0), Duration::seconds(5)),
+        ] {
+            let mut order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order.created_at = now - age;
+            order_book.add_order(order).unwrap();
+        }
+
+        assert_eq!(
+            order_book.order_ages(now),
+            vec![Duration::minutes(10), Duration::seconds(30), Duration::seconds(5)]
+        );
+        assert_eq!(
+            order_book.oldest_resting().unwrap().id,
+            Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap()
+        );
+
+        // Once it leaves the book the next oldest takes over
+        order_book
+            .cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap())
+            .unwrap();
+        assert_eq!(
+            order_book.oldest_resting().unwrap().id,
+            Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap()
+        );
+        assert_eq!(order_book.order_ages(now).len(), 2);
+    }
+
+    #[test]
+    fn test_reconcile_brings_both_copies_into_agreement() {
+        print_separator("Reconcile After Matching");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (id, quantity) in [
+            ("00000000-0000-0000-0000-000000000002", dec!(2.0)),
+            ("00000000-0000-0000-0000-000000000004", dec!(5.0)),
+        ] {
+            let sell_order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                quantity,
+            );
+            order_book.add_order(sell_order).unwrap();
+        }
+        let partial_id = Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap();
+
+        // Knock the two copies of the resting order out of step, the way a
+        // one-sided update during matching would
+        let stored = order_book.orders.get_mut(&partial_id).unwrap();
+        stored.remaining_quantity = dec!(0);
+        order_book.reconcile(&[partial_id]);
+
+        assert_eq!(order_book.get_order(partial_id).unwrap().status, OrderStatus::FILLED);
+        assert!(order_book.asks[&dec!(100.0)].iter().all(|o| o.id != partial_id));
+
+        // A normal sweep leaves both copies agreeing on every touched order
+        let resting = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(4.0),
+        );
+        order_book.add_order(resting).unwrap();
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(3.0),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        assert_eq!(trades.len(), 2);
+
+        for order in &order_book.asks[&dec!(100.0)] {
+            let stored = order_book.get_order(order.id).unwrap();
+            assert_eq!(stored.remaining_quantity, order.remaining_quantity);
+            assert_eq!(stored.status, order.status);
+            assert!(!order.remaining_quantity.is_zero());
+        }
+        for trade in &trades {
+            let seller = order_book.get_order(trade.seller_order_id).unwrap();
+            let expected = if seller.remaining_quantity.is_zero() { OrderStatus::FILLED } else { OrderStatus::PARTIAL };
+            assert_eq!(seller.status, expected);
+        }
+    }
+
+    #[test]
+    fn test_sequential_partial_fills_keep_both_copies_in_step() {
+        print_separator("Sequential Partial Fills");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let resting_id = Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap();
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(10.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+
+        for (quantity, expected_remaining) in [(dec!(3.0), dec!(7.0)), (dec!(4.0), dec!(3.0))] {
+            let buy_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                quantity,
+            );
+            let trades = order_book.add_order(buy_order).unwrap();
+            assert_eq!(trades.len(), 1);
+
+            let in_level = order_book.asks[&dec!(100.0)].iter().find(|o| o.id == resting_id).unwrap();
+            let in_map = order_book.get_order(resting_id).unwrap();
+            assert_eq!(in_level.remaining_quantity, expected_remaining);
+            assert_eq!(in_map.remaining_quantity, expected_remaining);
+            assert_eq!(in_map.filled_quantity, dec!(10.0) - expected_remaining);
+            assert_eq!(in_map.status, OrderStatus::PARTIAL);
+            assert_eq!(in_level.status, in_map.status);
+        }
+
+        visualize_order_book_state(&order_book);
+    }
+
+    #[test]
+    fn test_is_marketable() {
+        print_separator("Marketable At Submission");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        // Nothing to trade against yet
+        assert!(!order_book.is_marketable(OrderSide::BUY, None));
+        assert!(!order_book.is_marketable(OrderSide::SELL, None));
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+
+        assert!(order_book.is_marketable(OrderSide::BUY, Some(dec!(101.0))));
+        assert!(order_book.is_marketable(OrderSide::BUY, Some(dec!(100.0))));
+        assert!(!order_book.is_marketable(OrderSide::BUY, Some(dec!(99.0))));
+        assert!(order_book.is_marketable(OrderSide::BUY, None));
+        // No bids, so a sell can't trade at any price
+        assert!(!order_book.is_marketable(OrderSide::SELL, Some(dec!(1.0))));
+    }
+
+    #[test]
+    fn test_self_trade_skip_matches_next_counterparty() {
+        print_separator("Self-Trade Skip");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig {
+            self_trade: SelfTradePolicy::Skip,
+            ..BookConfig::default()
+        };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+
+        let broker = "00000000-0000-0000-0000-000000000003";
+        let own_sell_id = Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap();
+        let own_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            broker,
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(own_sell).unwrap();
+
+        let other_sell_id = Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap();
+        let other_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(other_sell).unwrap();
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            broker,
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(3.0),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+        visualize_order_book_state(&order_book);
+
+        // The broker's own sell at the better price is passed over
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].seller_order_id, other_sell_id);
+        assert_eq!(trades[0].price, dec!(101.0));
+        assert_eq!(trades[0].quantity, dec!(3.0));
+
+        let own_sell = order_book.get_order(own_sell_id).unwrap();
+        assert_eq!(own_sell.status, OrderStatus::PENDING);
+        assert_eq!(own_sell.remaining_quantity, dec!(5.0));
+        assert_eq!(order_book.best_ask(), Some(dec!(100.0)));
+    }
+
+    #[test]
+    fn test_structurally_eq() {
+        print_separator("Structural Equality");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(2.0),
+        );
+
+        let mut fifo = OrderBook::new(instrument_id);
+        fifo.add_order(sell_order.clone()).unwrap();
+        fifo.add_order(buy_order.clone()).unwrap();
+
+        // Pro-rata matching with batched events reaches the same resting state
+        let config = BookConfig {
+            matching_algo: MatchingAlgo::ProRata,
+            event_batch_size: Some(10),
+            ..BookConfig::default()
+        };
+        let mut pro_rata = OrderBook::with_config(instrument_id, config);
+        pro_rata.add_order(sell_order).unwrap();
+        pro_rata.add_order(buy_order).unwrap();
+
+        assert!(fifo.structurally_eq(&pro_rata));
+        assert!(pro_rata.structurally_eq(&fifo));
+
+        // One extra resting order makes them differ
+        let extra = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(1.0),
+        );
+        pro_rata.add_order(extra).unwrap();
+        assert!(!fifo.structurally_eq(&pro_rata));
+        assert!(!fifo.structurally_eq(&OrderBook::new(instrument_id)));
+    }
+
+    #[test]
+    fn test_trades_record_pre_trade_spread() {
+        print_separator("Spread At Execution");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (price, quantity) in [(dec!(101.0), dec!(2.0)), (dec!(102.0), dec!(2.0))] {
+            let sell_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(sell_order).unwrap();
+        }
+        let buy_order = create_test_order(
+            &Uuid::new_v4().to_string(),
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(1.0),
+        );
+        order_book.add_order(buy_order).unwrap();
+
+        let (bid, ask) = order_book.bbo();
+        let expected = ask.unwrap() - bid.unwrap();
+        assert_eq!(expected, dec!(2.0));
+
+        // The sweep takes out the best ask, but both fills carry the spread from before it
+        let market_buy = create_test_order(
+            &Uuid::new_v4().to_string(),
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(3.0),
+        );
+        let trades = order_book.add_order(market_buy).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        assert_eq!(trades.len(), 2);
+        assert!(trades.iter().all(|t| t.spread_at_execution == Some(expected)));
+        assert_eq!(order_book.spread(), Some(dec!(3.0)));
+    }
+
+    fn build_auction_book(allocation: MatchingAlgo) -> (OrderBook, Vec<Uuid>) {
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig {
+            auction_allocation: allocation,
+            ..BookConfig::default()
+        };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+        order_book.begin_pre_open();
+
+        // Three sellers at the clearing price, queued in this order
+        let mut sell_ids = Vec::new();
+        for quantity in [dec!(4.0), dec!(4.0), dec!(2.0)] {
+            let sell_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                quantity,
+            );
+            sell_ids.push(sell_order.id);
+            assert!(order_book.add_order(sell_order).unwrap().is_empty());
+        }
+
+        // Only 5 wanted at 100; the bid at 101 crosses but does not trade yet
+        for (price, quantity) in [(dec!(101.0), dec!(3.0)), (dec!(100.0), dec!(2.0))] {
+            let buy_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            assert!(order_book.add_order(buy_order).unwrap().is_empty());
+        }
+
+        (order_book, sell_ids)
+    }
+
+    fn auction_fills(order_book: &OrderBook, order_ids: &[Uuid]) -> Vec<Decimal> {
+        order_ids
+            .iter()
+            .map(|id| order_book.get_order(*id).unwrap().filled_quantity)
+            .collect()
+    }
+
+    #[test]
+    fn test_opening_auction_time_priority_allocation() {
+        print_separator("Opening Auction, Time Priority");
+
+        let (mut order_book, sell_ids) = build_auction_book(MatchingAlgo::Fifo);
+        assert_eq!(order_book.trading_phase(), TradingPhase::PreOpen);
+        assert!(order_book.trades().is_empty());
+
+        let (price, trades) = order_book.run_opening_auction().unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(price, Some(dec!(100.0)));
+        assert!(trades.iter().all(|t| t.price == dec!(100.0)));
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<Decimal>(), dec!(5.0));
+        assert_eq!(auction_fills(&order_book, &sell_ids), vec![dec!(4.0), dec!(1.0), dec!(0.0)]);
+        assert_eq!(order_book.trading_phase(), TradingPhase::Continuous);
+        assert_eq!(order_book.bbo(), (None, Some(dec!(100.0))));
+        assert_eq!(order_book.get_order(sell_ids[0]).unwrap().status, OrderStatus::FILLED);
+        assert_eq!(order_book.get_order(sell_ids[1]).unwrap().status, OrderStatus::PARTIAL);
+    }
+
+    #[test]
+    fn test_opening_auction_pro_rata_allocation() {
+        print_separator("Opening Auction, Pro-Rata");
+
+        let (mut order_book, sell_ids) = build_auction_book(MatchingAlgo::ProRata);
+
+        // Market orders have nothing to execute against until the auction runs
+        let market_buy = create_test_order(
+            &Uuid::new_v4().to_string(),
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(1.0),
+        );
+        assert_eq!(order_book.add_order(market_buy).unwrap_err(), OrderError::NoContinuousMatchingInAuction);
+
+        let (price, trades) = order_book.run_opening_auction().unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        assert_eq!(price, Some(dec!(100.0)));
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<Decimal>(), dec!(5.0));
+        // 5 shared 4:4:2
+        let fills = auction_fills(&order_book, &sell_ids);
+        assert_eq!(fills, vec![dec!(2.0), dec!(2.0), dec!(1.0)]);
+        assert_eq!(fills.iter().sum::<Decimal>(), dec!(5.0));
+        assert_eq!(order_book.best_bid(), None);
+
+        // Continuous matching is back on
+        let buy_order = create_test_order(
+            &Uuid::new_v4().to_string(),
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(1.0),
+        );
+        assert_eq!(order_book.add_order(buy_order).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_opening_auction_without_a_cross() {
+        print_separator("Opening Auction Without A Cross");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.begin_pre_open();
+        for (side, price) in [(OrderSide::BUY, dec!(99.0)), (OrderSide::SELL, dec!(100.0))] {
+            let order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        let (price, trades) = order_book.run_opening_auction().unwrap();
+        assert_eq!(price, None);
+        assert!(trades.is_empty());
+        assert_eq!(order_book.bbo(), (Some(dec!(99.0)), Some(dec!(100.0))));
+        assert_eq!(order_book.trading_phase(), TradingPhase::Continuous);
+    }
+
+    #[test]
+    fn test_spread_bps_is_comparable_across_price_levels() {
+        print_separator("Spread In Basis Points");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+
+        let book_with_quotes = |bid: Decimal, ask: Decimal| {
+            let mut order_book = OrderBook::new(instrument_id);
+            for (side, price) in [(OrderSide::BUY, bid), (OrderSide::SELL, ask)] {
+                let order = create_test_order(
+                    &Uuid::new_v4().to_string(),
+                    "00000000-0000-0000-0000-000000000003",
+                    side,
+                    OrderType::LIMIT,
+                    Some(price),
+                    dec!(1.0),
+                );
+                order_book.add_order(order).unwrap();
+            }
+            order_book
+        };
+
+        // A 1.0 spread around 100 and a 0.1 spread around 10 are both 100 bps wide
+        let wide_expensive = book_with_quotes(dec!(99.5), dec!(100.5));
+        let wide_cheap = book_with_quotes(dec!(9.95), dec!(10.05));
+        assert_eq!(wide_expensive.spread_bps(), Some(dec!(100)));
+        assert_eq!(wide_cheap.spread_bps(), Some(dec!(100)));
+        assert_ne!(wide_expensive.spread(), wide_cheap.spread());
+
+        // Tight: 0.1 around 100 is 10 bps, 0.01 around 10 likewise
+        let tight_expensive = book_with_quotes(dec!(99.95), dec!(100.05));
+        let tight_cheap = book_with_quotes(dec!(9.995), dec!(10.005));
+        assert_eq!(tight_expensive.spread_bps(), Some(dec!(10)));
+        assert_eq!(tight_cheap.spread_bps(), Some(dec!(10)));
+
+        // One-sided, or a zero mid
+        let mut one_sided = OrderBook::new(instrument_id);
+        let buy_order = create_test_order(
+            &Uuid::new_v4().to_string(),
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(1.0),
+        );
+        one_sided.add_order(buy_order).unwrap();
+        assert_eq!(one_sided.spread_bps(), None);
+        assert_eq!(book_with_quotes(dec!(-1.0), dec!(1.0)).spread_bps(), None);
+    }
+
+    #[test]
+    fn test_health_check_on_a_clean_book() {
+        print_separator("Health Check, Clean Book");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        assert!(order_book.health_check().is_empty());
+
+        for (side, price, quantity) in [
+            (OrderSide::SELL, dec!(101.0), dec!(5.0)),
+            (OrderSide::BUY, dec!(99.0), dec!(5.0)),
+            (OrderSide::BUY, dec!(101.0), dec!(2.0)),
+        ] {
+            let order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        assert!(order_book.health_check().is_empty());
+    }
+
+    #[test]
+    fn test_health_check_reports_corruption() {
+        print_separator("Health Check, Corrupted Book");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let bid_id = Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap();
+        let ask_id = Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap();
+        for (id, side, price) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(99.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::SELL, dec!(101.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(5.0),
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        // Cross the book with a bid the map never heard of, leave an empty
+        // level behind, and let the resting ask's copies drift apart
+        let mut stray = order_book.bids[&dec!(99.0)][0].clone();
+        stray.id = Uuid::from_str("00000000-0000-0000-0000-000000000006").unwrap();
+        order_book.bids.insert(dec!(102.0), vec![stray.clone()]);
+        order_book.asks.insert(dec!(103.0), Vec::new());
+        order_book.orders.get_mut(&ask_id).unwrap().remaining_quantity = dec!(0);
+        order_book.bids.remove(&dec!(99.0));
+
+        let violations = order_book.health_check();
+        for violation in &violations {
+            println!("{:?}", violation);
+        }
+
+        assert_eq!(
+            violations,
+            vec![
+                BookInvariantViolation::Crossed {
+                    bid: dec!(102.0),
+                    ask: dec!(101.0),
+                },
+                BookInvariantViolation::Untracked(stray.id),
+                BookInvariantViolation::CopiesDiffer(ask_id),
+                BookInvariantViolation::EmptyLevel {
+                    side: OrderSide::SELL,
+                    price: dec!(103.0),
+                },
+                BookInvariantViolation::NotResting(bid_id),
+                BookInvariantViolation::StatusMismatch {
+                    order_id: ask_id,
+                    status: OrderStatus::PENDING,
+                    remaining: dec!(0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quote_and_requote() {
+        print_separator("Market-Maker Quotes");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        let maker = Uuid::from_str("00000000-0000-0000-0000-000000000003").unwrap();
+
+        let trades = order_book.quote(maker, dec!(99.0), dec!(5.0), dec!(101.0), dec!(5.0)).unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(order_book.bbo(), (Some(dec!(99.0)), Some(dec!(101.0))));
+
+        // The new quote replaces the old one on both sides
+        let trades = order_book.quote(maker, dec!(99.5), dec!(3.0), dec!(100.5), dec!(4.0)).unwrap();
+        assert!(trades.is_empty());
+        visualize_order_book_state(&order_book);
+
+        assert_eq!(order_book.bbo(), (Some(dec!(99.5)), Some(dec!(100.5))));
+        assert!(!order_book.bids.contains_key(&dec!(99.0)));
+        assert!(!order_book.asks.contains_key(&dec!(101.0)));
+        assert_eq!(order_book.bids[&dec!(99.5)][0].remaining_quantity, dec!(3.0));
+        assert_eq!(order_book.asks[&dec!(100.5)][0].remaining_quantity, dec!(4.0));
+
+        // A quote the book refuses leaves the current one in place
+        order_book.tick_size = Some(dec!(0.5));
+        assert!(order_book.quote(maker, dec!(99.3), dec!(1.0), dec!(100.5), dec!(1.0)).is_err());
+        assert_eq!(order_book.bbo(), (Some(dec!(99.5)), Some(dec!(100.5))));
+    }
+
+    #[test]
+    fn test_quote_trades_against_existing_flow() {
+        print_separator("Marketable Quote");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        let maker = Uuid::from_str("00000000-0000-0000-0000-000000000003").unwrap();
+
+        let resting_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(2.0),
+        );
+        order_book.add_order(resting_sell).unwrap();
+
+        let trades = order_book.quote(maker, dec!(100.0), dec!(5.0), dec!(102.0), dec!(5.0)).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].buyer_broker_id, maker);
+        assert_eq!(trades[0].quantity, dec!(2.0));
+        assert_eq!(order_book.bbo(), (Some(dec!(100.0)), Some(dec!(102.0))));
+        assert_eq!(order_book.bids[&dec!(100.0)][0].remaining_quantity, dec!(3.0));
+    }
+
+    #[test]
+    fn test_time_weighted_avg_spread() {
+        print_separator("Time-Weighted Average Spread");
+
+        let start = Utc::now();
+        let clock = Arc::new(MockClock::new(start));
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.set_clock(clock.clone());
+
+        let add_order = |order_book: &mut OrderBook, side: OrderSide, price: Decimal| {
+            let order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            let order_id = order.id;
+            order_book.add_order(order).unwrap();
+            order_id
+        };
+
+        // One-sided for 10s, which does not count
+        add_order(&mut order_book, OrderSide::BUY, dec!(99.0));
+        clock.advance(Duration::seconds(10));
+
+        // Spread 2 for 30s
+        let ask_id = add_order(&mut order_book, OrderSide::SELL, dec!(101.0));
+        clock.advance(Duration::seconds(30));
+
+        // Spread 1 for 10s
+        add_order(&mut order_book, OrderSide::SELL, dec!(100.0));
+        clock.advance(Duration::seconds(10));
+
+        // Cancelling the wider ask changes nothing at the top
+        order_book.cancel_order(ask_id).unwrap();
+        clock.advance(Duration::seconds(20));
+
+        // (2 * 30 + 1 * 30) / 60
+        assert_eq!(order_book.time_weighted_avg_spread(start), Some(dec!(1.5)));
+        // From 20s in: (2 * 20 + 1 * 30) / 50
+        assert_eq!(order_book.time_weighted_avg_spread(start + Duration::seconds(20)), Some(dec!(1.4)));
+        assert_eq!(order_book.time_weighted_avg_spread(clock.now()), None);
+        assert_eq!(OrderBook::new(instrument_id).time_weighted_avg_spread(start), None);
+    }
+
+    #[test]
+    fn test_cancel_best() {
+        print_separator("Cancel Best");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        assert!(order_book.cancel_best(OrderSide::BUY).is_none());
+
+        let mut bid_ids = Vec::new();
+        for price in [dec!(100.0), dec!(100.0), dec!(99.0)] {
+            let buy_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            bid_ids.push(buy_order.id);
+            order_book.add_order(buy_order).unwrap();
+        }
+
+        // Earliest at the best price goes first, leaving the next in line at the top
+        let cancelled = order_book.cancel_best(OrderSide::BUY).unwrap();
+        assert_eq!(cancelled.id, bid_ids[0]);
+        assert_eq!(cancelled.status, OrderStatus::CANCELLED);
+        assert_eq!(order_book.best_bid(), Some(dec!(100.0)));
+        assert_eq!(order_book.bids[&dec!(100.0)][0].id, bid_ids[1]);
+
+        // Emptying the top level removes it
+        assert_eq!(order_book.cancel_best(OrderSide::BUY).unwrap().id, bid_ids[1]);
+        assert!(!order_book.bids.contains_key(&dec!(100.0)));
+        assert_eq!(order_book.best_bid(), Some(dec!(99.0)));
+        assert!(order_book.cancel_best(OrderSide::SELL).is_none());
+    }
+
+    #[test]
+    fn test_trade_stats_over_a_window() {
+        print_separator("Trade Stats");
+
+        let start = Utc::now();
+        let clock = Arc::new(MockClock::new(start));
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.set_clock(clock.clone());
+
+        // A trade every minute: 1 @ 90, then 2 @ 100, 1 @ 103, 1 @ 98
+        for (price, quantity) in [
+            (dec!(90.0), dec!(1.0)),
+            (dec!(100.0), dec!(2.0)),
+            (dec!(103.0), dec!(1.0)),
+            (dec!(98.0), dec!(1.0)),
+        ] {
+            let sell_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(sell_order).unwrap();
+            let buy_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::MARKET,
+                None,
+                quantity,
+            );
+            assert_eq!(order_book.add_order(buy_order).unwrap().len(), 1);
+            clock.advance(Duration::minutes(1));
+        }
+
+        // The last three minutes leave out the trade at 90
+        let now = start + Duration::minutes(3);
+        let stats = order_book.trade_stats(Duration::minutes(3), now);
+        assert_eq!(
+            stats,
+            TradeStats {
+                count: 3,
+                volume: dec!(4.0),
+                // (200 + 103 + 98) / 4
+                vwap: Some(dec!(100.25)),
+                high: Some(dec!(103.0)),
+                low: Some(dec!(98.0)),
+            }
+        );
+
+        // Nothing traded in the window
+        let stats = order_book.trade_stats(Duration::minutes(5), start + Duration::minutes(10));
+        assert_eq!(stats, TradeStats::default());
+        assert_eq!(stats.vwap, None);
+    }
+
+    fn build_amend_book() -> (OrderBook, Vec<Uuid>) {
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        // Three bids queued at 100 and one already waiting at each of 101 and 99
+        let mut ids = Vec::new();
+        for price in [dec!(100.0), dec!(100.0), dec!(100.0), dec!(101.0), dec!(99.0)] {
+            let buy_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(5.0),
+            );
+            ids.push(buy_order.id);
+            order_book.add_order(buy_order).unwrap();
+        }
+        (order_book, ids)
+    }
+
+    #[test]
+    fn test_amend_size_reduction_keeps_priority() {
+        print_separator("Amend: Size Reduction");
+
+        let (mut order_book, ids) = build_amend_book();
+        assert_eq!(order_book.queue_positions(OrderSide::BUY)[&ids[0]], 0);
+
+        let trades = order_book.amend_order(ids[0], None, Some(dec!(2.0))).unwrap();
+        assert!(trades.is_empty());
+
+        let positions = order_book.queue_positions(OrderSide::BUY);
+        assert_eq!((positions[&ids[0]], positions[&ids[1]], positions[&ids[2]]), (0, 1, 2));
+        let amended = order_book.get_order(ids[0]).unwrap();
+        assert_eq!(amended.remaining_quantity, dec!(2.0));
+        assert_eq!(amended.original_quantity, dec!(2.0));
+        assert_eq!(order_book.bids[&dec!(100.0)][0].remaining_quantity, dec!(2.0));
+
+        // Growing the order costs its place
+        order_book.amend_order(ids[0], None, Some(dec!(6.0))).unwrap();
+        let positions = order_book.queue_positions(OrderSide::BUY);
+        assert_eq!((positions[&ids[1]], positions[&ids[2]], positions[&ids[0]]), (0, 1, 2));
+    }
+
+    #[test]
+    fn test_amend_price_improvement_resets_priority() {
+        print_separator("Amend: Price Improvement");
+
+        let (mut order_book, ids) = build_amend_book();
+
+        order_book.amend_order(ids[0], Some(dec!(101.0)), None).unwrap();
+        visualize_order_book_state(&order_book);
+
+        // Joins 101 behind the order already there
+        let positions = order_book.queue_positions(OrderSide::BUY);
+        assert_eq!(positions[&ids[3]], 0);
+        assert_eq!(positions[&ids[0]], 1);
+        assert_eq!(positions[&ids[1]], 0);
+        assert_eq!(order_book.bids[&dec!(100.0)].len(), 2);
+        assert_eq!(order_book.get_order(ids[0]).unwrap().price, Some(dec!(101.0)));
+    }
+
+    #[test]
+    fn test_amend_price_worsening_moves_to_back() {
+        print_separator("Amend: Price Worsening");
+
+        let (mut order_book, ids) = build_amend_book();
+
+        order_book.amend_order(ids[1], Some(dec!(99.0)), None).unwrap();
+
+        let positions = order_book.queue_positions(OrderSide::BUY);
+        assert_eq!(positions[&ids[4]], 0);
+        assert_eq!(positions[&ids[1]], 1);
+        assert_eq!((positions[&ids[0]], positions[&ids[2]]), (0, 1));
+
+        // Terminal orders and non-positive sizes are refused
+        assert_eq!(
+            order_book.amend_order(ids[1], None, Some(dec!(0))).unwrap_err(),
+            OrderError::InvalidQuantity(dec!(0))
+        );
+        order_book.cancel_order(ids[1]).unwrap();
+        assert_eq!(order_book.amend_order(ids[1], None, Some(dec!(1.0))).unwrap_err(), OrderError::NotAmendable(ids[1]));
+    }
+
+    #[test]
+    fn test_amend_into_the_book_trades() {
+        print_separator("Amend: Marketable Price");
+
+        let (mut order_book, ids) = build_amend_book();
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(102.0)),
+            dec!(2.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+
+        let trades = order_book.amend_order(ids[2], Some(dec!(102.0)), None).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].buyer_order_id, ids[2]);
+
+        let amended = order_book.get_order(ids[2]).unwrap();
+        assert_eq!(amended.status, OrderStatus::PARTIAL);
+        assert_eq!(amended.remaining_quantity, dec!(3.0));
+        assert_eq!(order_book.best_bid(), Some(dec!(102.0)));
+        assert!(order_book.health_check().is_empty());
+    }
+
+    #[test]
+    fn test_maker_quotes_must_not_lock_the_market() {
+        print_separator("Minimum Quote Distance");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig {
+            min_quote_distance_ticks: Some(1),
+            ..BookConfig::default()
+        };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+        order_book.tick_size = Some(dec!(0.5));
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(buy_order).unwrap();
+
+        let maker_ask = |price: Decimal| {
+            let mut order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order.tags.push(MARKET_MAKER_TAG.to_string());
+            order
+        };
+
+        assert_eq!(
+            order_book.add_order(maker_ask(dec!(100.0))).unwrap_err(),
+            OrderError::WouldLockMarket {
+                price: dec!(100.0),
+                touch: dec!(100.0),
+            }
+        );
+        assert!(order_book.add_order(maker_ask(dec!(100.5))).unwrap().is_empty());
+        assert_eq!(order_book.bbo(), (Some(dec!(100.0)), Some(dec!(100.5))));
+
+        // Untagged flow may still take the bid
+        let sell_order = create_test_order(
+            &Uuid::new_v4().to_string(),
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(1.0),
+        );
+        assert_eq!(order_book.add_order(sell_order).unwrap().len(), 1);
+
+        // Two-sided quotes are tagged, so the same rule applies
+        let maker = Uuid::new_v4();
+        assert!(order_book.quote(maker, dec!(99.0), dec!(1.0), dec!(101.0), dec!(1.0)).unwrap().is_empty());
+        assert_eq!(
+            order_book.quote(maker, dec!(99.0), dec!(1.0), dec!(100.0), dec!(1.0)).unwrap_err(),
+            OrderError::WouldLockMarket {
+                price: dec!(100.0),
+                touch: dec!(100.0),
+            }
+        );
+
+        // The refused requote left the previous quote standing on both sides
+        assert_eq!(order_book.best_resting_for_broker(maker, OrderSide::BUY), Some((dec!(99.0), dec!(1.0))));
+        assert_eq!(order_book.best_resting_for_broker(maker, OrderSide::SELL), Some((dec!(101.0), dec!(1.0))));
+        assert_eq!(order_book.orders_by_tag(MARKET_MAKER_TAG).len(), 3);
+    }
+
+    #[test]
+    fn test_estimate_total_cost_includes_taker_fees() {
+        print_separator("All-In Cost Estimate");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for (side, price, quantity) in [
+            (OrderSide::SELL, dec!(100.0), dec!(2.0)),
+            (OrderSide::SELL, dec!(101.0), dec!(3.0)),
+            (OrderSide::BUY, dec!(99.0), dec!(4.0)),
+        ] {
+            let order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            order_book.add_order(order).unwrap();
+        }
+
+        let fill = order_book.estimate_market_fill(OrderSide::BUY, dec!(4.0));
+        assert_eq!(fill.filled_quantity, dec!(4.0));
+        assert_eq!(fill.notional, dec!(402.0));
+        assert_eq!(fill.avg_price, Some(dec!(100.5)));
+        assert_eq!(fill.worst_price, Some(dec!(101.0)));
+
+        let schedule = FeeSchedule {
+            maker_bps: dec!(10),
+            taker_bps: dec!(25),
+            decimal_places: 2,
+            rounding: RoundingMode::HalfUp,
+            currency: DEFAULT_CURRENCY.to_string(),
+        };
+
+        // 0.25% of 402 = 1.005, rounded half-up to 1.01
+        let cost = order_book.estimate_total_cost(OrderSide::BUY, dec!(4.0), &schedule);
+        assert_eq!(cost.notional, fill.notional);
+        assert_eq!(cost.fees, dec!(1.01));
+        assert_eq!(cost.all_in, cost.notional + cost.fees);
+
+        // Selling receives the notional less fees; only 4 of 10 can fill
+        let cost = order_book.estimate_total_cost(OrderSide::SELL, dec!(10.0), &schedule);
+        assert_eq!(cost.notional, dec!(396.0));
+        assert_eq!(cost.fees, dec!(0.99));
+        assert_eq!(cost.all_in, dec!(395.01));
+
+        // Nothing is touched by estimating
+        assert_eq!(order_book.bbo(), (Some(dec!(99.0)), Some(dec!(100.0))));
+        assert!(order_book.trades().is_empty());
+    }
+
+    #[test]
+    fn test_depth_prices_at_display_scale() {
+        print_separator("Depth Display Scale");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        for price in [dec!(100), dec!(100.5)] {
+            let sell_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order_book.add_order(sell_order).unwrap();
+        }
+
+        // Without a tick size prices show as stored
+        let shown = |order_book: &OrderBook| -> Vec<String> {
+            order_book.depth(5).asks.iter().map(|level| level.price.to_string()).collect()
+        };
+        assert_eq!(order_book.display_scale(), None);
+        assert_eq!(shown(&order_book), vec!["100", "100.5"]);
+
+        order_book.tick_size = Some(dec!(0.01));
+        assert_eq!(order_book.display_scale(), Some(2));
+        assert_eq!(shown(&order_book), vec!["100.00", "100.50"]);
+
+        order_book.set_display_scale(Some(3));
+        assert_eq!(shown(&order_book), vec!["100.000", "100.500"]);
+
+        // A scale too small for a price never rounds it
+        order_book.set_display_scale(Some(0));
+        assert_eq!(shown(&order_book), vec!["100", "100.5"]);
+
+        // Stored prices are untouched and still compare equal
+        assert_eq!(order_book.depth(5).asks[0].price, dec!(100));
+        assert_eq!(order_book.asks.keys().next().unwrap().to_string(), "100");
+    }
+
+    #[test]
+    fn test_cancelled_orders_record_why() {
+        print_separator("Cancel Reasons");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let gtc_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(99.0)),
+            dec!(1.0),
+        );
+        let mut day_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(98.0)),
+            dec!(1.0),
+        );
+        day_order.time_in_force = TimeInForce::DAY;
+        let mut ioc_order = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(1.0),
+        );
+        ioc_order.time_in_force = TimeInForce::IOC;
+        let (gtc_id, day_id, ioc_id) = (gtc_order.id, day_order.id, ioc_order.id);
+
+        order_book.add_order(gtc_order).unwrap();
+        order_book.add_order(day_order).unwrap();
+        order_book.add_order(ioc_order).unwrap();
+        assert_eq!(order_book.get_order(gtc_id).unwrap().cancel_reason, None);
+
+        let cancelled = order_book.cancel_order(gtc_id).unwrap();
+        assert_eq!(cancelled.cancel_reason, Some(CancelReason::UserRequested));
+        assert_eq!(order_book.get_order(gtc_id).unwrap().cancel_reason, Some(CancelReason::UserRequested));
+
+        let expired = order_book.close_session();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].cancel_reason, Some(CancelReason::Expiry));
+        assert_eq!(order_book.get_order(day_id).unwrap().cancel_reason, Some(CancelReason::Expiry));
+
+        assert_eq!(order_book.get_order(ioc_id).unwrap().cancel_reason, Some(CancelReason::ImmediateOrCancel));
+    }
+
+    #[test]
+    fn test_add_order_detailed_reports_sweep_stats() {
+        print_separator("Detailed Execution Report");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        // Two sellers at 100 and one at 101
+        for (broker, price) in [
+            ("00000000-0000-0000-0000-000000000003", dec!(100.0)),
+            ("00000000-0000-0000-0000-000000000005", dec!(100.0)),
+            ("00000000-0000-0000-0000-000000000003", dec!(101.0)),
+        ] {
+            let sell_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                broker,
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(2.0),
+            );
+            order_book.add_order(sell_order).unwrap();
+        }
+
+        let buy_order = create_test_order(
+            &Uuid::new_v4().to_string(),
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(5.0),
+        );
+        let report = order_book.add_order_detailed(buy_order).unwrap();
+        for trade in &report.trades {
+            visualize_trade(trade);
+        }
+
+        assert_eq!(report.trades.len(), 3);
+        assert_eq!(report.levels_swept, 2);
+        assert_eq!(report.counterparties, 3);
+        // 2 * 100 + 2 * 100 + 1 * 101
+        assert_eq!(report.notional, dec!(501.0));
+
+        // A resting order reports nothing matched
+        let buy_order = create_test_order(
+            &Uuid::new_v4().to_string(),
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(90.0)),
+            dec!(1.0),
+        );
+        let report = order_book.add_order_detailed(buy_order).unwrap();
+        assert_eq!((report.levels_swept, report.counterparties), (0, 0));
+        assert!(report.notional.is_zero());
+    }
+
+    #[test]
+    fn test_trades_carry_the_aggressor_memo() {
+        print_separator("Trade Memo");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let mut sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(3.0),
+        );
+        // The resting side's memo is not what the trade reports
+        sell_order.memo = Some("passive".to_string());
+        order_book.add_order(sell_order).unwrap();
+
+        let mut tagged_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(1.0),
+        );
+        tagged_buy.memo = Some("REG-2025-0042".to_string());
+        let trades = order_book.add_order(tagged_buy).unwrap();
+        assert_eq!(trades[0].memo.as_deref(), Some("REG-2025-0042"));
+
+        let untagged_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(1.0),
+        );
+        let trades = order_book.add_order(untagged_buy).unwrap();
+        assert_eq!(trades[0].memo, None);
+        assert_eq!(order_book.trades()[0].memo.as_deref(), Some("REG-2025-0042"));
+    }
+
+    #[test]
+    fn test_submit_market_outcomes() {
+        print_separator("Market Order Outcomes");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        let market_buy = |quantity: Decimal| {
+            create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::MARKET,
+                None,
+                quantity,
+            )
+        };
+
+        let outcome = order_book.submit_market(market_buy(dec!(1.0))).unwrap();
+        assert_eq!(outcome.status, OrderStatus::REJECTED);
+        assert_eq!(outcome.reason, Some(MarketOrderReason::NoLiquidity));
+        assert!(outcome.filled_quantity.is_zero());
+        assert!(outcome.trades.is_empty());
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(3.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+
+        let outcome = order_book.submit_market(market_buy(dec!(2.0))).unwrap();
+        assert_eq!(outcome.status, OrderStatus::FILLED);
+        assert_eq!(outcome.reason, None);
+        assert_eq!(outcome.filled_quantity, dec!(2.0));
+        assert_eq!(outcome.trades.len(), 1);
+
+        // One left to take, four wanted
+        let outcome = order_book.submit_market(market_buy(dec!(4.0))).unwrap();
+        assert_eq!(outcome.status, OrderStatus::REJECTED);
+        assert_eq!(outcome.reason, Some(MarketOrderReason::LiquidityExhausted));
+        assert_eq!(outcome.filled_quantity, dec!(1.0));
+        assert_eq!(outcome.trades.len(), 1);
+    }
+
+    #[test]
+    fn test_settlement_print_ignores_individual_limits() {
+        print_separator("Trade At Settlement Print");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut book = OrderBook::new(instrument_id);
+
+        let tas = |id: &str, broker: &str, side: OrderSide, price: Option<Decimal>, qty: Decimal| Order {
+            order_type: OrderType::TAS,
+            price,
+            ..create_test_order(id, broker, side, OrderType::LIMIT, Some(dec!(1)), qty)
+        };
+        // Limits on either side of the eventual settlement price do not matter
+        let buy_low = tas("00000000-0000-0000-0000-000000000001", "00000000-0000-0000-0000-00000000000a", OrderSide::BUY, Some(dec!(90)), dec!(4));
+        let buy_any = tas("00000000-0000-0000-0000-000000000002", "00000000-0000-0000-0000-00000000000b", OrderSide::BUY, None, dec!(6));
+        let sell_high = tas("00000000-0000-0000-0000-000000000003", "00000000-0000-0000-0000-00000000000c", OrderSide::SELL, Some(dec!(110)), dec!(5));
+        let sell_any = tas("00000000-0000-0000-0000-000000000004", "00000000-0000-0000-0000-00000000000d", OrderSide::SELL, None, dec!(3));
+        let ids = [buy_low.id, buy_any.id, sell_high.id, sell_any.id];
+
+        for order in [buy_low, buy_any, sell_high, sell_any] {
+            assert!(book.add_order(order).unwrap().is_empty());
+        }
+        // A plain limit order is never matched against the waiting TAS orders
+        let resting = create_test_order("00000000-0000-0000-0000-000000000005", "00000000-0000-0000-0000-00000000000e", OrderSide::SELL, OrderType::LIMIT, Some(dec!(95)), dec!(2));
+        assert!(book.add_order(resting.clone()).unwrap().is_empty());
+        assert_eq!(book.bbo(), (None, Some(dec!(95))));
+        assert!(book.health_check().is_empty());
+
+        let trades = book.run_settlement_print(dec!(100)).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+        visualize_order_book_state(&book);
+
+        assert!(trades.iter().all(|t| t.price == dec!(100)));
+        let fills: Vec<(Uuid, Uuid, Decimal)> = trades.iter().map(|t| (t.buyer_order_id, t.seller_order_id, t.quantity)).collect();
+        assert_eq!(fills, vec![(ids[0], ids[2], dec!(4)), (ids[1], ids[2], dec!(1)), (ids[1], ids[3], dec!(3))]);
+
+        assert_eq!(book.get_order(ids[0]).unwrap().status, OrderStatus::FILLED);
+        assert_eq!(book.get_order(ids[2]).unwrap().status, OrderStatus::FILLED);
+        assert_eq!(book.get_order(ids[3]).unwrap().status, OrderStatus::FILLED);
+        // The two lots left over were only good for this print
+        let leftover = book.get_order(ids[1]).unwrap();
+        assert_eq!((leftover.status.clone(), leftover.remaining_quantity), (OrderStatus::CANCELLED, dec!(2)));
+        assert_eq!(leftover.cancel_reason, Some(CancelReason::Expiry));
+
+        assert_eq!(book.get_order(resting.id).unwrap().status, OrderStatus::PENDING);
+        assert!(book.run_settlement_print(dec!(100)).unwrap().is_empty());
+        assert!(book.health_check().is_empty());
+    }
+
+    #[test]
+    fn test_depth_after_sweep_leaves_book_untouched() {
+        print_separator("Depth After Sweep");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut book = OrderBook::new(instrument_id);
+
+        let resting = [
+            ("00000000-0000-0000-0000-000000000001", OrderSide::SELL, dec!(101), dec!(3)),
+            ("00000000-0000-0000-0000-000000000002", OrderSide::SELL, dec!(102), dec!(2)),
+            ("00000000-0000-0000-0000-000000000003", OrderSide::SELL, dec!(102), dec!(4)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::SELL, dec!(103), dec!(5)),
+            ("00000000-0000-0000-0000-000000000005", OrderSide::BUY, dec!(99), dec!(7)),
+        ];
+        for (id, side, price, qty) in resting {
+            book.add_order(create_test_order(id, "00000000-0000-0000-0000-00000000000a", side, OrderType::LIMIT, Some(price), qty))
+                .unwrap();
+        }
+        visualize_order_book_state(&book);
+        let before = book.depth(usize::MAX);
+
+        // 3 clears 101, 2 + 1 of the 4 behind it at 102
+        let after = book.depth_after_sweep(OrderSide::BUY, dec!(6));
+        let level = |price, quantity, order_count| DepthLevel { price, quantity, order_count };
+        assert_eq!(after.asks, vec![level(dec!(102), dec!(3), 1), level(dec!(103), dec!(5), 1)]);
+        assert_eq!(after.bids, before.bids);
+
+        // Sweeping more than the side holds leaves it empty
+        assert!(book.depth_after_sweep(OrderSide::SELL, dec!(50)).bids.is_empty());
+        assert_eq!(book.depth_after_sweep(OrderSide::BUY, Decimal::ZERO), before);
+
+        // Nothing actually traded
+        assert_eq!(book.depth(usize::MAX), before);
+        assert!(book.trades().is_empty());
+    }
+
+    // Tracks what each broker has locked, as the cash and inventory
+    // services would, so releases can be checked against it. Further locks
+    // are refused past `cash_limit`, when one is set.
+    #[derive(Debug, Default)]
+    struct LockingBalances {
+        cash: Mutex<HashMap<Uuid, Decimal>>,
+        inventory: Mutex<HashMap<(Uuid, Uuid), Decimal>>,
+        cash_limit: Option<Decimal>,
+    }
+
+    impl LockingBalances {
+        fn locked_cash(&self, broker_id: Uuid) -> Decimal {
+            self.cash.lock().unwrap().get(&broker_id).copied().unwrap_or_default()
+        }
+
+        fn locked_inventory(&self, broker_id: Uuid, instrument_id: Uuid) -> Decimal {
+            self.inventory.lock().unwrap().get(&(broker_id, instrument_id)).copied().unwrap_or_default()
+        }
+
+        fn lock_for(&self, order: &Order) {
+            match order.side {
+                OrderSide::BUY => {
+                    *self.cash.lock().unwrap().entry(order.broker_id).or_default() += order.price.unwrap() * order.remaining_quantity
+                }
+                OrderSide::SELL => {
+                    *self.inventory.lock().unwrap().entry((order.broker_id, order.instrument_id)).or_default() +=
+                        order.remaining_quantity
+                }
+            }
+        }
+    }
+
+    impl BalanceProvider for LockingBalances {
+        fn release_cash(&self, broker_id: Uuid, currency: &str, amount: Decimal) {
+            assert_eq!(currency, DEFAULT_CURRENCY);
+            *self.cash.lock().unwrap().entry(broker_id).or_default() -= amount;
+        }
+
+        fn release_inventory(&self, broker_id: Uuid, instrument_id: Uuid, quantity: Decimal) {
+            *self.inventory.lock().unwrap().entry((broker_id, instrument_id)).or_default() -= quantity;
+        }
+
+        fn lock_cash(&self, broker_id: Uuid, currency: &str, amount: Decimal) -> bool {
+            assert_eq!(currency, DEFAULT_CURRENCY);
+            let mut cash = self.cash.lock().unwrap();
+            let locked = cash.entry(broker_id).or_default();
+            if self.cash_limit.is_some_and(|limit| *locked + amount > limit) {
+                return false;
+            }
+            *locked += amount;
+            true
+        }
+
+        fn lock_inventory(&self, broker_id: Uuid, instrument_id: Uuid, quantity: Decimal) -> bool {
+            *self.inventory.lock().unwrap().entry((broker_id, instrument_id)).or_default() += quantity;
+            true
+        }
+    }
+
+    #[test]
+    fn test_cancel_and_reduce_release_locked_balances() {
+        print_separator("Releasing Locked Balances");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut book = OrderBook::new(instrument_id);
+        let balances = Arc::new(LockingBalances::default());
+        book.set_balance_provider(balances.clone());
+
+        let broker = Uuid::from_str("00000000-0000-0000-0000-00000000000a").unwrap();
+        let mut buy = create_test_order("00000000-0000-0000-0000-000000000002", "00000000-0000-0000-0000-00000000000a", OrderSide::BUY, OrderType::LIMIT, Some(dec!(50)), dec!(10));
+        let mut sell = create_test_order("00000000-0000-0000-0000-000000000003", "00000000-0000-0000-0000-00000000000a", OrderSide::SELL, OrderType::LIMIT, Some(dec!(60)), dec!(8));
+        buy.instrument_id = instrument_id;
+        sell.instrument_id = instrument_id;
+        for order in [&buy, &sell] {
+            balances.lock_for(order);
+            book.add_order(order.clone()).unwrap();
+        }
+        assert_eq!(balances.locked_cash(broker), dec!(500));
+        assert_eq!(balances.locked_inventory(broker, instrument_id), dec!(8));
+
+        // Cutting the buy from 10 to 4 frees the notional of the other 6
+        book.amend_order(buy.id, None, Some(dec!(4))).unwrap();
+        assert_eq!(balances.locked_cash(broker), dec!(200));
+        book.amend_order(sell.id, None, Some(dec!(5))).unwrap();
+        assert_eq!(balances.locked_inventory(broker, instrument_id), dec!(5));
+        visualize_order_book_state(&book);
+
+        // Cancelling frees whatever is left
+        book.cancel_order(buy.id).unwrap();
+        book.cancel_order(sell.id).unwrap();
+        assert_eq!(balances.locked_cash(broker), Decimal::ZERO);
+        assert_eq!(balances.locked_inventory(broker, instrument_id), Decimal::ZERO);
+
+        // Nothing is released twice
+        assert!(book.cancel_order(buy.id).is_err());
+        assert_eq!(balances.locked_cash(broker), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_batch_auction_clears_each_interval_at_one_price() {
+        print_separator("Frequent Batch Auctions");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut book = OrderBook::new(instrument_id);
+        book.begin_batch_auctions();
+
+        let first_interval = [
+            ("00000000-0000-0000-0000-000000000001", OrderSide::SELL, dec!(99), dec!(4)),
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(102), dec!(3)),
+            ("00000000-0000-0000-0000-000000000003", OrderSide::SELL, dec!(100), dec!(3)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::BUY, dec!(101), dec!(4)),
+        ];
+        for (id, side, price, qty) in first_interval {
+            let order = create_test_order(id, "00000000-0000-0000-0000-00000000000a", side, OrderType::LIMIT, Some(price), qty);
+            // Crossing orders only rest until the batch runs
+            assert!(book.add_order(order).unwrap().is_empty());
+        }
+        assert!(book.trades().is_empty());
+
+        let (price, trades) = book.run_batch().unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+        visualize_order_book_state(&book);
+
+        // 7 each way trade at 100, the price that executes all of it
+        assert_eq!(price, Some(dec!(100)));
+        assert!(trades.iter().all(|t| t.price == dec!(100)));
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<Decimal>(), dec!(7));
+        assert_eq!(book.bbo(), (None, None));
+        assert_eq!(book.trading_phase(), TradingPhase::Batch);
+
+        // The next interval collects afresh; a batch with nothing crossing trades nothing
+        let bid = create_test_order("00000000-0000-0000-0000-000000000005", "00000000-0000-0000-0000-00000000000a", OrderSide::BUY, OrderType::LIMIT, Some(dec!(98)), dec!(2));
+        let ask = create_test_order("00000000-0000-0000-0000-000000000006", "00000000-0000-0000-0000-00000000000a", OrderSide::SELL, OrderType::LIMIT, Some(dec!(98)), dec!(5));
+        book.add_order(bid).unwrap();
+        let (price, trades) = book.run_batch().unwrap();
+        assert!(price.is_none() && trades.is_empty());
+        assert!(book.add_order(ask).unwrap().is_empty());
+
+        let (price, trades) = book.run_batch().unwrap();
+        assert_eq!(price, Some(dec!(98)));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(book.bbo(), (None, Some(dec!(98))));
+        assert!(book.health_check().is_empty());
+    }
+
+    #[test]
+    fn test_best_resting_for_broker() {
+        print_separator("Best Resting For Broker");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut book = OrderBook::new(instrument_id);
+        let maker = "00000000-0000-0000-0000-00000000000a";
+        let other = "00000000-0000-0000-0000-00000000000b";
+
+        let resting = [
+            ("00000000-0000-0000-0000-000000000001", maker, OrderSide::BUY, dec!(98), dec!(5)),
+            ("00000000-0000-0000-0000-000000000002", maker, OrderSide::BUY, dec!(99), dec!(2)),
+            ("00000000-0000-0000-0000-000000000003", other, OrderSide::BUY, dec!(100), dec!(9)),
+            ("00000000-0000-0000-0000-000000000004", other, OrderSide::BUY, dec!(99), dec!(6)),
+            ("00000000-0000-0000-0000-000000000005", maker, OrderSide::BUY, dec!(99), dec!(3)),
+            ("00000000-0000-0000-0000-000000000006", other, OrderSide::SELL, dec!(101), dec!(4)),
+            ("00000000-0000-0000-0000-000000000007", maker, OrderSide::SELL, dec!(103), dec!(1)),
+        ];
+        for (id, broker, side, price, qty) in resting {
+            book.add_order(create_test_order(id, broker, side, OrderType::LIMIT, Some(price), qty)).unwrap();
+        }
+        visualize_order_book_state(&book);
+
+        let maker = Uuid::from_str(maker).unwrap();
+        // Another broker is alone at 100; the maker's best is 99, where it has 2 + 3
+        assert_eq!(book.best_resting_for_broker(maker, OrderSide::BUY), Some((dec!(99), dec!(5))));
+        assert_eq!(book.best_resting_for_broker(maker, OrderSide::SELL), Some((dec!(103), dec!(1))));
+        assert_eq!(book.best_resting_for_broker(Uuid::new_v4(), OrderSide::BUY), None);
+
+        book.cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap()).unwrap();
+        book.cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000005").unwrap()).unwrap();
+        assert_eq!(book.best_resting_for_broker(maker, OrderSide::BUY), Some((dec!(98), dec!(5))));
+    }
+
+    #[test]
+    fn test_crossing_orders_during_pre_open() {
+        print_separator("Crossing Orders In Pre-Open");
+
+        for policy in [AuctionCrossingPolicy::Accept, AuctionCrossingPolicy::Reject] {
+            let config = BookConfig {
+                auction_crossing: policy,
+                ..BookConfig::default()
+            };
+            let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+            let mut book = OrderBook::with_config(instrument_id, config);
+            book.begin_pre_open();
+
+            let ask = create_test_order("00000000-0000-0000-0000-000000000001", "00000000-0000-0000-0000-00000000000a", OrderSide::SELL, OrderType::LIMIT, Some(dec!(100)), dec!(5));
+            let passive_bid = create_test_order("00000000-0000-0000-0000-000000000002", "00000000-0000-0000-0000-00000000000b", OrderSide::BUY, OrderType::LIMIT, Some(dec!(99)), dec!(5));
+            let crossing_bid = create_test_order("00000000-0000-0000-0000-000000000003", "00000000-0000-0000-0000-00000000000b", OrderSide::BUY, OrderType::LIMIT, Some(dec!(101)), dec!(3));
+            book.add_order(ask).unwrap();
+            // Orders that do not cross are collected either way
+            assert!(book.add_order(passive_bid).unwrap().is_empty());
+
+            let result = book.add_order(crossing_bid.clone());
+            assert!(book.trades().is_empty());
+            match policy {
+                AuctionCrossingPolicy::Accept => {
+                    assert!(result.unwrap().is_empty());
+                    assert_eq!(book.bbo(), (Some(dec!(101)), Some(dec!(100))));
+                    let (price, trades) = book.run_opening_auction().unwrap();
+                    assert_eq!((price, trades.len()), (Some(dec!(100)), 1));
+                }
+                AuctionCrossingPolicy::Reject => {
+                    assert_eq!(result.unwrap_err(), OrderError::NoContinuousMatchingInAuction);
+                    assert!(book.get_order(crossing_bid.id).is_none());
+                    assert_eq!(book.bbo(), (Some(dec!(99)), Some(dec!(100))));
+                    let (price, trades) = book.run_opening_auction().unwrap();
+                    assert!(price.is_none() && trades.is_empty());
+                }
+            }
+            visualize_order_book_state(&book);
+        }
+    }
+
+    #[test]
+    fn test_all_resting_orders_in_priority_order() {
+        print_separator("All Resting Orders");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut book = OrderBook::new(instrument_id);
+
+        // Arrival order is scrambled across sides and levels
+        let resting = [
+            ("00000000-0000-0000-0000-000000000001", OrderSide::BUY, dec!(98)),
+            ("00000000-0000-0000-0000-000000000002", OrderSide::SELL, dec!(102)),
+            ("00000000-0000-0000-0000-000000000003", OrderSide::BUY, dec!(99)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::SELL, dec!(101)),
+            ("00000000-0000-0000-0000-000000000005", OrderSide::BUY, dec!(99)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::SELL, dec!(101)),
+        ];
+        for (id, side, price) in resting {
+            book.add_order(create_test_order(id, "00000000-0000-0000-0000-00000000000a", side, OrderType::LIMIT, Some(price), dec!(1)))
+                .unwrap();
+        }
+        visualize_order_book_state(&book);
+
+        let orders = book.all_resting_orders();
+        let ids: Vec<u128> = orders.iter().map(|o| o.id.as_u128()).collect();
+        // 98 | 99 (first, second) || 101 (first, second) | 102
+        assert_eq!(ids, vec![1, 3, 5, 4, 6, 2]);
+        assert!(orders[..3].iter().all(|o| o.side == OrderSide::BUY));
+        assert!(orders[3..].iter().all(|o| o.side == OrderSide::SELL));
+        assert_eq!((orders[2].price, orders[3].price), (Some(dec!(99)), Some(dec!(101))));
+
+        // Nothing that has left the book is exported
+        book.cancel_order(orders[0].id).unwrap();
+        assert_eq!(book.all_resting_orders().len(), 5);
+        assert!(OrderBook::new(Uuid::new_v4()).all_resting_orders().is_empty());
+    }
+
+    #[test]
+    fn test_estimated_fill_time_from_trailing_rate() {
+        print_separator("Estimated Fill Time");
+        let start = Utc::now();
+        let clock = Arc::new(MockClock::new(start));
+        let config = BookConfig {
+            fill_rate_window: Some(Duration::seconds(60)),
+            ..BookConfig::default()
+        };
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut book = OrderBook::with_config(instrument_id, config);
+        book.set_clock(clock.clone());
+
+        let order = |id: &str, side: OrderSide, price: Decimal, qty: Decimal| {
+            create_test_order(id, "00000000-0000-0000-0000-00000000000a", side, OrderType::LIMIT, Some(price), qty)
+        };
+
+        // Two minutes of trading: 30 at 100, 20 through at 99 and 10 at 102, which is above the order
+        let prints = [(dec!(100), dec!(30)), (dec!(99), dec!(20)), (dec!(102), dec!(10))];
+        for (i, (price, qty)) in prints.into_iter().enumerate() {
+            book.add_order(order(&format!("00000000-0000-0000-0000-0000000001{:02}", i), OrderSide::BUY, price, qty)).unwrap();
+            book.add_order(order(&format!("00000000-0000-0000-0000-0000000002{:02}", i), OrderSide::SELL, price, qty)).unwrap();
+            clock.advance(Duration::seconds(30));
+        }
+        assert_eq!(book.trades().len(), 3);
+
+        let waiting = order("00000000-0000-0000-0000-000000000003", OrderSide::BUY, dec!(100), dec!(5));
+        assert_eq!(book.estimated_fill_time(waiting.id, clock.now()), None);
+        book.add_order(order("00000000-0000-0000-0000-000000000001", OrderSide::BUY, dec!(101), dec!(15))).unwrap();
+        book.add_order(order("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(100), dec!(10))).unwrap();
+        book.add_order(waiting.clone()).unwrap();
+        visualize_order_book_state(&book);
+
+        assert_eq!(book.quantity_ahead(waiting.id), Some(dec!(25)));
+        // The last 60s hold the 99 and 102 prints; only 20 traded at or below 100,
+        // so a rate of 1/3 a second for 25 ahead plus 5 of its own is 90s
+        assert_eq!(book.estimated_fill_time(waiting.id, clock.now()), Some(Duration::seconds(90)));
+
+        // Once the window holds no qualifying trades there is no rate to go on
+        clock.advance(Duration::seconds(120));
+        assert_eq!(book.estimated_fill_time(waiting.id, clock.now()), None);
+        assert_eq!(book.estimated_fill_time(Uuid::new_v4(), clock.now()), None);
+    }
+
+    #[test]
+    fn test_order_for_another_instrument_is_rejected() {
+        print_separator("Instrument Mismatch");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+
+        // Priced to cross, but for a different instrument
+        let other_instrument = Uuid::from_str("00000000-0000-0000-0000-000000000009").unwrap();
+        let mut buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        buy_order.instrument_id = other_instrument;
+
+        assert_eq!(
+            order_book.add_order(buy_order.clone()).unwrap_err(),
+            OrderError::InstrumentMismatch { expected: instrument_id, found: other_instrument }
+        );
+        visualize_order_book_state(&order_book);
+
+        assert!(order_book.trades().is_empty());
+        assert!(order_book.get_order(buy_order.id).is_none());
+        assert_eq!(order_book.bbo(), (None, Some(dec!(100.0))));
+        assert_eq!(order_book.depth(1).asks[0].quantity, dec!(5.0));
+    }
+
+    #[test]
+    fn test_average_fill_price_rounds_to_display_scale() {
+        print_separator("Average Fill Price");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.set_display_scale(Some(2));
+
+        for (id, price) in [
+            ("00000000-0000-0000-0000-000000000002", dec!(10.01)),
+            ("00000000-0000-0000-0000-000000000003", dec!(10.02)),
+            ("00000000-0000-0000-0000-000000000004", dec!(10.04)),
+        ] {
+            let sell_order = create_test_order(id, "00000000-0000-0000-0000-00000000000a", OrderSide::SELL, OrderType::LIMIT, Some(price), dec!(1.0));
+            order_book.add_order(sell_order).unwrap();
+        }
+        let market_buy = create_test_order(
+            "00000000-0000-0000-0000-000000000005",
+            "00000000-0000-0000-0000-00000000000b",
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(3.0),
+        );
+        let trades = order_book.add_order(market_buy).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+
+        // 30.07 over 3 shares
+        let exact = order_book.exact_average_fill_price(&trades).unwrap();
+        assert_eq!(exact, dec!(30.07) / dec!(3));
+        assert!(exact.scale() > 2);
+        assert_eq!(order_book.average_fill_price(&trades), Some(dec!(10.02)));
+
+        order_book.config_mut().average_price_rounding = RoundingMode::Ceiling;
+        assert_eq!(order_book.average_fill_price(&trades), Some(dec!(10.03)));
+
+        // Without a display scale the report is the exact figure
+        order_book.set_display_scale(None);
+        assert_eq!(order_book.average_fill_price(&trades), Some(exact));
+        assert_eq!(order_book.average_fill_price(&[]), None);
+    }
+
+    #[test]
+    fn test_broker_activity_flags_heavy_cancelling() {
+        print_separator("Broker Activity");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        let spoofer = "00000000-0000-0000-0000-00000000000a";
+        let other = "00000000-0000-0000-0000-00000000000b";
+
+        // Big bids that are pulled, and one small sell that actually trades
+        for (i, price) in [dec!(99.0), dec!(98.0), dec!(97.0)].into_iter().enumerate() {
+            let id = format!("00000000-0000-0000-0000-00000000000{}", i + 1);
+            order_book.add_order(create_test_order(&id, spoofer, OrderSide::BUY, OrderType::LIMIT, Some(price), dec!(100.0))).unwrap();
+        }
+        let sell_order = create_test_order("00000000-0000-0000-0000-000000000004", spoofer, OrderSide::SELL, OrderType::LIMIT, Some(dec!(101.0)), dec!(10.0));
+        order_book.add_order(sell_order).unwrap();
+        let buy_order = create_test_order("00000000-0000-0000-0000-000000000005", other, OrderSide::BUY, OrderType::LIMIT, Some(dec!(101.0)), dec!(10.0));
+        order_book.add_order(buy_order).unwrap();
+
+        // Cancel two outright and cut the third down to 20
+        order_book.cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap()).unwrap();
+        order_book.cancel_order(Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap()).unwrap();
+        order_book.amend_order(Uuid::from_str("00000000-0000-0000-0000-000000000003").unwrap(), None, Some(dec!(20.0))).unwrap();
+        visualize_order_book_state(&order_book);
+
+        let activity = order_book.broker_activity(Uuid::from_str(spoofer).unwrap());
+        assert_eq!(activity.submitted, dec!(310.0));
+        assert_eq!(activity.filled_volume, dec!(10.0));
+        assert_eq!(activity.cancelled_volume, dec!(280.0));
+        assert_eq!(activity.cancel_to_fill_ratio, Some(dec!(28)));
+
+        let activity = order_book.broker_activity(Uuid::from_str(other).unwrap());
+        assert_eq!((activity.submitted, activity.filled_volume, activity.cancelled_volume), (dec!(10.0), dec!(10.0), dec!(0)));
+        assert_eq!(activity.cancel_to_fill_ratio, Some(dec!(0)));
+
+        assert_eq!(order_book.broker_activity(Uuid::new_v4()), BrokerActivity::default());
+    }
+
+    #[test]
+    fn test_locked_order_cannot_be_cancelled() {
+        print_separator("Order Locked For Matching");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(buy_order.clone()).unwrap();
+        // Matching releases its lock once the order is processed
+        order_book.amend_order(buy_order.id, None, Some(dec!(4.0))).unwrap();
+
+        // Stand in for a sweep that is still working the order
+        order_book.lock_order(buy_order.id);
+        assert_eq!(order_book.cancel_order(buy_order.id).unwrap_err(), OrderError::OrderLocked(buy_order.id));
+        assert_eq!(order_book.amend_order(buy_order.id, None, Some(dec!(3.0))).unwrap_err(), OrderError::OrderLocked(buy_order.id));
+        assert_eq!(order_book.get_order(buy_order.id).unwrap().status, OrderStatus::PENDING);
+        visualize_order_book_state(&order_book);
+
+        order_book.unlock_order(buy_order.id);
+        let cancelled = order_book.cancel_order(buy_order.id).unwrap();
+        assert_eq!(cancelled.status, OrderStatus::CANCELLED);
+        assert_eq!(order_book.bbo(), (None, None));
+    }
 
-        loop {
-            let matching_order_opt = match side {
-                OrderSide::BUY => self.get_best_ask(),
-                OrderSide::SELL => self.get_best_bid(),
-            };
+    #[test]
+    fn test_notional_per_side() {
+        print_separator("Notional Per Side");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        assert_eq!((order_book.bid_notional(), order_book.ask_notional()), (Decimal::ZERO, Decimal::ZERO));
 
-            match matching_order_opt {
-                Some((price, matched_order)) => {
-                    let trade_quantity = order.remaining_quantity.min(matched_order.remaining_quantity);
+        let resting = [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(99.5), dec!(10.0)),
+            ("00000000-0000-0000-0000-000000000003", OrderSide::BUY, dec!(99.5), dec!(4.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::BUY, dec!(98.25), dec!(8.0)),
+            ("00000000-0000-0000-0000-000000000005", OrderSide::SELL, dec!(100.75), dec!(6.0)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::SELL, dec!(102.0), dec!(3.0)),
+        ];
+        for (id, side, price, quantity) in resting {
+            let order = create_test_order(id, "00000000-0000-0000-0000-00000000000a", side, OrderType::LIMIT, Some(price), quantity);
+            order_book.add_order(order).unwrap();
+        }
+        visualize_order_book_state(&order_book);
 
-                    trades.push(self.create_trade(
-                        &order,
-                        &matched_order,
-                        price,
-                        trade_quantity
-                    ));
+        // 99.5 * 14 + 98.25 * 8 and 100.75 * 6 + 102 * 3
+        assert_eq!(order_book.bid_notional(), dec!(1393.0) + dec!(786.0));
+        assert_eq!(order_book.ask_notional(), dec!(604.5) + dec!(306.0));
 
-                    order.remaining_quantity -= trade_quantity;
-                    order.status = if order.remaining_quantity == Decimal::ZERO {
-                        OrderStatus::FILLED
-                    } else {
-                        OrderStatus::PARTIAL
-                    };
+        // Taking out the asks leaves that side at zero
+        for id in ["00000000-0000-0000-0000-000000000005", "00000000-0000-0000-0000-000000000006"] {
+            order_book.cancel_order(Uuid::from_str(id).unwrap()).unwrap();
+        }
+        assert_eq!(order_book.ask_notional(), Decimal::ZERO);
+        assert_eq!(order_book.bid_notional(), dec!(2179.0));
+    }
 
-                    self.update_matched_order(&matched_order, trade_quantity, price, side.clone());
+    #[test]
+    fn test_closing_auction_tie_goes_to_reference_price() {
+        print_separator("Closing Auction Reference Price");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
 
-                    if order.remaining_quantity == Decimal::ZERO {
-                        break;
-                    }
-                }
-                None => {
-                    order.status = OrderStatus::REJECTED;
-                    break;
-                }
+        // 5 bid at 102 against 5 offered at 100: both prices clear all 5 with no imbalance
+        let collected_book = || {
+            let mut order_book = OrderBook::new(instrument_id);
+            order_book.begin_pre_open();
+            for (id, side, price) in [
+                ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(102.0)),
+                ("00000000-0000-0000-0000-000000000003", OrderSide::SELL, dec!(100.0)),
+            ] {
+                let order = create_test_order(id, "00000000-0000-0000-0000-00000000000a", side, OrderType::LIMIT, Some(price), dec!(5.0));
+                order_book.add_order(order).unwrap();
             }
-        }
+            order_book
+        };
 
-        if order.remaining_quantity > Decimal::ZERO {
-            order.status = OrderStatus::REJECTED;
+        let mut order_book = collected_book();
+        let (price, trades) = order_book.run_closing_auction(Some(dec!(101.8))).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
         }
+        assert_eq!(price, Some(dec!(102.0)));
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<Decimal>(), dec!(5.0));
+        assert!(trades.iter().all(|t| t.price == dec!(102.0)));
+        assert_eq!(order_book.trading_phase(), TradingPhase::PreOpen);
 
-        self.orders.insert(order.id, order);
+        assert_eq!(collected_book().run_closing_auction(Some(dec!(99.0))).unwrap().0, Some(dec!(100.0)));
+        // Equidistant or no reference falls back to the lowest price, as at the open
+        assert_eq!(collected_book().run_closing_auction(Some(dec!(101.0))).unwrap().0, Some(dec!(100.0)));
+        assert_eq!(collected_book().run_closing_auction(None).unwrap().0, Some(dec!(100.0)));
+        assert_eq!(collected_book().run_opening_auction().unwrap().0, Some(dec!(100.0)));
     }
 
-    pub fn cancel_order(&mut self, order_id: Uuid) -> Option<Order> {
-        if let Some(order) = self.orders.get(&order_id) {
-            if order.status != OrderStatus::PENDING && order.status != OrderStatus::PARTIAL {
-                return None;
-            }
-
-            let price = order.price.expect("Order should have a price");
-            let side = order.side.clone();
+    #[test]
+    fn test_implied_fill_behind_queue_priority() {
+        print_separator("Implied Fill For Resting Order");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
 
-            let book = match side {
-                OrderSide::BUY => &mut self.bids,
-                OrderSide::SELL => &mut self.asks,
-            };
+        // 20 at a better price and 10 earlier at the same price sit ahead of ours
+        for (id, price, quantity) in [
+            ("00000000-0000-0000-0000-000000000002", dec!(100.0), dec!(20.0)),
+            ("00000000-0000-0000-0000-000000000003", dec!(99.0), dec!(10.0)),
+            ("00000000-0000-0000-0000-000000000004", dec!(99.0), dec!(15.0)),
+            ("00000000-0000-0000-0000-000000000005", dec!(99.0), dec!(50.0)),
+        ] {
+            let buy_order = create_test_order(id, "00000000-0000-0000-0000-00000000000a", OrderSide::BUY, OrderType::LIMIT, Some(price), quantity);
+            order_book.add_order(buy_order).unwrap();
+        }
+        visualize_order_book_state(&order_book);
 
-            if let Some(orders) = book.get_mut(&price) {
-                if let Some(pos) = orders.iter().position(|o| o.id == order_id) {
-                    let cancelled_order = orders.remove(pos);
-                    if orders.is_empty() {
-                        book.remove(&price);
-                    }
+        let ours = Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap();
+        assert_eq!(order_book.quantity_ahead(ours), Some(dec!(30.0)));
+        assert_eq!(order_book.implied_fill_for(ours, dec!(30.0)), Decimal::ZERO);
+        assert_eq!(order_book.implied_fill_for(ours, dec!(36.0)), dec!(6.0));
+        // Never more than the order has left
+        assert_eq!(order_book.implied_fill_for(ours, dec!(100.0)), dec!(15.0));
 
-                    let mut updated_order = cancelled_order.clone();
-                    updated_order.status = OrderStatus::CANCELLED;
-                    self.orders.insert(order_id, updated_order.clone());
+        // Checking it against the real thing
+        let market_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-00000000000b",
+            OrderSide::SELL,
+            OrderType::MARKET,
+            None,
+            dec!(36.0),
+        );
+        order_book.add_order(market_sell).unwrap();
+        assert_eq!(order_book.get_order(ours).unwrap().filled_quantity, dec!(6.0));
 
-                    return Some(updated_order);
-                }
-            }
-        }
-        None
+        assert_eq!(order_book.implied_fill_for(Uuid::new_v4(), dec!(100.0)), Decimal::ZERO);
     }
 
-    fn get_best_ask(&mut self) -> Option<(Decimal, Order)> {
-        if let Some((&price, orders)) = self.asks.iter_mut().next() {
-            if !orders.is_empty() {
-                let order = orders[0].clone();
-                return Some((price, order));
-            }
-        }
-        None
-    }
+    #[test]
+    fn test_partial_lot_round_down_leaves_clean_residuals() {
+        print_separator("Partial Lot Round Down");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
 
-    fn get_best_bid(&mut self) -> Option<(Decimal, Order)> {
-        if let Some((&price, orders)) = self.bids.iter_mut().next() {
-            if !orders.is_empty() {
-                let order = orders[0].clone();
-                return Some((price, order));
-            }
-        }
-        None
-    }
+        for policy in [PartialLotPolicy::AllowOddResidual, PartialLotPolicy::RoundDown] {
+            let config = BookConfig {
+                partial_lot: policy,
+                ..BookConfig::default()
+            };
+            let mut order_book = OrderBook::with_config(instrument_id, config);
+            order_book.set_lot_size(dec!(100));
 
-    fn update_matched_order(&mut self, matched_order: &Order, trade_quantity: Decimal, price: Decimal, side: OrderSide) {
-        let book = match side {
-            OrderSide::BUY => &mut self.asks,
-            OrderSide::SELL => &mut self.bids,
-        };
+            for id in ["00000000-0000-0000-0000-000000000002", "00000000-0000-0000-0000-000000000003"] {
+                let sell_order = create_test_order(id, "00000000-0000-0000-0000-00000000000a", OrderSide::SELL, OrderType::LIMIT, Some(dec!(100.0)), dec!(100));
+                order_book.add_order(sell_order).unwrap();
+            }
+            // Wants 150 against 100 available from the first seller
+            let buy_order = create_test_order(
+                "00000000-0000-0000-0000-000000000004",
+                "00000000-0000-0000-0000-00000000000b",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                dec!(150),
+            );
+            let trades = order_book.add_order(buy_order).unwrap();
+            for trade in &trades {
+                visualize_trade(trade);
+            }
 
-        if let Some(orders) = book.get_mut(&price) {
-            if !orders.is_empty() {
-                if orders[0].remaining_quantity == trade_quantity {
-                    orders.remove(0);
-                    if orders.is_empty() {
-                        book.remove(&price);
-                    }
-                } else {
-                    orders[0].remaining_quantity -= trade_quantity;
-                    orders[0].status = OrderStatus::PARTIAL;
+            let second_seller = order_book.get_order(Uuid::from_str("00000000-0000-0000-0000-000000000003").unwrap()).unwrap();
+            match policy {
+                PartialLotPolicy::AllowOddResidual => {
+                    assert_eq!(trades.iter().map(|t| t.quantity).collect::<Vec<_>>(), vec![dec!(100), dec!(50)]);
+                    assert_eq!(second_seller.remaining_quantity, dec!(50));
+                }
+                PartialLotPolicy::RoundDown => {
+                    // The odd 50 cannot trade, so the second seller keeps a full lot
+                    assert_eq!(trades.iter().map(|t| t.quantity).collect::<Vec<_>>(), vec![dec!(100)]);
+                    assert_eq!(second_seller.remaining_quantity, dec!(100));
+                    assert_eq!(second_seller.status, OrderStatus::PENDING);
+                    let buy = order_book.get_order(Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap()).unwrap();
+                    assert_eq!((buy.filled_quantity, buy.remaining_quantity), (dec!(100), dec!(50)));
                 }
             }
+            visualize_order_book_state(&order_book);
         }
 
-        let mut updated_order = matched_order.clone();
-        updated_order.remaining_quantity -= trade_quantity;
-        updated_order.status = if updated_order.remaining_quantity == Decimal::ZERO {
-            OrderStatus::FILLED
-        } else {
-            OrderStatus::PARTIAL
+        // With the odd 50 at the front of the level, the full lot behind it
+        // must not trade first
+        let config = BookConfig {
+            partial_lot: PartialLotPolicy::RoundDown,
+            ..BookConfig::default()
         };
-        self.orders.insert(updated_order.id, updated_order);
-    }
-
-    fn create_trade(&self, order: &Order, matched_order: &Order, price: Decimal, quantity: Decimal) -> Trade {
-        Trade {
-            id: Uuid::new_v4(),
-            instrument_id: self.instrument_id,
-            buyer_order_id: if order.side == OrderSide::BUY {
-                order.id
-            } else {
-                matched_order.id
-            },
-            seller_order_id: if order.side == OrderSide::SELL {
-                order.id
-            } else {
-                matched_order.id
-            },
-            buyer_broker_id: if order.side == OrderSide::BUY {
-                order.broker_id
-            } else {
-                matched_order.broker_id
-            },
-            seller_broker_id: if order.side == OrderSide::SELL {
-                order.broker_id
-            } else {
-                matched_order.broker_id
-            },
-            price,
-            quantity,
-            execution_time: Utc::now(),
-            status: TradeStatus::PENDING_SETTLEMENT,
-            settlement_time: None,
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+        order_book.set_lot_size(dec!(100));
+        for (id, quantity) in [("00000000-0000-0000-0000-000000000002", dec!(50)), ("00000000-0000-0000-0000-000000000003", dec!(100))] {
+            let sell_order = create_test_order(id, "00000000-0000-0000-0000-00000000000a", OrderSide::SELL, OrderType::LIMIT, Some(dec!(100.0)), quantity);
+            order_book.add_order(sell_order).unwrap();
         }
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-00000000000b",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(150),
+        );
+        assert!(order_book.add_order(buy_order).unwrap().is_empty());
+        let queue: Vec<Decimal> = order_book.asks[&dec!(100.0)].iter().map(|o| o.remaining_quantity).collect();
+        assert_eq!(queue, vec![dec!(50), dec!(100)]);
     }
 
-    fn prices_match(&self, side: OrderSide, order_price: Decimal, book_price: Decimal) -> bool {
-        match side {
-            OrderSide::BUY => order_price >= book_price,
-            OrderSide::SELL => order_price <= book_price,
+    #[test]
+    fn test_diagnostics_matches_individual_metrics() {
+        print_separator("Book Diagnostics");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let start = Utc::now();
+        let clock = Arc::new(MockClock::new(start));
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.set_clock(clock.clone());
+
+        let empty = order_book.diagnostics();
+        assert_eq!((empty.total_orders, empty.spread, empty.is_crossed, empty.oldest_order_age), (0, None, false, None));
+
+        let resting = [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(99.0), dec!(10.0)),
+            ("00000000-0000-0000-0000-000000000003", OrderSide::BUY, dec!(99.0), dec!(5.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::BUY, dec!(97.0), dec!(8.0)),
+            ("00000000-0000-0000-0000-000000000005", OrderSide::SELL, dec!(101.0), dec!(4.0)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::SELL, dec!(102.0), dec!(6.0)),
+            ("00000000-0000-0000-0000-000000000007", OrderSide::SELL, dec!(104.0), dec!(2.0)),
+        ];
+        for (id, side, price, quantity) in resting {
+            let mut order = create_test_order(id, "00000000-0000-0000-0000-00000000000a", side, OrderType::LIMIT, Some(price), quantity);
+            order.created_at = clock.now();
+            order_book.add_order(order).unwrap();
+            clock.advance(Duration::seconds(10));
         }
+        visualize_order_book_state(&order_book);
+
+        let diagnostics = order_book.diagnostics();
+        let depth = order_book.depth(usize::MAX);
+        assert_eq!((diagnostics.bid_levels, diagnostics.ask_levels), (depth.bids.len(), depth.asks.len()));
+        assert_eq!((diagnostics.bid_levels, diagnostics.ask_levels), (2, 3));
+        assert_eq!(diagnostics.total_orders, order_book.all_resting_orders().len());
+        assert_eq!(diagnostics.total_orders, 6);
+        assert_eq!(diagnostics.total_bid_volume, depth.bids.iter().map(|l| l.quantity).sum::<Decimal>());
+        assert_eq!(diagnostics.total_bid_volume, dec!(23.0));
+        assert_eq!(diagnostics.total_ask_volume, dec!(12.0));
+        assert_eq!(diagnostics.spread, order_book.spread());
+        assert_eq!(diagnostics.spread, Some(dec!(2.0)));
+        assert!(!diagnostics.is_crossed);
+        // The first order went in a minute before the clock now reads
+        assert_eq!(diagnostics.oldest_order_age, Some(Duration::seconds(60)));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rust_decimal_macros::dec;
-    use std::str::FromStr;
+    #[test]
+    fn test_add_order_with_priority_queues_by_sequence() {
+        print_separator("Replay With Priority");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
 
-    // Helper function to print a visual separator
-    fn print_separator(test_name: &str) {
-        println!("\n{}", "=".repeat(50));
-        println!("🧪 TEST: {}", test_name);
-        println!("{}\n", "=".repeat(50));
-    }
+        let sell = |id: &str, broker: &str| {
+            create_test_order(id, broker, OrderSide::SELL, OrderType::LIMIT, Some(dec!(100.0)), dec!(5.0))
+        };
+        let live = sell("00000000-0000-0000-0000-000000000002", "00000000-0000-0000-0000-00000000000a");
+        let later = sell("00000000-0000-0000-0000-000000000003", "00000000-0000-0000-0000-00000000000b");
+        let earlier = sell("00000000-0000-0000-0000-000000000004", "00000000-0000-0000-0000-00000000000c");
 
-    // Helper function to visualize an order
-    fn visualize_order(prefix: &str, order: &Order) {
-        println!("📝 {} Order:", prefix);
-        println!("   ├─ ID: {}", order.id);
-        println!("   ├─ Type: {:?}", order.order_type);
-        println!("   ├─ Side: {:?}", order.side);
-        println!("   ├─ Price: {:?}", order.price);
-        println!("   ├─ Quantity: {}", order.original_quantity);
-        println!("   └─ Status: {:?}", order.status);
-    }
+        // Replayed out of order: the order from sequence 20 arrives before the one from 10
+        order_book.add_order(live.clone()).unwrap();
+        order_book.add_order_with_priority(later.clone(), 20).unwrap();
+        order_book.add_order_with_priority(earlier.clone(), 10).unwrap();
+        visualize_order_book_state(&order_book);
 
-    // Helper function to visualize a trade
-    fn visualize_trade(trade: &Trade) {
-        println!("\n🤝 Trade Executed:");
-        println!("   ├─ Price: {}", trade.price);
-        println!("   ├─ Quantity: {}", trade.quantity);
-        println!("   ├─ Buyer Order: {}", trade.buyer_order_id);
-        println!("   └─ Seller Order: {}", trade.seller_order_id);
-    }
+        let queue: Vec<Uuid> = order_book.queue_order_with_visibility(OrderSide::SELL, dec!(100.0)).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(queue, vec![earlier.id, later.id, live.id]);
+        assert_eq!(
+            order_book.add_order_with_priority(earlier.clone(), 5).unwrap_err(),
+            OrderError::DuplicateOrderId(earlier.id)
+        );
 
-    // Helper function to visualize the order book state
-    fn visualize_order_book_state(order_book: &OrderBook) {
-        println!("\n📚 Order Book State:");
-        println!("   ├─ Bids: {:?}", order_book.bids);
-        println!("   ├─ Asks: {:?}", order_book.asks);
-        println!("   └─ Orders: {:?}", order_book.orders);
+        // Matching follows the rebuilt queue
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000005",
+            "00000000-0000-0000-0000-00000000000d",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(7.0),
+        );
+        let trades = order_book.add_order(buy_order).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+        assert_eq!(trades.iter().map(|t| (t.seller_order_id, t.quantity)).collect::<Vec<_>>(), vec![(earlier.id, dec!(5.0)), (later.id, dec!(2.0))]);
     }
 
-    fn create_test_order(
-        id: &str,
-        broker_id: &str,
-        side: OrderSide,
-        order_type: OrderType,
-        price: Option<Decimal>,
-        quantity: Decimal,
-    ) -> Order {
-        Order {
-            id: Uuid::from_str(id).unwrap(),
-            broker_id: Uuid::from_str(broker_id).unwrap(),
-            instrument_id: Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap(),
-            order_type,
-            side,
-            status: OrderStatus::PENDING,
-            price,
-            original_quantity: quantity,
-            remaining_quantity: quantity,
+    #[test]
+    fn test_for_instrument_rejects_invalid_tick_and_lot_sizes() {
+        print_separator("Invalid Instrument");
+        let instrument = |tick_size: Decimal, lot_size: i32| Instrument {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap(),
+            symbol: "RSE".to_string(),
+            name: "Redmont Stock Exchange".to_string(),
+            r#type: InstrumentType::STOCK,
+            status: InstrumentStatus::ACTIVE,
+            lot_size,
+            tick_size,
+            currency: "RMD".to_string(),
             created_at: Utc::now(),
             updated_at: Utc::now(),
+        };
+
+        for (tick_size, lot_size) in [(dec!(0), 1), (dec!(-0.01), 1), (dec!(0.01), 0), (dec!(0.01), -100)] {
+            assert_eq!(
+                OrderBook::for_instrument(&instrument(tick_size, lot_size)).unwrap_err(),
+                OrderError::InvalidInstrument { tick_size, lot_size }
+            );
         }
+
+        let order_book = OrderBook::for_instrument(&instrument(dec!(0.01), 100)).unwrap();
+        assert_eq!((order_book.tick_size(), order_book.lot_size()), (Some(dec!(0.01)), dec!(100)));
+        assert!(validate_instrument(&instrument(dec!(0.01), 100)).is_ok());
     }
 
     #[test]
-    fn test_limit_order_full_match() {
-        print_separator("Limit Order Full Match");
-
+    fn test_order_can_opt_out_of_hidden_liquidity() {
+        print_separator("Do Not Match Against Hidden");
         let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
         let mut order_book = OrderBook::new(instrument_id);
 
-        // Create a sell limit order
-        let sell_order = create_test_order(
+        let mut hidden_sell = create_test_order(
             "00000000-0000-0000-0000-000000000002",
             "00000000-0000-0000-0000-000000000003",
             OrderSide::SELL,
             OrderType::LIMIT,
             Some(dec!(100.0)),
-            dec!(10.0),
+            dec!(5.0),
+        );
+        hidden_sell.hidden = true;
+        let displayed_sell = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(5.0),
         );
+        order_book.add_order(hidden_sell.clone()).unwrap();
+        order_book.add_order(displayed_sell.clone()).unwrap();
 
-        println!("➡️ Adding Sell Order to Book:");
-        visualize_order("SELL", &sell_order);
+        let mut buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000007",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(101.0)),
+            dec!(3.0),
+        );
+        buy_order.interact_with_hidden = false;
+        let trades = order_book.add_order(buy_order).unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
+        visualize_order_book_state(&order_book);
 
-        let trades = order_book.add_order(sell_order);
-        println!("\n📚 Order Book State: No trades, order added to book");
+        // The better-priced hidden sell is passed over for the displayed one
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].seller_order_id, trades[0].price), (displayed_sell.id, dec!(101.0)));
+        assert_eq!(order_book.get_order(hidden_sell.id).unwrap().remaining_quantity, dec!(5.0));
 
-        // Create a matching buy order
+        // An order that has not opted out still takes the hidden sell first
         let buy_order = create_test_order(
-            "00000000-0000-0000-0000-000000000004",
-            "00000000-0000-0000-0000-000000000005",
+            "00000000-0000-0000-0000-000000000008",
+            "00000000-0000-0000-0000-000000000007",
             OrderSide::BUY,
             OrderType::LIMIT,
-            Some(dec!(100.0)),
-            dec!(10.0),
+            Some(dec!(101.0)),
+            dec!(3.0),
         );
+        let trades = order_book.add_order(buy_order).unwrap();
+        assert_eq!((trades[0].seller_order_id, trades[0].price), (hidden_sell.id, dec!(100.0)));
+    }
 
-        println!("\n➡️ Adding Buy Order:");
-        visualize_order("BUY", &buy_order);
+    #[test]
+    fn test_pressure_adjusted_price_leans_toward_heavier_side() {
+        print_separator("Pressure Adjusted Price");
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+
+        let book_with = |levels: &[(OrderSide, Decimal, Decimal)]| {
+            let mut order_book = OrderBook::new(instrument_id);
+            for (side, price, quantity) in levels {
+                let order = create_test_order(
+                    &Uuid::new_v4().to_string(),
+                    "00000000-0000-0000-0000-00000000000a",
+                    side.clone(),
+                    OrderType::LIMIT,
+                    Some(*price),
+                    *quantity,
+                );
+                order_book.add_order(order).unwrap();
+            }
+            order_book
+        };
 
-        let trades = order_book.add_order(buy_order);
+        // 30 bid over two levels against 10 offered: 99 + 2 * 30 / 40
+        let bid_heavy = book_with(&[
+            (OrderSide::BUY, dec!(99.0), dec!(10.0)),
+            (OrderSide::BUY, dec!(98.0), dec!(20.0)),
+            (OrderSide::SELL, dec!(101.0), dec!(5.0)),
+            (OrderSide::SELL, dec!(102.0), dec!(5.0)),
+        ]);
+        visualize_order_book_state(&bid_heavy);
+        assert_eq!(bid_heavy.pressure_adjusted_price(2), Some(dec!(100.5)));
+        assert!(bid_heavy.pressure_adjusted_price(2) > bid_heavy.mid_price());
+        // At the touch alone it is the plain microprice: 99 + 2 * 10 / 15
+        assert_eq!(bid_heavy.pressure_adjusted_price(1), Some(dec!(99.0) + dec!(2.0) * dec!(10.0) / dec!(15.0)));
 
-        println!("\n💫 Result:");
-        for trade in &trades {
-            visualize_trade(trade);
+        let balanced = book_with(&[
+            (OrderSide::BUY, dec!(99.0), dec!(10.0)),
+            (OrderSide::BUY, dec!(98.0), dec!(5.0)),
+            (OrderSide::SELL, dec!(101.0), dec!(5.0)),
+            (OrderSide::SELL, dec!(102.0), dec!(10.0)),
+        ]);
+        assert_eq!(balanced.pressure_adjusted_price(2), balanced.mid_price());
+
+        let one_sided = book_with(&[(OrderSide::BUY, dec!(99.0), dec!(10.0))]);
+        assert_eq!(one_sided.pressure_adjusted_price(5), None);
+        assert_eq!(bid_heavy.pressure_adjusted_price(0), None);
+    }
+
+    #[test]
+    fn test_stop_refused_after_earlier_stop_halts_is_reported() {
+        print_separator("Stop Refused After Earlier Stop Halts");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.config_mut().stop_trigger = StopTrigger::BidAsk;
+        order_book.config_mut().max_spread = Some(dec!(5.0));
+
+        for (id, side, price) in [
+            ("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(99.0)),
+            ("00000000-0000-0000-0000-000000000004", OrderSide::SELL, dec!(100.0)),
+            ("00000000-0000-0000-0000-000000000006", OrderSide::SELL, dec!(120.0)),
+        ] {
+            let order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(5.0),
+            );
+            order_book.add_order(order).unwrap();
         }
-        println!("📚 Order Book State: Empty (all orders matched)");
 
-        assert_eq!(trades.len(), 1);
-        assert_eq!(trades[0].quantity, dec!(10.0));
-        assert_eq!(trades[0].price, dec!(100.0));
+        let stops: Vec<Order> = ["00000000-0000-0000-0000-000000000008", "00000000-0000-0000-0000-00000000000a"]
+            .iter()
+            .map(|id| {
+                create_test_order(
+                    id,
+                    "00000000-0000-0000-0000-000000000009",
+                    OrderSide::BUY,
+                    OrderType::MARKET,
+                    None,
+                    dec!(5.0),
+                )
+            })
+            .collect();
+        for stop in &stops {
+            order_book.add_stop_order(stop.clone(), dec!(100.0)).unwrap();
+        }
+
+        // The first stop lifts the 100 offer, leaving a 21 wide spread that halts the book
+        let outcome = order_book.check_stops();
+        assert_eq!(outcome.trades.len(), 1);
+        assert_eq!(outcome.trades[0].buyer_order_id, stops[0].id);
+        assert_eq!(order_book.halt_reason(), Some(HaltReason::Volatility));
+        assert_eq!(outcome.rejected, vec![(stops[1].id, OrderError::Halted(HaltReason::Volatility))]);
+
+        // The second has fired, so it is not retried once trading resumes
+        assert!(order_book.stop_orders().is_empty());
+        assert!(order_book.get_order(stops[1].id).is_none());
+        order_book.resume();
+        let outcome = order_book.check_stops();
+        assert!(outcome.rejected.is_empty());
+        assert!(outcome.trades.is_empty());
+        assert_eq!(order_book.best_ask(), Some(dec!(120.0)));
+
+        // It can be resubmitted as a new stop
+        order_book.add_stop_order(stops[1].clone(), dec!(100.0)).unwrap();
+        let outcome = order_book.check_stops();
+        assert_eq!(outcome.trades.len(), 1);
+        assert_eq!(outcome.trades[0].price, dec!(120.0));
     }
 
     #[test]
-    fn test_limit_order_partial_match() {
-        print_separator("Limit Order Partial Match");
+    fn test_duplicate_stop_ids_are_rejected() {
+        print_separator("Duplicate Stop Ids");
 
         let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
         let mut order_book = OrderBook::new(instrument_id);
 
-        let sell_order = create_test_order(
+        let stop = create_test_order(
             "00000000-0000-0000-0000-000000000002",
             "00000000-0000-0000-0000-000000000003",
-            OrderSide::SELL,
-            OrderType::LIMIT,
-            Some(dec!(100.0)),
-            dec!(10.0),
+            OrderSide::BUY,
+            OrderType::MARKET,
+            None,
+            dec!(5.0),
         );
+        order_book.add_stop_order(stop.clone(), dec!(100.0)).unwrap();
+        assert_eq!(
+            order_book.add_stop_order(stop.clone(), dec!(101.0)).unwrap_err(),
+            OrderError::DuplicateOrderId(stop.id)
+        );
+        assert_eq!(order_book.stop_orders().len(), 1);
+        assert_eq!(order_book.stop_orders()[0].stop_price, dec!(100.0));
 
-        println!("➡️ Adding Sell Order to Book (Quantity: 10):");
-        visualize_order("SELL", &sell_order);
-
-        order_book.add_order(sell_order);
-
-        let buy_order = create_test_order(
+        // Nor may a stop reuse the id of an order already on the book
+        let resting = create_test_order(
             "00000000-0000-0000-0000-000000000004",
-            "00000000-0000-0000-0000-000000000005",
-            OrderSide::BUY,
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
             OrderType::LIMIT,
-            Some(dec!(100.0)),
+            Some(dec!(110.0)),
             dec!(5.0),
         );
-
-        println!("\n➡️ Adding Buy Order (Quantity: 5):");
-        visualize_order("BUY", &buy_order);
-
-        let trades = order_book.add_order(buy_order);
-
-        println!("\n💫 Result:");
-        for trade in &trades {
-            visualize_trade(trade);
-        }
-
-        if let Some(order) = order_book.orders.get(&Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap()) {
-            println!("\n📚 Order Book State:");
-            println!("   Remaining Sell Order:");
-            println!("   ├─ Quantity: {}", order.remaining_quantity);
-            println!("   └─ Status: {:?}", order.status);
-        };
+        order_book.add_order(resting.clone()).unwrap();
+        assert_eq!(
+            order_book.add_stop_order(resting.clone(), dec!(100.0)).unwrap_err(),
+            OrderError::DuplicateOrderId(resting.id)
+        );
     }
 
     #[test]
-    fn test_market_order_full_execution() {
-        print_separator("Market Order Full Execution");
+    fn test_fok_respects_min_fill_increment() {
+        print_separator("FOK With Fill Increment");
 
         let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
         let mut order_book = OrderBook::new(instrument_id);
+        order_book.config_mut().min_fill_increment = Some(dec!(10));
 
         let sell_order = create_test_order(
             "00000000-0000-0000-0000-000000000002",
@@ -429,229 +8841,429 @@ mod tests {
             OrderSide::SELL,
             OrderType::LIMIT,
             Some(dec!(100.0)),
-            dec!(10.0),
+            dec!(25),
         );
+        order_book.add_order(sell_order).unwrap();
 
-        println!("➡️ Adding Limit Sell Order to Book:");
-        visualize_order("SELL", &sell_order);
+        let fok_buy = |id: &str, quantity: Decimal| {
+            let mut order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000005",
+                OrderSide::BUY,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                quantity,
+            );
+            order.time_in_force = TimeInForce::FOK;
+            order
+        };
+
+        // 25 rests, but only 20 of it can trade in steps of 10
+        let trades = order_book.add_order(fok_buy("00000000-0000-0000-0000-000000000004", dec!(25))).unwrap();
+        assert!(trades.is_empty());
+        let fok = order_book.get_order(Uuid::from_str("00000000-0000-0000-0000-000000000004").unwrap()).unwrap();
+        assert_eq!(fok.status, OrderStatus::CANCELLED);
+        assert_eq!(fok.filled_quantity, Decimal::ZERO);
+        assert_eq!(order_book.asks[&dec!(100.0)][0].remaining_quantity, dec!(25));
+
+        let trades = order_book.add_order(fok_buy("00000000-0000-0000-0000-000000000006", dec!(20))).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, dec!(20));
+        assert_eq!(order_book.asks[&dec!(100.0)][0].remaining_quantity, dec!(5));
+    }
+
+    #[test]
+    fn test_spread_history_is_pruned() {
+        print_separator("Spread History Pruning");
+
+        let start = Utc::now();
+        let clock = Arc::new(MockClock::new(start));
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.set_clock(clock.clone());
+        order_book.config_mut().spread_history = Some(Duration::seconds(30));
+
+        let mut add_order = |side: OrderSide, price: Decimal| {
+            let order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                side,
+                OrderType::LIMIT,
+                Some(price),
+                dec!(1.0),
+            );
+            order_book.add_order(order).unwrap();
+        };
+
+        // Spread 2 from the start, 1 from 60s and 0.5 from 120s
+        add_order(OrderSide::BUY, dec!(99.0));
+        add_order(OrderSide::SELL, dec!(101.0));
+        clock.advance(Duration::seconds(60));
+        add_order(OrderSide::SELL, dec!(100.0));
+        clock.advance(Duration::seconds(60));
+        add_order(OrderSide::SELL, dec!(99.5));
+        clock.advance(Duration::seconds(20));
+
+        // Only the spread in force when the window opened and the one after it remain
+        assert_eq!(order_book.spread_samples.len(), 2);
+        // (1 * 60 + 0.5 * 20) / 80
+        assert_eq!(order_book.time_weighted_avg_spread(start), Some(dec!(0.875)));
+    }
+
+    #[test]
+    fn test_refused_amend_keeps_queue_place_and_balances() {
+        print_separator("Amend: Refused And Rebalanced");
+        let broker = Uuid::from_str("00000000-0000-0000-0000-000000000003").unwrap();
+
+        // Level 101 already holds one order and takes no more
+        let (mut order_book, ids) = build_amend_book();
+        order_book.config_mut().max_orders_per_level = Some(1);
+        let balances = Arc::new(LockingBalances {
+            cash_limit: Some(dec!(2600)),
+            ..LockingBalances::default()
+        });
+        order_book.set_balance_provider(balances.clone());
+        for id in &ids {
+            balances.lock_for(order_book.get_order(*id).unwrap());
+        }
+        assert_eq!(balances.locked_cash(broker), dec!(2500));
+        order_book.drain_events();
+        let before = order_book.broker_activity(broker);
+
+        assert_eq!(
+            order_book.amend_order(ids[0], Some(dec!(101.0)), None).unwrap_err(),
+            OrderError::LevelFull {
+                price: dec!(101.0),
+                max_orders: 1,
+            }
+        );
+        // 5 more at 100 would need another 500 locked, past the limit
+        order_book.config_mut().max_orders_per_level = None;
+        assert_eq!(order_book.amend_order(ids[0], None, Some(dec!(10.0))).unwrap_err(), OrderError::BalanceUnavailable(ids[0]));
+
+        // Still first in line at 100, untouched
+        let positions = order_book.queue_positions(OrderSide::BUY);
+        assert_eq!((positions[&ids[0]], positions[&ids[1]], positions[&ids[2]]), (0, 1, 2));
+        assert_eq!(order_book.get_order(ids[0]).unwrap().price, Some(dec!(100.0)));
+        assert_eq!(balances.locked_cash(broker), dec!(2500));
+
+        // Moving down to 99 frees 5 of cash, and adds 1 to the size at a cost of 99
+        order_book.amend_order(ids[0], Some(dec!(99.0)), Some(dec!(6.0))).unwrap();
+        assert_eq!(balances.locked_cash(broker), dec!(2500) - dec!(500) + dec!(594));
+        assert_eq!(order_book.queue_positions(OrderSide::BUY)[&ids[0]], 1);
+
+        // The same order carried on: no new-order event, only the extra 1 counts as submitted
+        assert!(order_book.drain_events().is_empty());
+        let after = order_book.broker_activity(broker);
+        assert_eq!(after.submitted - before.submitted, dec!(1.0));
+        assert_eq!(after.cancelled_volume, before.cancelled_volume);
+        assert!(order_book.health_check().is_empty());
+    }
+
+    #[test]
+    fn test_market_remainder_outside_collar_is_rejected() {
+        print_separator("Market Remainder Outside Collar");
 
-        order_book.add_order(sell_order);
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig {
+            market_remainder: MarketRemainderPolicy::RestAtLastTrade,
+            price_collar_bps: Some(dec!(500)),
+            ..BookConfig::default()
+        };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+
+        // A print at 100 sets the reference
+        for (id, side) in [("00000000-0000-0000-0000-000000000002", OrderSide::SELL), ("00000000-0000-0000-0000-000000000004", OrderSide::BUY)] {
+            let order = create_test_order(id, "00000000-0000-0000-0000-000000000003", side, OrderType::LIMIT, Some(dec!(100.0)), dec!(1.0));
+            order_book.add_order(order).unwrap();
+        }
+        for (id, price) in [("00000000-0000-0000-0000-000000000006", dec!(101.0)), ("00000000-0000-0000-0000-000000000008", dec!(110.0))] {
+            let sell_order = create_test_order(id, "00000000-0000-0000-0000-000000000003", OrderSide::SELL, OrderType::LIMIT, Some(price), dec!(2.0));
+            order_book.add_order(sell_order).unwrap();
+        }
 
         let buy_order = create_test_order(
-            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-00000000000a",
             "00000000-0000-0000-0000-000000000005",
             OrderSide::BUY,
             OrderType::MARKET,
             None,
-            dec!(10.0),
+            dec!(7.0),
         );
+        let trades = order_book.add_order(buy_order).unwrap();
 
-        println!("\n➡️ Adding Market Buy Order:");
-        visualize_order("BUY", &buy_order);
+        // The sweep to 110 stands, but a bid at 110 is 10% through the reference
+        assert_eq!(trades.iter().map(|t| t.price).collect::<Vec<_>>(), vec![dec!(101.0), dec!(110.0)]);
+        let buy = order_book.get_order(Uuid::from_str("00000000-0000-0000-0000-00000000000a").unwrap()).unwrap();
+        assert_eq!((buy.status.clone(), buy.remaining_quantity), (OrderStatus::REJECTED, dec!(3.0)));
+        assert_eq!(buy.order_type, OrderType::MARKET);
+        assert_eq!(order_book.best_bid(), None);
+    }
 
-        let trades = order_book.add_order(buy_order);
+    #[test]
+    fn test_health_check_allows_crossed_book_before_the_uncross() {
+        print_separator("Health Check During Auction Phases");
 
-        println!("\n💫 Result:");
-        for trade in &trades {
-            visualize_trade(trade);
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.begin_pre_open();
+
+        for (id, side, price) in [("00000000-0000-0000-0000-000000000002", OrderSide::BUY, dec!(101.0)), ("00000000-0000-0000-0000-000000000004", OrderSide::SELL, dec!(99.0))] {
+            let order = create_test_order(id, "00000000-0000-0000-0000-000000000003", side, OrderType::LIMIT, Some(price), dec!(5.0));
+            order_book.add_order(order).unwrap();
         }
-        println!("\n📚 Order Book State: Empty (all orders matched)");
+        assert_eq!(order_book.bbo(), (Some(dec!(101.0)), Some(dec!(99.0))));
+        assert!(order_book.health_check().is_empty());
+
+        order_book.begin_batch_auctions();
+        assert!(order_book.health_check().is_empty());
+
+        // The same book in continuous trading is broken
+        order_book.phase = TradingPhase::Continuous;
+        assert_eq!(
+            order_book.health_check(),
+            vec![BookInvariantViolation::Crossed {
+                bid: dec!(101.0),
+                ask: dec!(99.0),
+            }]
+        );
+
+        order_book.begin_pre_open();
+        let (price, trades) = order_book.run_opening_auction().unwrap();
+        assert!(price.is_some());
+        assert_eq!(trades.len(), 1);
+        assert!(order_book.health_check().is_empty());
     }
 
     #[test]
-    fn test_multiple_price_levels() {
-        print_separator("Multiple Price Levels");
+    fn test_lit_order_amended_below_a_lot_can_still_be_cancelled() {
+        print_separator("Amend Below A Lot, Then Cancel");
 
         let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
-        let mut order_book = OrderBook::new(instrument_id);
+        let config = BookConfig {
+            odd_lot_policy: OddLotPolicy::SeparateBook,
+            ..BookConfig::default()
+        };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+        order_book.set_lot_size(dec!(100));
 
-        let sell_order_1 = create_test_order(
+        let buy_order = create_test_order(
             "00000000-0000-0000-0000-000000000002",
             "00000000-0000-0000-0000-000000000003",
-            OrderSide::SELL,
+            OrderSide::BUY,
             OrderType::LIMIT,
             Some(dec!(100.0)),
-            dec!(5.0),
+            dec!(200),
         );
+        order_book.add_order(buy_order.clone()).unwrap();
 
-        println!("➡️ Adding First Sell Order (Price: 100):");
-        visualize_order("SELL", &sell_order_1);
+        // The reduction keeps its place in the lit book even though 50 is an odd lot now
+        order_book.amend_order(buy_order.id, None, Some(dec!(50))).unwrap();
+        assert_eq!(order_book.bids[&dec!(100.0)][0].remaining_quantity, dec!(50));
+        assert_eq!(order_book.quantity_ahead(buy_order.id), Some(Decimal::ZERO));
+        assert!(order_book.health_check().is_empty());
 
-        let sell_order_2 = create_test_order(
-            "00000000-0000-0000-0000-000000000006",
-            "00000000-0000-0000-0000-000000000007",
+        let cancelled = order_book.cancel_order(buy_order.id).unwrap();
+        assert_eq!(cancelled.status, OrderStatus::CANCELLED);
+        assert!(order_book.bids.is_empty());
+        assert!(order_book.health_check().is_empty());
+    }
+
+    #[test]
+    fn test_ioc_market_remainder_is_cancelled_not_rested() {
+        print_separator("IOC Market Remainder Under RestAtLastTrade");
+
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let config = BookConfig {
+            market_remainder: MarketRemainderPolicy::RestAtLastTrade,
+            ..BookConfig::default()
+        };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000002",
+            "00000000-0000-0000-0000-000000000003",
             OrderSide::SELL,
             OrderType::LIMIT,
             Some(dec!(101.0)),
-            dec!(5.0),
+            dec!(2.0),
         );
+        order_book.add_order(sell_order).unwrap();
 
-        println!("\n➡️ Adding Second Sell Order (Price: 101):");
-        visualize_order("SELL", &sell_order_2);
-
-        order_book.add_order(sell_order_1);
-        order_book.add_order(sell_order_2);
-
-        println!("\n📚 Order Book State: Two sell orders at different prices");
-
-        let buy_order = create_test_order(
+        let mut buy_order = create_test_order(
             "00000000-0000-0000-0000-000000000004",
             "00000000-0000-0000-0000-000000000005",
             OrderSide::BUY,
-            OrderType::LIMIT,
-            Some(dec!(101.0)),
-            dec!(10.0),
+            OrderType::MARKET,
+            None,
+            dec!(5.0),
         );
+        buy_order.time_in_force = TimeInForce::IOC;
+        let trades = order_book.add_order(buy_order.clone()).unwrap();
+        assert_eq!(trades.len(), 1);
 
-        println!("\n➡️ Adding Buy Order (Quantity: 10, Price: 101):");
-        visualize_order("BUY", &buy_order);
-
-        let trades = order_book.add_order(buy_order);
+        let buy = order_book.get_order(buy_order.id).unwrap();
+        assert_eq!(buy.status, OrderStatus::CANCELLED);
+        assert_eq!(buy.cancel_reason, Some(CancelReason::ImmediateOrCancel));
+        assert_eq!(buy.remaining_quantity, dec!(3.0));
+        assert_eq!(order_book.best_bid(), None);
 
-        println!("\n💫 Results:");
-        for (i, trade) in trades.iter().enumerate() {
-            println!("\n🤝 Trade {} Executed:", i + 1);
-            visualize_trade(trade);
-        }
-        println!("\n📚 Order Book State: Empty (all orders matched)");
+        // A sweep is IOC as well, so it never leaves a bid behind either
+        let sell_order = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000003",
+            OrderSide::SELL,
+            OrderType::LIMIT,
+            Some(dec!(102.0)),
+            dec!(1.0),
+        );
+        order_book.add_order(sell_order).unwrap();
+        let broker_id = Uuid::from_str("00000000-0000-0000-0000-000000000005").unwrap();
+        let trades = order_book.sweep(OrderSide::BUY, dec!(4.0), None, broker_id).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(order_book.best_bid(), None);
     }
 
     #[test]
-    fn test_cancel_pending_order() {
-        print_separator("Cancel Pending Order");
+    fn test_inverted_quote_is_refused_before_the_old_one_is_pulled() {
+        print_separator("Inverted Quote");
 
         let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
         let mut order_book = OrderBook::new(instrument_id);
+        let maker = Uuid::from_str("00000000-0000-0000-0000-000000000005").unwrap();
 
-        // Create a sell limit order
-        let sell_order = Order {
-            id: Uuid::new_v4(),
-            broker_id: Uuid::new_v4(),
-            instrument_id,
-            order_type: OrderType::LIMIT,
-            side: OrderSide::SELL,
-            status: OrderStatus::PENDING,
-            price: Some(dec!(100.0)),
-            original_quantity: dec!(10.0),
-            remaining_quantity: dec!(10.0),
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
-        };
-
-        let order_id = sell_order.id;
-        visualize_order("SELL", &sell_order);
-
-        order_book.add_order(sell_order);
-        visualize_order_book_state(&order_book);
-
-        // Cancel the order
-        let cancelled_order = order_book.cancel_order(order_id).unwrap();
-        visualize_order("CANCELLED", &cancelled_order);
-
-        visualize_order_book_state(&order_book);
+        assert!(order_book.quote(maker, dec!(99.0), dec!(1.0), dec!(101.0), dec!(1.0)).unwrap().is_empty());
+        for (bid, ask) in [(dec!(101.0), dec!(100.0)), (dec!(100.0), dec!(100.0))] {
+            assert_eq!(
+                order_book.quote(maker, bid, dec!(1.0), ask, dec!(1.0)).unwrap_err(),
+                OrderError::QuoteTooNarrow { bid, ask }
+            );
+        }
 
-        assert_eq!(cancelled_order.status, OrderStatus::CANCELLED);
-        assert_eq!(cancelled_order.remaining_quantity, dec!(10.0));
-        assert!(order_book.asks.is_empty());
+        // Nothing traded and the previous quote still stands on both sides
+        assert!(order_book.tape.is_empty());
+        assert_eq!(order_book.best_resting_for_broker(maker, OrderSide::BUY), Some((dec!(99.0), dec!(1.0))));
+        assert_eq!(order_book.best_resting_for_broker(maker, OrderSide::SELL), Some((dec!(101.0), dec!(1.0))));
+        assert_eq!(order_book.orders_by_tag(MARKET_MAKER_TAG).len(), 2);
     }
 
     #[test]
-    fn test_cancel_partially_filled_order() {
-        print_separator("Cancel Partially Filled Order");
+    fn test_quote_legs_must_keep_the_minimum_distance_apart() {
+        print_separator("Quote Leg Distance");
 
         let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
-        let mut order_book = OrderBook::new(instrument_id);
-
-        // Create a sell limit order
-        let sell_order = Order {
-            id: Uuid::new_v4(),
-            broker_id: Uuid::new_v4(),
-            instrument_id,
-            order_type: OrderType::LIMIT,
-            side: OrderSide::SELL,
-            status: OrderStatus::PENDING,
-            price: Some(dec!(100.0)),
-            original_quantity: dec!(10.0),
-            remaining_quantity: dec!(10.0),
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
+        let config = BookConfig {
+            min_quote_distance_ticks: Some(2),
+            ..BookConfig::default()
         };
+        let mut order_book = OrderBook::with_config(instrument_id, config);
+        order_book.tick_size = Some(dec!(0.5));
+        let maker = Uuid::from_str("00000000-0000-0000-0000-000000000005").unwrap();
 
-        let sell_order_id = sell_order.id;
-        visualize_order("SELL", &sell_order);
+        assert!(order_book.quote(maker, dec!(99.0), dec!(1.0), dec!(101.0), dec!(1.0)).unwrap().is_empty());
 
-        order_book.add_order(sell_order);
+        // One tick apart is too close, even with nothing else in the book
+        assert_eq!(
+            order_book.quote(maker, dec!(100.0), dec!(1.0), dec!(100.5), dec!(1.0)).unwrap_err(),
+            OrderError::QuoteTooNarrow {
+                bid: dec!(100.0),
+                ask: dec!(100.5),
+            }
+        );
+        assert_eq!(order_book.bbo(), (Some(dec!(99.0)), Some(dec!(101.0))));
 
-        // Create a partial matching buy order
-        let buy_order = Order {
-            id: Uuid::new_v4(),
-            broker_id: Uuid::new_v4(),
-            instrument_id,
-            order_type: OrderType::LIMIT,
-            side: OrderSide::BUY,
-            status: OrderStatus::PENDING,
-            price: Some(dec!(100.0)),
-            original_quantity: dec!(6.0),
-            remaining_quantity: dec!(6.0),
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
-        };
+        // Two ticks apart is enough
+        assert!(order_book.quote(maker, dec!(100.0), dec!(1.0), dec!(101.0), dec!(1.0)).unwrap().is_empty());
+        assert_eq!(order_book.bbo(), (Some(dec!(100.0)), Some(dec!(101.0))));
+        assert_eq!(order_book.orders_by_tag(MARKET_MAKER_TAG).len(), 2);
+    }
 
-        visualize_order("BUY", &buy_order);
+    #[test]
+    fn test_opening_auction_skips_frozen_levels() {
+        print_separator("Opening Auction With A Frozen Level");
 
-        // This should partially fill the sell order
-        order_book.add_order(buy_order);
-        visualize_order_book_state(&order_book);
+        let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let mut order_book = OrderBook::new(instrument_id);
+        order_book.begin_pre_open();
 
-        // Cancel the partially filled sell order
-        let cancelled_order = order_book.cancel_order(sell_order_id).unwrap();
-        visualize_order("CANCELLED", &cancelled_order);
+        let mut sell_ids = Vec::new();
+        for (price, quantity) in [(dec!(100.0), dec!(2.0)), (dec!(101.0), dec!(3.0))] {
+            let sell_order = create_test_order(
+                &Uuid::new_v4().to_string(),
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(price),
+                quantity,
+            );
+            sell_ids.push(sell_order.id);
+            order_book.add_order(sell_order).unwrap();
+        }
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000004",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(102.0)),
+            dec!(5.0),
+        );
+        order_book.add_order(buy_order.clone()).unwrap();
 
-        visualize_order_book_state(&order_book);
+        // Unfrozen, all 5 would clear at 101 with the ask at 100 taking part
+        order_book.freeze_price(dec!(100.0));
+        let (price, trades) = order_book.run_opening_auction().unwrap();
+        for trade in &trades {
+            visualize_trade(trade);
+        }
 
-        assert_eq!(cancelled_order.status, OrderStatus::CANCELLED);
-        assert_eq!(cancelled_order.remaining_quantity, dec!(4.0));
-        assert!(order_book.asks.is_empty());
+        assert_eq!(price, Some(dec!(101.0)));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].seller_order_id, sell_ids[1]);
+        assert_eq!(trades[0].quantity, dec!(3.0));
+        assert_eq!(order_book.get_order(sell_ids[0]).unwrap().remaining_quantity, dec!(2.0));
+        assert_eq!(order_book.asks[&dec!(100.0)].len(), 1);
+        assert_eq!(order_book.get_order(buy_order.id).unwrap().remaining_quantity, dec!(2.0));
     }
 
     #[test]
-    fn test_cancel_filled_order() {
-        print_separator("Cancel Filled Order");
+    fn test_level_that_cannot_trade_in_full_is_left_untouched() {
+        print_separator("All-or-Nothing Level Fill");
 
         let instrument_id = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
         let mut order_book = OrderBook::new(instrument_id);
 
-        // Create a sell limit order
-        let sell_order = Order {
-            id: Uuid::new_v4(),
-            broker_id: Uuid::new_v4(),
-            instrument_id,
-            order_type: OrderType::LIMIT,
-            side: OrderSide::SELL,
-            status: OrderStatus::FILLED,
-            price: Some(dec!(100.0)),
-            original_quantity: dec!(10.0),
-            remaining_quantity: dec!(0.0),
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
-        };
-
-        let order_id = sell_order.id;
-        visualize_order("SELL", &sell_order);
-
-        order_book.add_order(sell_order);
-        visualize_order_book_state(&order_book);
-
-        // Attempt to cancel the filled order
-        let cancelled_order = order_book.cancel_order(order_id);
-
-        if cancelled_order.is_none() {
-            println!("\n➡️ Attempt to Cancel Filled Order:");
-            println!("   └─ No order was cancelled (expected behavior).");
+        let mut sell_ids = Vec::new();
+        for id in ["00000000-0000-0000-0000-000000000002", "00000000-0000-0000-0000-000000000004"] {
+            let sell_order = create_test_order(
+                id,
+                "00000000-0000-0000-0000-000000000003",
+                OrderSide::SELL,
+                OrderType::LIMIT,
+                Some(dec!(100.0)),
+                dec!(2.0),
+            );
+            sell_ids.push(sell_order.id);
+            order_book.add_order(sell_order).unwrap();
         }
 
-        visualize_order_book_state(&order_book);
+        // The second maker's limit no longer agrees with its level, so it cannot trade at 100
+        order_book.asks.get_mut(&dec!(100.0)).unwrap()[1].price = Some(dec!(101.0));
+        let level_before = order_book.asks[&dec!(100.0)].clone();
+
+        let buy_order = create_test_order(
+            "00000000-0000-0000-0000-000000000006",
+            "00000000-0000-0000-0000-000000000005",
+            OrderSide::BUY,
+            OrderType::LIMIT,
+            Some(dec!(100.0)),
+            dec!(4.0),
+        );
+        assert!(matches!(order_book.add_order(buy_order).unwrap_err(), OrderError::InternalInconsistency(_)));
 
-        assert!(cancelled_order.is_none());
+        // The first maker was not filled ahead of the failure
+        assert_eq!(order_book.asks[&dec!(100.0)], level_before);
+        let first = order_book.get_order(sell_ids[0]).unwrap();
+        assert_eq!((first.status.clone(), first.remaining_quantity), (OrderStatus::PENDING, dec!(2.0)));
+        assert!(order_book.trades().is_empty());
     }
-}
\ No newline at end of file
+}
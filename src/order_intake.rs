@@ -0,0 +1,113 @@
+use super::models::*;
+use super::order_engine::OrderError;
+use super::shared_order_book::SharedOrderBook;
+use tokio::sync::{mpsc, oneshot};
+
+type Reply = oneshot::Sender<Result<Vec<Trade>, OrderError>>;
+
+/// Async front door to a `SharedOrderBook` for networked callers.
+///
+/// Submissions go through a channel to a single consumer task, which adds
+/// them to the book one at a time in the order they were sent, so no caller
+/// ever holds `&mut OrderBook`. Each add runs on Tokio's blocking pool, since
+/// it waits on the book's std lock and matches synchronously; the runtime's
+/// workers stay free meanwhile. Queries can still go straight to the shared
+/// book; they see each order's effects once its `submit` has returned.
+#[derive(Debug, Clone)]
+pub struct OrderIntake {
+    sender: mpsc::UnboundedSender<(Order, Reply)>,
+}
+
+impl OrderIntake {
+    /// Starts the consumer task on the current Tokio runtime. It runs until
+    /// every `OrderIntake` handle has been dropped.
+    pub fn spawn(book: SharedOrderBook) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(Order, Reply)>();
+
+        tokio::spawn(async move {
+            while let Some((order, reply)) = receiver.recv().await {
+                let book = book.clone();
+                // Awaited before the next order is taken, which keeps the adds in sequence.
+                // If the add panicked the book is unusable, so the intake shuts down
+                // and every waiting submitter sees `IntakeClosed`.
+                let Ok(result) = tokio::task::spawn_blocking(move || book.add_order(order)).await else {
+                    break;
+                };
+                // The submitter may have given up waiting; the order still counts
+                let _ = reply.send(result);
+            }
+        });
+
+        Self { sender }
+    }
+
+    pub async fn submit(&self, order: Order) -> Result<Vec<Trade>, OrderError> {
+        let (reply, response) = oneshot::channel();
+        self.sender.send((order, reply)).map_err(|_| OrderError::IntakeClosed)?;
+        response.await.map_err(|_| OrderError::IntakeClosed)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::order_engine::OrderBook;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    fn create_test_order(side: OrderSide, price: Decimal, quantity: Decimal) -> Order {
+        Order::for_test(side, Some(price), quantity)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_submitters_are_each_processed_once() {
+        let book = SharedOrderBook::new(OrderBook::new(Uuid::nil()));
+        let intake = OrderIntake::spawn(book.clone());
+
+        // Eight submitters, half buying and half selling one lot at a time at
+        // the same price, so everything eventually crosses
+        let submitters: Vec<_> = (0..8)
+            .map(|i| {
+                let intake = intake.clone();
+                let side = if i % 2 == 0 { OrderSide::BUY } else { OrderSide::SELL };
+                tokio::spawn(async move {
+                    let mut ids = Vec::new();
+                    for _ in 0..25 {
+                        let order = create_test_order(side.clone(), dec!(100), dec!(1));
+                        ids.push(order.id);
+                        intake.submit(order).await.unwrap();
+                    }
+                    ids
+                })
+            })
+            .collect();
+
+        let mut ids = Vec::new();
+        for submitter in submitters {
+            ids.extend(submitter.await.unwrap());
+        }
+
+        let book = book.read();
+        assert_eq!(ids.len(), 200);
+        assert!(ids.iter().all(|id| book.get_order(*id).is_some()));
+
+        // 100 units each way, all matched exactly once
+        assert_eq!(book.trades().len(), 100);
+        assert_eq!(book.trades().iter().map(|t| t.quantity).sum::<Decimal>(), dec!(100));
+        assert!(ids.iter().all(|id| book.get_order(*id).unwrap().status == OrderStatus::FILLED));
+        assert_eq!(book.bbo(), (None, None));
+        assert!(book.health_check().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_submit_reports_book_errors() {
+        let book = SharedOrderBook::new(OrderBook::new(Uuid::nil()));
+        let intake = OrderIntake::spawn(book.clone());
+
+        let order = create_test_order(OrderSide::BUY, dec!(100), dec!(1));
+        intake.submit(order.clone()).await.unwrap();
+
+        assert_eq!(intake.submit(order.clone()).await.unwrap_err(), OrderError::DuplicateOrderId(order.id));
+    }
+}
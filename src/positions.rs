@@ -0,0 +1,63 @@
+use super::models::*;
+use super::settlement::position_deltas;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Net share position per `(broker_id, instrument_id)` after replaying
+/// `trades` from flat: buyers go up by the traded quantity and sellers down.
+/// A broker who has traded but is flat again keeps a zero entry.
+pub fn reconstruct_positions(trades: &[Trade]) -> HashMap<(Uuid, Uuid), Decimal> {
+    let mut positions: HashMap<(Uuid, Uuid), Decimal> = HashMap::new();
+
+    for delta in trades.iter().flat_map(position_deltas) {
+        *positions.entry((delta.broker_id, delta.instrument_id)).or_default() += delta.quantity_delta;
+    }
+
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn create_test_trade(instrument_id: Uuid, buyer: Uuid, seller: Uuid, quantity: Decimal) -> Trade {
+        Trade {
+            instrument_id,
+            buyer_broker_id: buyer,
+            seller_broker_id: seller,
+            ..Trade::for_test(dec!(100), quantity)
+        }
+    }
+
+    #[test]
+    fn test_reconstruct_long_and_short_positions() {
+        let (stock, bond) = (Uuid::new_v4(), Uuid::new_v4());
+        let (alice, bob, carol) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        let trades = vec![
+            create_test_trade(stock, alice, bob, dec!(10)),
+            create_test_trade(stock, bob, alice, dec!(3)),
+            create_test_trade(stock, alice, carol, dec!(5)),
+            create_test_trade(bond, carol, alice, dec!(2)),
+            create_test_trade(bond, bob, carol, dec!(2)),
+        ];
+
+        let positions = reconstruct_positions(&trades);
+
+        // Alice bought 15 and sold 3 of the stock, Bob the reverse
+        assert_eq!(positions[&(alice, stock)], dec!(12));
+        assert_eq!(positions[&(bob, stock)], dec!(-7));
+        assert_eq!(positions[&(carol, stock)], dec!(-5));
+        assert_eq!(positions[&(alice, bond)], dec!(-2));
+        assert_eq!(positions[&(bob, bond)], dec!(2));
+        // Carol bought and sold the same amount of the bond
+        assert_eq!(positions[&(carol, bond)], dec!(0));
+        assert_eq!(positions.len(), 6);
+
+        // Every share bought was sold by someone
+        assert!(positions.values().copied().sum::<Decimal>().is_zero());
+        assert!(reconstruct_positions(&[]).is_empty());
+    }
+}
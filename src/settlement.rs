@@ -0,0 +1,143 @@
+use super::models::*;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+/// Trades settle one day after execution (T+1)
+pub const SETTLEMENT_PERIOD: Duration = Duration::days(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SettlementSummary {
+    pub settled: usize,
+    pub failed: usize,
+    pub pending: usize,
+}
+
+/// Settles every `PENDING_SETTLEMENT` trade that is due at `now`, marking the
+/// ones matched by `fail_predicate` as `FAILED` to model counterparty failures.
+/// Trades not yet due stay pending; trades already settled or failed are left
+/// untouched and not counted.
+pub fn run_settlement(
+    trades: &mut [Trade],
+    now: DateTime<Utc>,
+    fail_predicate: impl Fn(&Trade) -> bool,
+) -> SettlementSummary {
+    let mut summary = SettlementSummary::default();
+
+    for trade in trades.iter_mut() {
+        if trade.status != TradeStatus::PENDING_SETTLEMENT {
+            continue;
+        }
+
+        if trade.execution_time + SETTLEMENT_PERIOD > now {
+            summary.pending += 1;
+        } else if fail_predicate(trade) {
+            trade.status = TradeStatus::FAILED;
+            summary.failed += 1;
+        } else {
+            trade.status = TradeStatus::SETTLED;
+            trade.settlement_time = Some(now);
+            summary.settled += 1;
+        }
+    }
+
+    summary
+}
+
+/// The change a trade makes to one broker's cash and holdings in an instrument
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionDelta {
+    pub broker_id: Uuid,
+    pub instrument_id: Uuid,
+    pub cash_delta: Decimal,
+    pub quantity_delta: Decimal,
+}
+
+/// Buyer and seller deltas for a trade, to be applied to `CashPosition` and
+/// `SecurityPosition`: the buyer pays cash and receives shares, the seller the reverse.
+pub fn position_deltas(trade: &Trade) -> Vec<PositionDelta> {
+    let notional = trade.price * trade.quantity;
+
+    vec![
+        PositionDelta {
+            broker_id: trade.buyer_broker_id,
+            instrument_id: trade.instrument_id,
+            cash_delta: -notional,
+            quantity_delta: trade.quantity,
+        },
+        PositionDelta {
+            broker_id: trade.seller_broker_id,
+            instrument_id: trade.instrument_id,
+            cash_delta: notional,
+            quantity_delta: -trade.quantity,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn create_test_trade(quantity: Decimal, execution_time: DateTime<Utc>) -> Trade {
+        Trade {
+            execution_time,
+            ..Trade::for_test(dec!(100.0), quantity)
+        }
+    }
+
+    #[test]
+    fn test_settlement_run_with_failures() {
+        let now = Utc::now();
+        let executed = now - Duration::days(2);
+        let mut trades: Vec<Trade> = (1..=5)
+            .map(|quantity| create_test_trade(Decimal::from(quantity), executed))
+            .collect();
+
+        let summary = run_settlement(&mut trades, now, |trade| trade.quantity >= dec!(4));
+
+        assert_eq!(summary, SettlementSummary { settled: 3, failed: 2, pending: 0 });
+        assert_eq!(trades.iter().filter(|t| t.status == TradeStatus::FAILED).count(), 2);
+        for trade in trades.iter().filter(|t| t.status == TradeStatus::SETTLED) {
+            assert_eq!(trade.settlement_time, Some(now));
+        }
+    }
+
+    #[test]
+    fn test_settlement_run_leaves_undue_trades_pending() {
+        let now = Utc::now();
+        let mut trades = vec![
+            create_test_trade(dec!(1), now - Duration::days(1)),
+            create_test_trade(dec!(2), now - Duration::hours(2)),
+        ];
+
+        let summary = run_settlement(&mut trades, now, |_| false);
+        assert_eq!(summary, SettlementSummary { settled: 1, failed: 0, pending: 1 });
+
+        // A second run only reports what is still outstanding
+        let summary = run_settlement(&mut trades, now, |_| false);
+        assert_eq!(summary, SettlementSummary { settled: 0, failed: 0, pending: 1 });
+        assert_eq!(trades[1].status, TradeStatus::PENDING_SETTLEMENT);
+        assert_eq!(trades[1].settlement_time, None);
+    }
+
+    #[test]
+    fn test_position_deltas_net_to_zero() {
+        let trade = create_test_trade(dec!(3), Utc::now());
+
+        let deltas = position_deltas(&trade);
+
+        assert_eq!(deltas.len(), 2);
+        let (buyer, seller) = (deltas[0], deltas[1]);
+        assert_eq!(buyer.broker_id, trade.buyer_broker_id);
+        assert_eq!(buyer.cash_delta, dec!(-300.0));
+        assert_eq!(buyer.quantity_delta, dec!(3));
+        assert_eq!(seller.broker_id, trade.seller_broker_id);
+        assert_eq!(seller.cash_delta, dec!(300.0));
+        assert_eq!(seller.quantity_delta, dec!(-3));
+        assert!(deltas.iter().all(|d| d.instrument_id == trade.instrument_id));
+        assert!((buyer.cash_delta + seller.cash_delta).is_zero());
+        assert!((buyer.quantity_delta + seller.quantity_delta).is_zero());
+    }
+}
+
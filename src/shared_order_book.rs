@@ -0,0 +1,113 @@
+use super::models::*;
+use super::order_engine::{BookDepth, OrderBook, OrderError};
+use rust_decimal::Decimal;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use uuid::Uuid;
+
+/// An `OrderBook` that can be shared between threads.
+///
+/// Locking discipline: queries (`bbo`, `depth`, `get_order`) take the read
+/// lock, so any number of readers run concurrently. Anything that mutates the
+/// book (`add_order`, `cancel_order`) takes the single write lock, which waits
+/// for in-flight readers and blocks new ones until the mutation completes, so
+/// readers never observe a half-applied match. Each method holds its lock only
+/// for the duration of the call; use `read`/`write` to hold a guard across
+/// several operations, and never call back into the same `SharedOrderBook`
+/// while holding a guard.
+#[derive(Debug, Clone)]
+pub struct SharedOrderBook {
+    inner: Arc<RwLock<OrderBook>>,
+}
+
+impl SharedOrderBook {
+    pub fn new(book: OrderBook) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(book)),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, OrderBook> {
+        self.inner.read().expect("order book lock poisoned")
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, OrderBook> {
+        self.inner.write().expect("order book lock poisoned")
+    }
+
+    pub fn add_order(&self, order: Order) -> Result<Vec<Trade>, OrderError> {
+        self.write().add_order(order)
+    }
+
+    pub fn cancel_order(&self, order_id: Uuid) -> Result<Order, OrderError> {
+        self.write().cancel_order(order_id)
+    }
+
+    pub fn bbo(&self) -> (Option<Decimal>, Option<Decimal>) {
+        self.read().bbo()
+    }
+
+    pub fn depth(&self, levels: usize) -> BookDepth {
+        self.read().depth(levels)
+    }
+
+    pub fn get_order(&self, order_id: Uuid) -> Option<Order> {
+        self.read().get_order(order_id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::thread;
+
+    fn create_test_order(side: OrderSide, price: Decimal, quantity: Decimal) -> Order {
+        Order::for_test(side, Some(price), quantity)
+    }
+
+    #[test]
+    fn test_concurrent_readers_with_single_writer() {
+        let shared = SharedOrderBook::new(OrderBook::new(Uuid::nil()));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let book = shared.clone();
+                thread::spawn(move || {
+                    for _ in 0..500 {
+                        // Bids never rest at or above the asks, so a consistent
+                        // view can never be crossed
+                        if let (Some(bid), Some(ask)) = book.bbo() {
+                            assert!(bid < ask);
+                        }
+                        let depth = book.depth(5);
+                        assert!(depth.bids.len() <= 5 && depth.asks.len() <= 5);
+                    }
+                })
+            })
+            .collect();
+
+        let writer = {
+            let book = shared.clone();
+            thread::spawn(move || {
+                for i in 0..200 {
+                    let offset = Decimal::from(i % 10);
+                    let bid = create_test_order(OrderSide::BUY, dec!(90) - offset, dec!(1));
+                    let ask = create_test_order(OrderSide::SELL, dec!(110) + offset, dec!(1));
+                    let ask_id = ask.id;
+
+                    book.add_order(bid).unwrap();
+                    book.add_order(ask).unwrap();
+                    assert!(book.get_order(ask_id).is_some());
+                }
+            })
+        };
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(shared.bbo(), (Some(dec!(90)), Some(dec!(110))));
+        assert_eq!(shared.read().depth(10).bids.len(), 10);
+    }
+}